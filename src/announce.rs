@@ -0,0 +1,405 @@
+﻿//! Cliente de announce e scrape para trackers HTTP (BEP 3, BEP 23) e UDP (BEP 15).
+
+use crate::bencode::{self, Value};
+use crate::http::percent_encode;
+use crate::metainfo::InfoHash;
+use crate::tracker::{
+    ScrapeStats, UDP_ACTION_ANNOUNCE, UDP_ACTION_CONNECT, UDP_ACTION_ERROR, UDP_ACTION_SCRAPE, UDP_PROTOCOL_ID,
+};
+use crate::wire::PeerId;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Tempo máximo de espera por uma resposta do tracker.
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Tempo de vida de um connection id do lado do cliente (BEP 15).
+const UDP_CONNECTION_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    None,
+    Started,
+    Completed,
+    Stopped,
+}
+
+impl Event {
+    fn udp_code(&self) -> u32 {
+        match self {
+            Event::None => 0,
+            Event::Completed => 1,
+            Event::Started => 2,
+            Event::Stopped => 3,
+        }
+    }
+
+    fn as_str(&self) -> Option<&'static str> {
+        match self {
+            Event::None => None,
+            Event::Started => Some("started"),
+            Event::Completed => Some("completed"),
+            Event::Stopped => Some("stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnounceRequest {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+    pub port: u16,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub event: Event,
+    pub numwant: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnnounceResponse {
+    /// Segundos até o próximo announce.
+    pub interval: u32,
+    pub min_interval: Option<u32>,
+    pub complete: Option<u32>,
+    pub incomplete: Option<u32>,
+    pub peers: Vec<SocketAddr>,
+    pub warning: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AnnounceError {
+    Http(reqwest::Error),
+    Io(io::Error),
+    /// Nenhuma resposta do tracker UDP depois de todas as retransmissões.
+    Timeout,
+    Bencode(bencode::Error),
+    /// O tracker respondeu com `failure reason`.
+    Failure(String),
+    Invalid(&'static str),
+    UnsupportedScheme(String),
+}
+
+impl fmt::Display for AnnounceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnounceError::Http(e) => write!(f, "erro HTTP: {}", e),
+            AnnounceError::Io(e) => write!(f, "erro de rede: {}", e),
+            AnnounceError::Timeout => write!(f, "tracker não respondeu"),
+            AnnounceError::Bencode(e) => write!(f, "resposta inválida: {}", e),
+            AnnounceError::Failure(reason) => write!(f, "tracker recusou: {}", reason),
+            AnnounceError::Invalid(msg) => write!(f, "{}", msg),
+            AnnounceError::UnsupportedScheme(url) => write!(f, "tipo de tracker não suportado: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for AnnounceError {}
+
+impl From<reqwest::Error> for AnnounceError {
+    fn from(e: reqwest::Error) -> Self {
+        AnnounceError::Http(e)
+    }
+}
+
+impl From<io::Error> for AnnounceError {
+    fn from(e: io::Error) -> Self {
+        AnnounceError::Io(e)
+    }
+}
+
+impl From<bencode::Error> for AnnounceError {
+    fn from(e: bencode::Error) -> Self {
+        AnnounceError::Bencode(e)
+    }
+}
+
+/// Envia um announce ao tracker indicado pela URL.
+pub async fn announce(url: &str, request: &AnnounceRequest) -> Result<AnnounceResponse, AnnounceError> {
+    if url.starts_with("udp://") {
+        return UdpTrackerClient::default().announce(url, request).await;
+    }
+    if !is_http(url) {
+        return Err(AnnounceError::UnsupportedScheme(url.to_string()));
+    }
+
+    let mut query = format!(
+        "info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact=1",
+        percent_encode(request.info_hash.as_bytes()),
+        percent_encode(&request.peer_id),
+        request.port,
+        request.uploaded,
+        request.downloaded,
+        request.left,
+    );
+    if let Some(event) = request.event.as_str() {
+        query.push_str(&format!("&event={}", event));
+    }
+    if let Some(numwant) = request.numwant {
+        query.push_str(&format!("&numwant={}", numwant));
+    }
+
+    let body = http_get(url, &query).await?;
+    parse_announce_response(&bencode::decode(&body)?)
+}
+
+/// Consulta as estatísticas dos torrents no endpoint `/scrape` do tracker.
+pub async fn scrape(url: &str, info_hashes: &[InfoHash]) -> Result<Vec<(InfoHash, ScrapeStats)>, AnnounceError> {
+    if url.starts_with("udp://") {
+        return UdpTrackerClient::default().scrape(url, info_hashes).await;
+    }
+    if !is_http(url) {
+        return Err(AnnounceError::UnsupportedScheme(url.to_string()));
+    }
+    // Convenção: a URL de scrape troca o último "announce" do caminho por "scrape"
+    let scrape_url = match url.rfind("/announce") {
+        Some(pos) => format!("{}/scrape{}", &url[..pos], &url[pos + "/announce".len()..]),
+        None => return Err(AnnounceError::Invalid("tracker não suporta scrape")),
+    };
+    let query: Vec<String> = info_hashes
+        .iter()
+        .map(|hash| format!("info_hash={}", percent_encode(hash.as_bytes())))
+        .collect();
+
+    let body = http_get(&scrape_url, &query.join("&")).await?;
+    let response = bencode::decode(&body)?;
+    check_failure(&response)?;
+    let files = response
+        .get("files")
+        .and_then(Value::as_dict)
+        .ok_or(AnnounceError::Invalid("scrape sem 'files'"))?;
+
+    Ok(files
+        .iter()
+        .filter_map(|(hash, stats)| {
+            let field = |name| stats.get(name).and_then(Value::as_int).unwrap_or(0) as u32;
+            Some((
+                InfoHash::from_bytes(hash)?,
+                ScrapeStats {
+                    complete: field("complete"),
+                    incomplete: field("incomplete"),
+                    downloaded: field("downloaded"),
+                },
+            ))
+        })
+        .collect())
+}
+
+/// Cliente do protocolo UDP de tracker (BEP 15).
+///
+/// Cada tentativa `n` espera `base_timeout * 2^n` por uma resposta; o padrão é
+/// 15 s e 8 retransmissões, como no BEP. Testes em loopback podem usar valores menores.
+#[derive(Debug, Clone)]
+pub struct UdpTrackerClient {
+    pub base_timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for UdpTrackerClient {
+    fn default() -> Self {
+        Self { base_timeout: TRACKER_TIMEOUT, max_retries: 8 }
+    }
+}
+
+impl UdpTrackerClient {
+    pub async fn announce(&self, url: &str, request: &AnnounceRequest) -> Result<AnnounceResponse, AnnounceError> {
+        let mut packet = Vec::with_capacity(98);
+        packet.extend_from_slice(request.info_hash.as_bytes());
+        packet.extend_from_slice(&request.peer_id);
+        packet.extend_from_slice(&request.downloaded.to_be_bytes());
+        packet.extend_from_slice(&request.left.to_be_bytes());
+        packet.extend_from_slice(&request.uploaded.to_be_bytes());
+        packet.extend_from_slice(&request.event.udp_code().to_be_bytes());
+        packet.extend_from_slice(&0u32.to_be_bytes()); // ip: o tracker usa o endereço de origem
+        packet.extend_from_slice(&rand::random::<u32>().to_be_bytes()); // key
+        let numwant = request.numwant.map(|n| n as i32).unwrap_or(-1);
+        packet.extend_from_slice(&numwant.to_be_bytes());
+        packet.extend_from_slice(&request.port.to_be_bytes());
+
+        let (tracker, body) = self.request(url, UDP_ACTION_ANNOUNCE, &packet).await?;
+        if body.len() < 12 {
+            return Err(AnnounceError::Invalid("resposta de announce UDP curta demais"));
+        }
+        let u32_at = |offset: usize| u32::from_be_bytes(body[offset..offset + 4].try_into().unwrap());
+        let ip_len = if tracker.is_ipv4() { 4 } else { 16 };
+        Ok(AnnounceResponse {
+            interval: u32_at(0),
+            min_interval: None,
+            incomplete: Some(u32_at(4)),
+            complete: Some(u32_at(8)),
+            peers: parse_compact_peers(&body[12..], ip_len),
+            warning: None,
+        })
+    }
+
+    pub async fn scrape(&self, url: &str, info_hashes: &[InfoHash]) -> Result<Vec<(InfoHash, ScrapeStats)>, AnnounceError> {
+        let packet: Vec<u8> = info_hashes.iter().flat_map(|hash| *hash.as_bytes()).collect();
+        let (_, body) = self.request(url, UDP_ACTION_SCRAPE, &packet).await?;
+        Ok(info_hashes
+            .iter()
+            .zip(body.chunks_exact(12))
+            .map(|(hash, chunk)| {
+                let u32_at = |offset: usize| u32::from_be_bytes(chunk[offset..offset + 4].try_into().unwrap());
+                (*hash, ScrapeStats { complete: u32_at(0), downloaded: u32_at(4), incomplete: u32_at(8) })
+            })
+            .collect())
+    }
+
+    /// Obtém um connection id e envia o pedido, retransmitindo com backoff.
+    /// Retorna o endereço do tracker e o corpo da resposta após o cabeçalho.
+    async fn request(&self, url: &str, action: u32, payload: &[u8]) -> Result<(SocketAddr, Vec<u8>), AnnounceError> {
+        let tracker = resolve_udp(url).await?;
+        let bind = if tracker.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(tracker).await?;
+
+        let mut connection: Option<(u64, Instant)> = None;
+        for attempt in 0..=self.max_retries {
+            let timeout = self.base_timeout * 2u32.pow(attempt);
+
+            let connection_id = match connection {
+                Some((id, obtained)) if obtained.elapsed() < UDP_CONNECTION_TTL => id,
+                _ => {
+                    let Some(body) = exchange(&socket, UDP_PROTOCOL_ID, UDP_ACTION_CONNECT, &[], timeout).await? else {
+                        continue;
+                    };
+                    let id = body
+                        .get(..8)
+                        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+                        .ok_or(AnnounceError::Invalid("resposta de connect UDP curta demais"))?;
+                    connection = Some((id, Instant::now()));
+                    id
+                }
+            };
+
+            if let Some(body) = exchange(&socket, connection_id, action, payload, timeout).await? {
+                return Ok((tracker, body));
+            }
+        }
+        Err(AnnounceError::Timeout)
+    }
+}
+
+/// Envia um pacote e espera a resposta com o mesmo transaction id.
+/// Retorna `None` se o tempo esgotar, para que o chamador retransmita.
+async fn exchange(
+    socket: &UdpSocket,
+    connection_id: u64,
+    action: u32,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Option<Vec<u8>>, AnnounceError> {
+    let transaction_id: u32 = rand::random();
+    let mut packet = Vec::with_capacity(16 + payload.len());
+    packet.extend_from_slice(&connection_id.to_be_bytes());
+    packet.extend_from_slice(&action.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet.extend_from_slice(payload);
+    socket.send(&packet).await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let n = match tokio::time::timeout_at(deadline, socket.recv(&mut buffer)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+        if n < 8 || u32::from_be_bytes(buffer[4..8].try_into().unwrap()) != transaction_id {
+            // Resposta atrasada de uma tentativa anterior
+            continue;
+        }
+        let reply_action = u32::from_be_bytes(buffer[0..4].try_into().unwrap());
+        if reply_action == UDP_ACTION_ERROR {
+            return Err(AnnounceError::Failure(String::from_utf8_lossy(&buffer[8..n]).to_string()));
+        }
+        if reply_action != action {
+            return Err(AnnounceError::Invalid("ação inesperada na resposta UDP"));
+        }
+        return Ok(Some(buffer[8..n].to_vec()));
+    }
+}
+
+async fn resolve_udp(url: &str) -> Result<SocketAddr, AnnounceError> {
+    let rest = &url["udp://".len()..];
+    let host = rest.split('/').next().unwrap_or(rest);
+    tokio::net::lookup_host(host)
+        .await?
+        .next()
+        .ok_or(AnnounceError::Invalid("endereço do tracker UDP não encontrado"))
+}
+
+fn is_http(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+async fn http_get(url: &str, query: &str) -> Result<Vec<u8>, AnnounceError> {
+    // A query é montada à mão porque o info_hash é binário e precisa de percent-encoding exato
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let client = reqwest::Client::builder().timeout(TRACKER_TIMEOUT).build()?;
+    let response = client
+        .get(format!("{}{}{}", url, separator, query))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn check_failure(response: &Value) -> Result<(), AnnounceError> {
+    match response.get("failure reason") {
+        Some(reason) => Err(AnnounceError::Failure(
+            String::from_utf8_lossy(reason.as_bytes().unwrap_or_default()).to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn parse_announce_response(response: &Value) -> Result<AnnounceResponse, AnnounceError> {
+    check_failure(response)?;
+    let int = |name| response.get(name).and_then(Value::as_int).map(|v| v.max(0) as u32);
+
+    let mut peers = match response.get("peers") {
+        Some(Value::Bytes(compact)) => parse_compact_peers(compact, 4),
+        Some(Value::List(list)) => list
+            .iter()
+            .filter_map(|peer| {
+                let ip: IpAddr = peer.get("ip")?.as_str()?.parse().ok()?;
+                let port = u16::try_from(peer.get("port")?.as_int()?).ok()?;
+                Some(SocketAddr::new(ip, port))
+            })
+            .collect(),
+        Some(_) => return Err(AnnounceError::Invalid("campo 'peers' inválido")),
+        None => Vec::new(),
+    };
+    if let Some(compact6) = response.get("peers6").and_then(Value::as_bytes) {
+        peers.extend(parse_compact_peers(compact6, 16));
+    }
+
+    Ok(AnnounceResponse {
+        interval: int("interval").ok_or(AnnounceError::Invalid("resposta sem 'interval'"))?,
+        min_interval: int("min interval"),
+        complete: int("complete"),
+        incomplete: int("incomplete"),
+        peers,
+        warning: response.get("warning message").and_then(Value::as_str).map(str::to_string),
+    })
+}
+
+/// Lista compacta de peers: IP (4 ou 16 bytes) seguido da porta em big-endian.
+pub fn parse_compact_peers(bytes: &[u8], ip_len: usize) -> Vec<SocketAddr> {
+    bytes
+        .chunks_exact(ip_len + 2)
+        .map(|chunk| {
+            let ip = if ip_len == 4 {
+                IpAddr::V4(Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]))
+            } else {
+                let octets: [u8; 16] = chunk[..16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = u16::from_be_bytes([chunk[ip_len], chunk[ip_len + 1]]);
+            SocketAddr::new(ip, port)
+        })
+        .collect()
+}
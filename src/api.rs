@@ -0,0 +1,658 @@
+﻿//! API HTTP local, com respostas JSON, para controlar um peer em execução,
+//! e a interface web que a usa, servida em `/`.
+//!
+//! Toda requisição à API precisa do token, no cabeçalho `Authorization: Bearer
+//! <token>`, como senha do Basic auth ou no parâmetro `?token=`. Limites são em KiB/s; 0 é sem limite.
+//!
+//! ```text
+//! GET    /api/torrents                 lista os torrents com o progresso e as taxas em bytes/s
+//! POST   /api/torrents                 adiciona: {"source": "<magnet ou .torrent>", "save_dir": "..."}
+//!                                      ou o próprio .torrent com Content-Type application/x-bittorrent
+//! GET    /api/torrents/<hash>          um torrent
+//! DELETE /api/torrents/<hash>          remove; ?delete_files=true apaga também os arquivos
+//! POST   /api/torrents/<hash>/pause
+//! POST   /api/torrents/<hash>/resume
+//! GET    /api/torrents/<hash>/peers    conexões ativas do torrent
+//! GET    /api/torrents/<hash>/pieces   mapa de peças: bitfield em hex e o total de peças
+//! GET    /api/torrents/<hash>/limits   limites do torrent; PUT altera
+//! GET    /api/limits                   limites global e por peer e slots de unchoke; PUT altera
+//! POST   /api/chat                     {"port": <porta de chat do destino>, "message": "..."}
+//! GET    /api/chat/ws                  WebSocket: recebe as mensagens de chat que chegam
+//!                                      e envia as que o navegador manda, no formato do POST
+//! POST   /transmission/rpc             RPC compatível com o Transmission (ver [`crate::transmission`])
+//! ```
+
+use crate::chat::{self, ChatServer};
+use crate::codec::Framed;
+use crate::http::{HttpCodec, Request, Response};
+use crate::magnet::Magnet;
+use crate::metainfo::{InfoHash, Metainfo};
+use crate::ratelimit::{self, RateLimiter, RateLimits, RateTooLarge};
+use crate::session::{Session, SessionError, TorrentState, TorrentStatus};
+use crate::torrent::Torrent;
+use crate::transmission::{self, Transmission};
+use crate::websocket::{self, Message, WebSocketCodec};
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn, Instrument};
+
+/// Arquivo, no diretório de estado, com o token gerado quando nenhum é configurado.
+const TOKEN_FILE: &str = "api.token";
+
+/// Tempo máximo para o cliente enviar a requisição.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const JSON: &str = "application/json";
+
+/// Interface web, uma página só com o HTML, o CSS e o JavaScript.
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+const CHAT_SOCKET_PATH: &str = "/api/chat/ws";
+
+/// Lê o token gravado em `state_dir` ou gera um novo, legível só pelo usuário.
+pub async fn load_or_create_token(state_dir: &Path) -> io::Result<String> {
+    let path = state_dir.join(TOKEN_FILE);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let token = hex::encode(rand::random::<[u8; 16]>());
+    tokio::fs::create_dir_all(state_dir).await?;
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await?;
+    file.write_all(token.as_bytes()).await?;
+    Ok(token)
+}
+
+#[derive(Clone)]
+pub struct ApiServer {
+    inner: Arc<Inner>,
+    /// Servidor de chat cujas mensagens vão para o WebSocket; sem ele, o chat só envia.
+    chat: Option<ChatServer>,
+}
+
+struct Inner {
+    session: Session,
+    token: String,
+    /// Destino dos torrents adicionados sem `save_dir`.
+    download_dir: PathBuf,
+    transmission: Transmission,
+}
+
+/// Erro de uma rota, já com o status HTTP.
+struct ApiError(u16, String);
+
+impl From<RateTooLarge> for ApiError {
+    fn from(e: RateTooLarge) -> Self {
+        ApiError(400, e.to_string())
+    }
+}
+
+impl From<SessionError> for ApiError {
+    fn from(e: SessionError) -> Self {
+        let status = match e {
+            SessionError::AlreadyAdded(_) => 409,
+            SessionError::NotFound(_) => 404,
+            SessionError::Io(_) => 500,
+        };
+        ApiError(status, e.to_string())
+    }
+}
+
+type ApiResult = Result<Value, ApiError>;
+
+#[derive(Deserialize)]
+struct AddRequest {
+    source: String,
+    save_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    port: u16,
+    message: String,
+}
+
+/// Limites de um nível; campos ausentes não são alterados.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsUpdate {
+    upload: Option<u64>,
+    download: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct GlobalLimitsUpdate {
+    global: LimitsUpdate,
+    peer: LimitsUpdate,
+    unchoke_slots: Option<usize>,
+}
+
+impl ApiServer {
+    pub fn new(session: Session, token: String, download_dir: PathBuf) -> Self {
+        let transmission = Transmission::new(session.clone(), download_dir.clone());
+        Self { inner: Arc::new(Inner { session, token, download_dir, transmission }), chat: None }
+    }
+
+    pub fn with_chat(mut self, chat: ChatServer) -> Self {
+        self.chat = Some(chat);
+        self
+    }
+
+    pub async fn start(&self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        info!(%addr, "API rodando em /api, interface web em /");
+
+        loop {
+            let (socket, remote) = listener.accept().await?;
+            let server = self.clone();
+
+            let span = tracing::debug_span!("api", %remote);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = server.handle_connection(socket).await {
+                        warn!(error = %e, "Erro na requisição da API");
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    async fn handle_connection(&self, socket: TcpStream) -> io::Result<()> {
+        let mut framed = Framed::new(socket, HttpCodec);
+        let Some(request) = tokio::time::timeout(REQUEST_TIMEOUT, framed.next()).await? else {
+            return Ok(());
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return framed.send(error_response(400, &e.to_string())).await,
+        };
+        if request.path == CHAT_SOCKET_PATH && websocket::is_upgrade(&request) && self.authorized(&request) {
+            if let (Some(chat), Some(handshake)) = (&self.chat, websocket::handshake_response(&request)) {
+                let events = chat.subscribe();
+                framed.get_mut().write_all(handshake.as_bytes()).await?;
+                return self.chat_socket(framed.map_codec(|_| WebSocketCodec::default()), events).await;
+            }
+        }
+        let response = self.handle(&request).await;
+        // Só o caminho: a query pode trazer o token
+        debug!(method = %request.method, path = %request.path, status = response.status, "Requisição da API");
+        framed.send(response).await
+    }
+
+    /// Repassa ao navegador as mensagens de chat recebidas e envia as que ele manda.
+    async fn chat_socket(
+        &self,
+        mut socket: Framed<TcpStream, WebSocketCodec>,
+        mut events: broadcast::Receiver<(String, String)>,
+    ) -> io::Result<()> {
+        loop {
+            tokio::select! {
+                message = socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = match serde_json::from_str::<ChatRequest>(&text) {
+                            Ok(chat) => match self.send_chat(chat).await {
+                                Ok(value) => value,
+                                Err(ApiError(_, message)) => json!({ "error": message }),
+                            },
+                            Err(e) => json!({ "error": format!("JSON inválido: {}", e) }),
+                        };
+                        socket.send(Message::Text(reply.to_string())).await?;
+                    }
+                    Some(Ok(Message::Ping(data))) => socket.send(Message::Pong(data)).await?,
+                    Some(Ok(Message::Close)) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                },
+                event = events.recv() => match event {
+                    Ok((from, message)) => {
+                        let event = json!({ "from": from, "message": message });
+                        socket.send(Message::Text(event.to_string())).await?;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Atende uma requisição já decodificada.
+    pub async fn handle(&self, request: &Request) -> Response {
+        // A página não tem dados; ela pede o token e o usa nas chamadas à API
+        if request.method == "GET" && matches!(request.path.as_str(), "/" | "/index.html") {
+            return Response::new(200, "text/html; charset=utf-8", INDEX_HTML);
+        }
+        if !self.authorized(request) {
+            // Clientes do Transmission só pedem usuário e senha diante do desafio Basic
+            let challenge = if request.path == transmission::RPC_PATH { "Basic realm=\"Transmission\"" } else { "Bearer" };
+            return error_response(401, "token ausente ou inválido").with_header("WWW-Authenticate", challenge);
+        }
+        if request.path == transmission::RPC_PATH {
+            return self.inner.transmission.handle(request).await;
+        }
+        match self.route(request).await {
+            Ok(value) => Response::new(200, JSON, value.to_string()),
+            Err(ApiError(status, message)) => error_response(status, &message),
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let authorization = request.header("Authorization").unwrap_or_default();
+        let token = if let Some(token) = authorization.strip_prefix("Bearer ") {
+            token.as_bytes().to_vec()
+        } else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+            // usuário:senha; o usuário é ignorado
+            let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).unwrap_or_default();
+            match decoded.iter().position(|&byte| byte == b':') {
+                Some(colon) => decoded[colon + 1..].to_vec(),
+                None => return false,
+            }
+        } else if let Some(token) = request.query_str("token") {
+            token.as_bytes().to_vec()
+        } else {
+            return false;
+        };
+        constant_time_eq(token.trim_ascii(), self.inner.token.as_bytes())
+    }
+
+    async fn route(&self, request: &Request) -> ApiResult {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let method = request.method.as_str();
+        match segments.as_slice() {
+            ["api", "torrents"] => match method {
+                "GET" => self.list().await,
+                "POST" => self.add(request).await,
+                _ => Err(method_not_allowed()),
+            },
+            ["api", "torrents", hash] => {
+                let info_hash = parse_hash(hash)?;
+                match method {
+                    "GET" => self.status(&info_hash).await,
+                    "DELETE" => {
+                        let delete_files = matches!(request.query_str("delete_files"), Some("true" | "1"));
+                        self.inner.session.remove(&info_hash, delete_files).await?;
+                        Ok(json!({ "removed": info_hash.to_hex() }))
+                    }
+                    _ => Err(method_not_allowed()),
+                }
+            }
+            ["api", "torrents", hash, action] => {
+                let info_hash = parse_hash(hash)?;
+                match (method, *action) {
+                    ("POST", "pause") => {
+                        self.inner.session.pause(&info_hash).await?;
+                        self.status(&info_hash).await
+                    }
+                    ("POST", "resume") => {
+                        self.inner.session.resume(&info_hash).await?;
+                        self.status(&info_hash).await
+                    }
+                    ("GET", "peers") => self.peers(&info_hash).await,
+                    ("GET", "pieces") => {
+                        let have = self.opened(&info_hash).await?.bitfield().await;
+                        Ok(json!({ "piece_count": have.len(), "bitfield": hex::encode(have.as_bytes()) }))
+                    }
+                    ("GET", "limits") => Ok(limits_json(&self.opened(&info_hash).await?.limits)),
+                    ("PUT", "limits") => {
+                        let update: LimitsUpdate = parse_body(request)?;
+                        update.check()?;
+                        let torrent = self.opened(&info_hash).await?;
+                        update.apply(&torrent.limits)?;
+                        Ok(limits_json(&torrent.limits))
+                    }
+                    (_, "pause" | "resume" | "peers" | "pieces" | "limits") => Err(method_not_allowed()),
+                    _ => Err(not_found()),
+                }
+            }
+            ["api", "limits"] => match method {
+                "GET" => Ok(self.global_limits()),
+                "PUT" => {
+                    let update: GlobalLimitsUpdate = parse_body(request)?;
+                    update.global.check()?;
+                    update.peer.check()?;
+                    let peer = self.inner.session.peer();
+                    update.global.apply(&peer.global_limits)?;
+                    update.peer.apply(&peer.peer_limits)?;
+                    if let Some(slots) = update.unchoke_slots {
+                        peer.set_unchoke_slots(slots);
+                    }
+                    Ok(self.global_limits())
+                }
+                _ => Err(method_not_allowed()),
+            },
+            ["api", "chat"] => match method {
+                "POST" => self.send_chat(parse_body(request)?).await,
+                _ => Err(method_not_allowed()),
+            },
+            ["api", "chat", "ws"] => match &self.chat {
+                Some(_) => Err(ApiError(400, "esperado um pedido de WebSocket".to_string())),
+                None => Err(ApiError(404, "chat não disponível neste modo".to_string())),
+            },
+            _ => Err(not_found()),
+        }
+    }
+
+    async fn send_chat(&self, chat: ChatRequest) -> ApiResult {
+        let message = format!("{}: {}", self.inner.session.peer().name, chat.message);
+        chat::send_message(chat.port, message)
+            .await
+            .map_err(|e| ApiError(502, format!("erro ao enviar para a porta {}: {}", chat.port, e)))?;
+        Ok(json!({ "sent": true }))
+    }
+
+    async fn list(&self) -> ApiResult {
+        let torrents: Vec<Value> = self.inner.session.list().await.iter().map(status_json).collect();
+        Ok(json!({ "torrents": torrents }))
+    }
+
+    async fn status(&self, info_hash: &InfoHash) -> ApiResult {
+        self.inner
+            .session
+            .list()
+            .await
+            .iter()
+            .find(|status| status.info_hash == *info_hash)
+            .map(status_json)
+            .ok_or_else(|| SessionError::NotFound(*info_hash).into())
+    }
+
+    async fn add(&self, request: &Request) -> ApiResult {
+        let session = &self.inner.session;
+        let is_torrent_file = request
+            .header("Content-Type")
+            .is_some_and(|content_type| content_type.starts_with("application/x-bittorrent"));
+        let info_hash = if is_torrent_file {
+            let metainfo = Metainfo::from_bytes(&request.body).map_err(|e| ApiError(400, e.to_string()))?;
+            let save_dir = request.query_str("save_dir").map(PathBuf::from);
+            session.add(metainfo, save_dir.unwrap_or_else(|| self.inner.download_dir.clone())).await?
+        } else {
+            let add: AddRequest = parse_body(request)?;
+            let save_dir = add.save_dir.unwrap_or_else(|| self.inner.download_dir.clone());
+            if add.source.starts_with("magnet:") {
+                let magnet = Magnet::parse(&add.source).map_err(|e| ApiError(400, e.to_string()))?;
+                session.add_magnet(magnet, save_dir).await?
+            } else {
+                let metainfo = Metainfo::load(Path::new(&add.source))
+                    .map_err(|e| ApiError(400, format!("erro ao abrir {}: {}", add.source, e)))?;
+                session.add(metainfo, save_dir).await?
+            }
+        };
+        self.status(&info_hash).await
+    }
+
+    async fn peers(&self, info_hash: &InfoHash) -> ApiResult {
+        let peers: Vec<Value> = self
+            .opened(info_hash)
+            .await?
+            .peers()
+            .await
+            .iter()
+            .map(|peer| {
+                json!({
+                    "addr": peer.addr.to_string(),
+                    "peer_id": String::from_utf8_lossy(&peer.peer_id),
+                    "listen": peer.listen.map(|addr| addr.to_string()),
+                    "downloaded": peer.downloaded,
+                    "uploaded": peer.uploaded,
+                    "download_rate": peer.download_rate,
+                    "upload_rate": peer.upload_rate,
+                    "interested": peer.interested,
+                    "unchoked": peer.unchoked,
+                })
+            })
+            .collect();
+        Ok(json!({ "peers": peers }))
+    }
+
+    /// O torrent aberto; pausados e os que esperam metadados não têm conexões nem limites.
+    async fn opened(&self, info_hash: &InfoHash) -> Result<Arc<Torrent>, ApiError> {
+        match self.inner.session.torrent(info_hash).await {
+            Some(torrent) => Ok(torrent),
+            None if self.inner.session.state(info_hash).await.is_some() => {
+                Err(ApiError(409, format!("torrent {} não está aberto", info_hash)))
+            }
+            None => Err(SessionError::NotFound(*info_hash).into()),
+        }
+    }
+
+    fn global_limits(&self) -> Value {
+        let peer = self.inner.session.peer();
+        json!({
+            "global": limits_json(&peer.global_limits),
+            "peer": limits_json(&peer.peer_limits),
+            "unchoke_slots": peer.unchoke_slots(),
+        })
+    }
+}
+
+impl LimitsUpdate {
+    /// Recusa valores que não cabem em bytes/s antes de alterar qualquer limite.
+    fn check(&self) -> Result<(), ApiError> {
+        for kib in [self.upload, self.download].into_iter().flatten() {
+            ratelimit::kib_to_bytes(kib)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, limits: &RateLimits) -> Result<(), ApiError> {
+        if let Some(upload) = self.upload {
+            limits.upload.set_rate_kib(upload)?;
+        }
+        if let Some(download) = self.download {
+            limits.download.set_rate_kib(download)?;
+        }
+        Ok(())
+    }
+}
+
+fn limits_json(limits: &RateLimits) -> Value {
+    let kib = |limiter: &RateLimiter| limiter.rate() / 1024;
+    json!({ "upload": kib(&limits.upload), "download": kib(&limits.download) })
+}
+
+fn status_json(status: &TorrentStatus) -> Value {
+    let (have, total) = status.pieces;
+    let (state, error) = match &status.state {
+        TorrentState::Metadata => ("metadata", None),
+        TorrentState::Checking => ("checking", None),
+        TorrentState::Downloading => ("downloading", None),
+        TorrentState::Seeding => ("seeding", None),
+        TorrentState::Paused => ("paused", None),
+        TorrentState::Error(e) => ("error", Some(e.as_str())),
+    };
+    json!({
+        "info_hash": status.info_hash.to_hex(),
+        "name": status.name,
+        "state": state,
+        "error": error,
+        "save_dir": status.save_dir,
+        "pieces": have,
+        "piece_count": total,
+        "size": status.size,
+        "left": status.left,
+        "progress": if total == 0 { 0.0 } else { have as f64 / total as f64 },
+        "uploaded": status.uploaded,
+        "downloaded": status.downloaded,
+        "total_uploaded": status.total_uploaded,
+        "total_downloaded": status.total_downloaded,
+        "download_rate": status.download_rate,
+        "upload_rate": status.upload_rate,
+        "peers": status.peers,
+    })
+}
+
+fn parse_hash(hash: &str) -> Result<InfoHash, ApiError> {
+    InfoHash::from_hex(hash).ok_or_else(|| ApiError(400, format!("info-hash inválido: {}", hash)))
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(request: &Request) -> Result<T, ApiError> {
+    serde_json::from_slice(&request.body).map_err(|e| ApiError(400, format!("JSON inválido: {}", e)))
+}
+
+fn not_found() -> ApiError {
+    ApiError(404, "rota não encontrada".to_string())
+}
+
+fn method_not_allowed() -> ApiError {
+    ApiError(405, "método não permitido".to_string())
+}
+
+fn error_response(status: u16, message: &str) -> Response {
+    Response::new(status, JSON, json!({ "error": message }).to_string())
+}
+
+/// Compara sem encerrar no primeiro byte diferente, para não vazar o token pelo tempo de resposta.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::Peer;
+    use crate::ratelimit::UNLIMITED;
+
+    const TOKEN: &str = "segredo";
+
+    fn server() -> ApiServer {
+        let session = Session::new(Peer::new("127.0.0.1".to_string(), 0, "teste".to_string()));
+        ApiServer::new(session, TOKEN.to_string(), std::env::temp_dir())
+    }
+
+    fn request(method: &str, path: &str, authorization: Option<String>, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: Vec::new(),
+            headers: authorization.map(|value| ("Authorization".to_string(), value)).into_iter().collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn bearer() -> Option<String> {
+        Some(format!("Bearer {}", TOKEN))
+    }
+
+    fn basic(credentials: &str) -> Option<String> {
+        Some(format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials)))
+    }
+
+    fn challenge(response: &Response) -> Option<&str> {
+        response.headers.iter().find(|(name, _)| name == "WWW-Authenticate").map(|(_, value)| value.as_str())
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_bearer_basic_and_query_tokens() {
+        let server = server();
+        for authorization in [bearer(), basic(&format!(":{}", TOKEN)), basic(&format!("admin:{}", TOKEN))] {
+            let response = server.handle(&request("GET", "/api/limits", authorization.clone(), "")).await;
+            assert_eq!(response.status, 200, "{:?}", authorization);
+        }
+        let mut with_query = request("GET", "/api/limits", None, "");
+        with_query.query.push(("token".to_string(), TOKEN.as_bytes().to_vec()));
+        assert_eq!(server.handle(&with_query).await.status, 200);
+
+        // A página não tem dados e não pede token
+        assert_eq!(server.handle(&request("GET", "/", None, "")).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_or_wrong_tokens_with_challenge() {
+        let server = server();
+        let rejected = [
+            None,
+            Some("Bearer errado".to_string()),
+            Some(format!("Bearer {}x", TOKEN)),
+            Some(format!("Token {}", TOKEN)),
+            basic(TOKEN),
+            basic(&format!("{}:", TOKEN)),
+            Some("Basic %%%".to_string()),
+        ];
+        for authorization in rejected {
+            let response = server.handle(&request("GET", "/api/limits", authorization.clone(), "")).await;
+            assert_eq!(response.status, 401, "{:?}", authorization);
+            assert_eq!(challenge(&response), Some("Bearer"));
+        }
+        let mut with_query = request("GET", "/api/limits", None, "");
+        with_query.query.push(("token".to_string(), b"errado".to_vec()));
+        assert_eq!(server.handle(&with_query).await.status, 401);
+
+        // Clientes do Transmission recebem o desafio Basic
+        let response = server.handle(&request("POST", transmission::RPC_PATH, None, "{}")).await;
+        assert_eq!(response.status, 401);
+        assert_eq!(challenge(&response), Some("Basic realm=\"Transmission\""));
+        let response = server.handle(&request("POST", transmission::RPC_PATH, basic(&format!("u:{}", TOKEN)), "{}")).await;
+        assert_eq!(response.status, 409);
+    }
+
+    #[tokio::test]
+    async fn maps_errors_to_status_codes() {
+        let server = server();
+        let hash = "ab".repeat(20);
+        let call = |method: &str, path: String, body: &str| {
+            let request = request(method, &path, bearer(), body);
+            let server = server.clone();
+            async move { server.handle(&request).await.status }
+        };
+        assert_eq!(call("GET", "/api/nada".to_string(), "").await, 404);
+        assert_eq!(call("GET", format!("/api/torrents/{}", hash), "").await, 404);
+        assert_eq!(call("GET", format!("/api/torrents/{}/nada", hash), "").await, 404);
+        assert_eq!(call("DELETE", "/api/limits".to_string(), "").await, 405);
+        assert_eq!(call("GET", format!("/api/torrents/{}/pause", hash), "").await, 405);
+        assert_eq!(call("PUT", "/api/torrents".to_string(), "").await, 405);
+        assert_eq!(call("GET", "/api/torrents/xyz".to_string(), "").await, 400);
+        assert_eq!(call("POST", "/api/torrents".to_string(), "{").await, 400);
+
+        let magnet = json!({ "source": format!("magnet:?xt=urn:btih:{}&dn=teste", hash) }).to_string();
+        assert_eq!(call("POST", "/api/torrents".to_string(), &magnet).await, 200);
+        assert_eq!(call("POST", "/api/torrents".to_string(), &magnet).await, 409);
+        assert_eq!(call("DELETE", format!("/api/torrents/{}", hash), "").await, 200);
+        assert_eq!(call("DELETE", format!("/api/torrents/{}", hash), "").await, 404);
+    }
+
+    #[tokio::test]
+    async fn limits_update_rejects_overflow_without_partial_changes() {
+        let server = server();
+        let peer = server.inner.session.peer();
+        let update = json!({ "global": { "upload": 10 }, "peer": { "download": u64::MAX } }).to_string();
+        let response = server.handle(&request("PUT", "/api/limits", bearer(), &update)).await;
+        assert_eq!(response.status, 400);
+        assert!(body(&response)["error"].as_str().unwrap().contains("grande demais"));
+        assert_eq!(peer.global_limits.upload.rate(), UNLIMITED);
+        assert_eq!(peer.peer_limits.download.rate(), UNLIMITED);
+
+        let update = json!({ "global": { "upload": 10 }, "peer": { "download": 20 }, "unchoke_slots": 6 }).to_string();
+        let response = server.handle(&request("PUT", "/api/limits", bearer(), &update)).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            body(&response),
+            json!({ "global": { "upload": 10, "download": 0 }, "peer": { "upload": 0, "download": 20 }, "unchoke_slots": 6 })
+        );
+        assert_eq!(peer.global_limits.upload.rate(), 10 * 1024);
+
+        let unknown = json!({ "global": { "up": 1 } }).to_string();
+        assert_eq!(server.handle(&request("PUT", "/api/limits", bearer(), &unknown)).await.status, 400);
+    }
+}
@@ -0,0 +1,194 @@
+﻿use super::{decode, Error, Result, Value};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use std::collections::btree_map;
+use std::vec;
+
+/// Converte um `Value` em qualquer tipo desserializável.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T> {
+    T::deserialize(value)
+}
+
+/// Decodifica bytes bencode (na forma canônica) diretamente em um tipo.
+pub fn from_bytes<T: DeserializeOwned>(input: &[u8]) -> Result<T> {
+    from_value(decode(input)?)
+}
+
+impl Value {
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match self {
+            Value::Integer(i) => de::Unexpected::Signed(*i),
+            Value::Bytes(b) => de::Unexpected::Bytes(b),
+            Value::List(_) => de::Unexpected::Seq,
+            Value::Dict(_) => de::Unexpected::Map,
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Bytes(b) => visitor.visit_byte_buf(b),
+            Value::List(l) => visitor.visit_seq(SeqAccess { iter: l.into_iter() }),
+            Value::Dict(d) => visitor.visit_map(MapAccess { iter: d.into_iter(), value: None }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Integer(0) => visitor.visit_bool(false),
+            Value::Integer(1) => visitor.visit_bool(true),
+            other => Err(de::Error::invalid_type(other.unexpected(), &"0 ou 1")),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Bytes(b) => match String::from_utf8(b) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => Err(de::Error::invalid_value(de::Unexpected::Bytes(e.as_bytes()), &"UTF-8 válido")),
+            },
+            other => Err(de::Error::invalid_type(other.unexpected(), &"uma string")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            Value::Bytes(b) => {
+                let variant = String::from_utf8(b).map_err(|_| Error::custom("nome de variante inválido"))?;
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            Value::Dict(d) if d.len() == 1 => {
+                let (variant, value) = d.into_iter().next().unwrap();
+                visitor.visit_enum(EnumAccess { variant: Value::Bytes(variant), value })
+            }
+            other => Err(de::Error::invalid_type(other.unexpected(), &"uma variante de enum")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf
+        unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl IntoDeserializer<'_, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct SeqAccess {
+    iter: vec::IntoIter<Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        self.iter.next().map(|v| seed.deserialize(v)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapAccess {
+    iter: btree_map::IntoIter<Vec<u8>, Value>,
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Value::Bytes(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| Error::custom("valor lido antes da chave"))?;
+        seed.deserialize(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess {
+    variant: Value,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess)> {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, VariantAccess { value: self.value }))
+    }
+}
+
+struct VariantAccess {
+    value: Value,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.value, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.value, visitor)
+    }
+}
@@ -0,0 +1,413 @@
+﻿use super::{Dict, Error, ErrorKind, Result, Value, MAX_DEPTH};
+use std::ops::Range;
+
+/// Elemento léxico produzido pelo `Decoder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    Integer(i64),
+    Bytes(&'a [u8]),
+    /// Início de lista (`l`).
+    List,
+    /// Início de dicionário (`d`).
+    Dict,
+    /// Fim da lista ou dicionário atual (`e`).
+    End,
+}
+
+enum Frame<'a> {
+    List,
+    Dict { expect_key: bool, last_key: Option<&'a [u8]> },
+}
+
+/// Decodificador incremental: lê um token por vez sem montar a árvore inteira
+/// e já rejeita entrada fora da forma canônica, informando a posição do erro.
+pub struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    stack: Vec<Frame<'a>>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input, pos: 0, stack: Vec::new() }
+    }
+
+    /// Offset do próximo byte a ser lido.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Quantidade de listas/dicionários abertos.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Indica se toda a entrada foi consumida.
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    /// Lê o próximo token.
+    pub fn next_token(&mut self) -> Result<Token<'a>> {
+        let start = self.pos;
+        let byte = *self
+            .input
+            .get(start)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, start))?;
+
+        if byte == b'e' {
+            return match self.stack.pop() {
+                Some(Frame::Dict { expect_key: false, .. }) | None => {
+                    Err(Error::new(ErrorKind::InvalidByte(b'e'), start))
+                }
+                Some(_) => {
+                    self.pos += 1;
+                    Ok(Token::End)
+                }
+            };
+        }
+
+        let token = match byte {
+            b'i' => Token::Integer(self.parse_integer()?),
+            b'0'..=b'9' => Token::Bytes(self.parse_bytes()?),
+            b'l' | b'd' => {
+                if self.stack.len() >= MAX_DEPTH {
+                    return Err(Error::new(ErrorKind::TooDeep, start));
+                }
+                self.pos += 1;
+                if byte == b'l' { Token::List } else { Token::Dict }
+            }
+            other => return Err(Error::new(ErrorKind::InvalidByte(other), start)),
+        };
+
+        // Dentro de dicionários, alterna entre chave e valor e valida a ordem das chaves
+        if let Some(Frame::Dict { expect_key, last_key }) = self.stack.last_mut() {
+            if *expect_key {
+                let key = match token {
+                    Token::Bytes(key) => key,
+                    _ => return Err(Error::new(ErrorKind::KeyNotBytes, start)),
+                };
+                if let Some(last) = last_key {
+                    if key == *last {
+                        return Err(Error::new(ErrorKind::DuplicateKey, start));
+                    }
+                    if key < *last {
+                        return Err(Error::new(ErrorKind::UnsortedKey, start));
+                    }
+                }
+                *last_key = Some(key);
+                *expect_key = false;
+            } else {
+                *expect_key = true;
+            }
+        }
+
+        match token {
+            Token::List => self.stack.push(Frame::List),
+            Token::Dict => self.stack.push(Frame::Dict { expect_key: true, last_key: None }),
+            _ => {}
+        }
+
+        Ok(token)
+    }
+
+    /// Lê um valor completo a partir da posição atual.
+    pub fn decode_value(&mut self) -> Result<Value> {
+        let token = self.next_token()?;
+        self.build(token)
+    }
+
+    /// Pula um valor completo e retorna o intervalo de bytes que ele ocupa.
+    ///
+    /// Usado para recuperar o trecho original de um valor, como o dicionário
+    /// `info` de um .torrent.
+    pub fn skip_value(&mut self) -> Result<Range<usize>> {
+        let start = self.pos;
+        let base = self.stack.len();
+        loop {
+            self.next_token()?;
+            if self.stack.len() == base {
+                return Ok(start..self.pos);
+            }
+        }
+    }
+
+    fn build(&mut self, token: Token<'a>) -> Result<Value> {
+        match token {
+            Token::Integer(i) => Ok(Value::Integer(i)),
+            Token::Bytes(b) => Ok(Value::Bytes(b.to_vec())),
+            Token::List => {
+                let mut list = Vec::new();
+                loop {
+                    match self.next_token()? {
+                        Token::End => return Ok(Value::List(list)),
+                        token => list.push(self.build(token)?),
+                    }
+                }
+            }
+            Token::Dict => {
+                let mut dict = Dict::new();
+                loop {
+                    let key = match self.next_token()? {
+                        Token::End => return Ok(Value::Dict(dict)),
+                        Token::Bytes(key) => key.to_vec(),
+                        // `next_token` já garante que a chave é uma string
+                        _ => unreachable!(),
+                    };
+                    let token = self.next_token()?;
+                    dict.insert(key, self.build(token)?);
+                }
+            }
+            Token::End => Err(Error::new(ErrorKind::InvalidByte(b'e'), self.pos - 1)),
+        }
+    }
+
+    fn parse_integer(&mut self) -> Result<i64> {
+        let start = self.pos + 1;
+        let mut pos = start;
+        let negative = self.input.get(pos) == Some(&b'-');
+        if negative {
+            pos += 1;
+        }
+        let digits_start = pos;
+        let mut value: i64 = 0;
+
+        loop {
+            let byte = *self
+                .input
+                .get(pos)
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, pos))?;
+            match byte {
+                b'0'..=b'9' => {
+                    if pos > digits_start && self.input[digits_start] == b'0' {
+                        let kind = if negative { ErrorKind::NegativeZero } else { ErrorKind::LeadingZero };
+                        return Err(Error::new(kind, digits_start));
+                    }
+                    let digit = (byte - b'0') as i64;
+                    value = value
+                        .checked_mul(10)
+                        .and_then(|v| if negative { v.checked_sub(digit) } else { v.checked_add(digit) })
+                        .ok_or_else(|| Error::new(ErrorKind::Overflow, start))?;
+                }
+                b'e' if pos > digits_start => break,
+                _ => return Err(Error::new(ErrorKind::InvalidInteger, pos)),
+            }
+            pos += 1;
+        }
+
+        if negative && value == 0 {
+            return Err(Error::new(ErrorKind::NegativeZero, start));
+        }
+
+        self.pos = pos + 1;
+        Ok(value)
+    }
+
+    fn parse_bytes(&mut self) -> Result<&'a [u8]> {
+        let start = self.pos;
+        let mut pos = start;
+        let mut len: usize = 0;
+
+        loop {
+            let byte = *self
+                .input
+                .get(pos)
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, pos))?;
+            match byte {
+                b'0'..=b'9' => {
+                    if pos > start && self.input[start] == b'0' {
+                        return Err(Error::new(ErrorKind::LeadingZero, start));
+                    }
+                    len = len
+                        .checked_mul(10)
+                        .and_then(|l| l.checked_add((byte - b'0') as usize))
+                        .ok_or_else(|| Error::new(ErrorKind::Overflow, start))?;
+                }
+                b':' => break,
+                other => return Err(Error::new(ErrorKind::InvalidByte(other), pos)),
+            }
+            pos += 1;
+        }
+
+        let data_start = pos + 1;
+        let data_end = data_start
+            .checked_add(len)
+            .ok_or_else(|| Error::new(ErrorKind::Overflow, start))?;
+        if data_end > self.input.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, self.input.len()));
+        }
+
+        self.pos = data_end;
+        Ok(&self.input[data_start..data_end])
+    }
+}
+
+/// Decodifica exatamente um valor, rejeitando bytes sobrando.
+pub fn decode(input: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder::new(input);
+    let value = decoder.decode_value()?;
+    if !decoder.is_done() {
+        return Err(Error::new(ErrorKind::TrailingData, decoder.position()));
+    }
+    Ok(value)
+}
+
+/// Decodifica um valor do início do buffer.
+///
+/// Retorna `Ok(None)` se o buffer ainda não contém um valor completo, e caso
+/// contrário o valor junto com a quantidade de bytes consumidos.
+pub fn decode_prefix(input: &[u8]) -> Result<Option<(Value, usize)>> {
+    let mut decoder = Decoder::new(input);
+    match decoder.decode_value() {
+        Ok(value) => Ok(Some((value, decoder.position()))),
+        Err(e) if e.is_eof() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{encode, from_bytes, to_bytes, Dict, ErrorKind, Value};
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    /// Tipo e posição do erro ao decodificar `input`.
+    fn error(input: &[u8]) -> (ErrorKind, Option<usize>) {
+        let e = decode(input).unwrap_err();
+        (e.kind().clone(), e.position())
+    }
+
+    #[test]
+    fn decodes_canonical_values() {
+        let value = decode(b"d4:listli1ei-20e3:abce3:numi42e3:str0:e").unwrap();
+        assert_eq!(value.get("num").and_then(Value::as_int), Some(42));
+        assert_eq!(value.get("str").and_then(Value::as_bytes), Some(&b""[..]));
+        let list = value.get("list").and_then(Value::as_list).unwrap();
+        assert_eq!(list, &[Value::Integer(1), Value::Integer(-20), Value::from("abc")]);
+        assert_eq!(decode(b"i0e").unwrap(), Value::Integer(0));
+        assert_eq!(decode(b"i-9223372036854775808e").unwrap(), Value::Integer(i64::MIN));
+    }
+
+    #[test]
+    fn rejects_unsorted_and_duplicate_keys() {
+        assert_eq!(error(b"d1:bi1e1:ai2ee"), (ErrorKind::UnsortedKey, Some(7)));
+        assert_eq!(error(b"d1:ai1e1:ai2ee"), (ErrorKind::DuplicateKey, Some(7)));
+        // A ordem vale por dicionário, não entre dicionários aninhados
+        assert!(decode(b"d1:bd1:ai1ee1:cd1:ai2eee").is_ok());
+        assert_eq!(error(b"d1:ad1:zi1e1:yi2eee"), (ErrorKind::UnsortedKey, Some(11)));
+        assert_eq!(error(b"di1ei2ee"), (ErrorKind::KeyNotBytes, Some(1)));
+    }
+
+    #[test]
+    fn rejects_non_canonical_numbers() {
+        assert_eq!(error(b"i-0e"), (ErrorKind::NegativeZero, Some(1)));
+        assert_eq!(error(b"i-03e"), (ErrorKind::NegativeZero, Some(2)));
+        assert_eq!(error(b"i03e"), (ErrorKind::LeadingZero, Some(1)));
+        assert_eq!(error(b"i00e"), (ErrorKind::LeadingZero, Some(1)));
+        assert_eq!(error(b"03:abc"), (ErrorKind::LeadingZero, Some(0)));
+        assert_eq!(error(b"l1:a03:abce"), (ErrorKind::LeadingZero, Some(4)));
+        assert_eq!(error(b"ie"), (ErrorKind::InvalidInteger, Some(1)));
+        assert_eq!(error(b"i-e"), (ErrorKind::InvalidInteger, Some(2)));
+        assert_eq!(error(b"i1x2e"), (ErrorKind::InvalidInteger, Some(2)));
+        assert_eq!(error(b"i9223372036854775808e"), (ErrorKind::Overflow, Some(1)));
+    }
+
+    #[test]
+    fn reports_truncated_input() {
+        assert_eq!(error(b""), (ErrorKind::UnexpectedEof, Some(0)));
+        assert_eq!(error(b"i42"), (ErrorKind::UnexpectedEof, Some(3)));
+        assert_eq!(error(b"5:abc"), (ErrorKind::UnexpectedEof, Some(5)));
+        assert_eq!(error(b"12"), (ErrorKind::UnexpectedEof, Some(2)));
+        assert_eq!(error(b"li1e"), (ErrorKind::UnexpectedEof, Some(4)));
+        assert_eq!(error(b"d3:key"), (ErrorKind::UnexpectedEof, Some(6)));
+        assert!(decode_prefix(b"d3:keyi1").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_trailing_and_invalid_bytes() {
+        assert_eq!(error(b"i1ei2e"), (ErrorKind::TrailingData, Some(3)));
+        assert_eq!(error(b"le0:"), (ErrorKind::TrailingData, Some(2)));
+        assert_eq!(error(b"x"), (ErrorKind::InvalidByte(b'x'), Some(0)));
+        assert_eq!(error(b"e"), (ErrorKind::InvalidByte(b'e'), Some(0)));
+        // Dicionário terminado depois de uma chave sem valor
+        assert_eq!(error(b"d1:ae"), (ErrorKind::InvalidByte(b'e'), Some(4)));
+        assert_eq!(error(b"3x:abc"), (ErrorKind::InvalidByte(b'x'), Some(1)));
+        let deep = [vec![b'l'; MAX_DEPTH + 1], vec![b'e'; MAX_DEPTH + 1]].concat();
+        assert_eq!(error(&deep), (ErrorKind::TooDeep, Some(MAX_DEPTH)));
+    }
+
+    #[test]
+    fn decode_prefix_reports_consumed_bytes() {
+        let (value, used) = decode_prefix(b"i7e4:rest").unwrap().unwrap();
+        assert_eq!((value, used), (Value::Integer(7), 3));
+        assert!(decode_prefix(b"i07e").is_err());
+    }
+
+    #[test]
+    fn skip_value_returns_original_range() {
+        let input = b"d4:infod4:name1:xe5:otheri1ee";
+        let mut decoder = Decoder::new(input);
+        assert_eq!(decoder.next_token().unwrap(), Token::Dict);
+        assert_eq!(decoder.next_token().unwrap(), Token::Bytes(b"info"));
+        let range = decoder.skip_value().unwrap();
+        assert_eq!(&input[range], b"d4:name1:xe");
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut inner = Dict::new();
+        inner.insert(b"z".to_vec(), Value::Integer(-1));
+        inner.insert(b"a".to_vec(), Value::from(vec![0u8, 255, b':']));
+        let mut dict = Dict::new();
+        dict.insert(b"list".to_vec(), Value::from(vec![Value::Integer(0), Value::from(inner)]));
+        dict.insert(b"empty".to_vec(), Value::from(Vec::<Value>::new()));
+        dict.insert(b"big".to_vec(), Value::Integer(i64::MAX));
+        let value = Value::from(dict);
+
+        let bytes = encode(&value);
+        assert_eq!(decode(&bytes).unwrap(), value);
+        // A saída do codificador já é canônica: recodificar não muda nada
+        assert_eq!(encode(&decode(&bytes).unwrap()), bytes);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct File {
+        path: Vec<String>,
+        length: u64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Info {
+        // Fora da ordem alfabética de propósito: o serializador precisa ordenar
+        name: String,
+        length: Option<u64>,
+        files: Vec<File>,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        private: Option<i64>,
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let info = Info {
+            name: "pasta".to_string(),
+            length: None,
+            files: vec![File { path: vec!["a".to_string(), "b.txt".to_string()], length: 10 }],
+            pieces: vec![0xAB; 20],
+            private: Some(1),
+        };
+        let bytes = to_bytes(&info).unwrap();
+        // Chaves ordenadas e campos `None` omitidos
+        assert!(bytes.starts_with(b"d5:filesld6:lengthi10e4:pathl1:a5:b.txteee4:name5:pasta6:pieces20:"));
+        assert_eq!(from_bytes::<Info>(&bytes).unwrap(), info);
+        assert!(decode(&bytes).unwrap().get("length").is_none());
+    }
+
+    #[test]
+    fn serde_reports_decode_errors() {
+        let e = from_bytes::<Info>(b"d4:name1:xi1e").unwrap_err();
+        assert_eq!(e.kind(), &ErrorKind::KeyNotBytes);
+        assert_eq!(e.position(), Some(10));
+        assert!(from_bytes::<Info>(b"d4:name1:xe").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+﻿use super::Value;
+use std::io::{self, Write};
+
+/// Codifica um valor na forma canônica (chaves de dicionário ordenadas).
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_to(value, &mut out).expect("escrita em Vec não falha");
+    out
+}
+
+/// Codifica um valor diretamente em um `Write`.
+pub fn encode_to<W: Write>(value: &Value, out: &mut W) -> io::Result<()> {
+    match value {
+        Value::Integer(i) => write!(out, "i{}e", i),
+        Value::Bytes(b) => write_bytes(b, out),
+        Value::List(list) => {
+            out.write_all(b"l")?;
+            for item in list {
+                encode_to(item, out)?;
+            }
+            out.write_all(b"e")
+        }
+        Value::Dict(dict) => {
+            out.write_all(b"d")?;
+            // BTreeMap<Vec<u8>, _> já itera em ordem lexicográfica de bytes
+            for (key, item) in dict {
+                write_bytes(key, out)?;
+                encode_to(item, out)?;
+            }
+            out.write_all(b"e")
+        }
+    }
+}
+
+fn write_bytes<W: Write>(bytes: &[u8], out: &mut W) -> io::Result<()> {
+    write!(out, "{}:", bytes.len())?;
+    out.write_all(bytes)
+}
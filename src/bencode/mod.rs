@@ -0,0 +1,120 @@
+﻿//! Codificação bencode (BEP 3).
+//!
+//! O módulo oferece um tipo de valor (`Value`), um decodificador incremental
+//! que valida a forma canônica da entrada (chaves ordenadas, sem zeros à
+//! esquerda), um codificador canônico e integração com serde.
+
+mod de;
+mod decode;
+mod encode;
+mod ser;
+mod value;
+
+pub use de::{from_bytes, from_value};
+pub use decode::{decode, decode_prefix, Decoder, Token};
+pub use encode::{encode, encode_to};
+pub use ser::{to_bytes, to_value};
+pub use value::{Dict, Value};
+
+use std::fmt;
+
+/// Profundidade máxima de aninhamento aceita pelo decodificador.
+pub const MAX_DEPTH: usize = 256;
+
+/// Tipos de erro de codificação e decodificação.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A entrada terminou antes do fim do valor.
+    UnexpectedEof,
+    /// Byte que não inicia nenhum valor bencode válido.
+    InvalidByte(u8),
+    /// Inteiro ou comprimento com zero à esquerda (`i03e`, `03:abc`).
+    LeadingZero,
+    /// `i-0e` não é permitido.
+    NegativeZero,
+    /// Inteiro vazio ou com dígitos inválidos.
+    InvalidInteger,
+    /// Inteiro ou comprimento que não cabe no tipo nativo.
+    Overflow,
+    /// Chave de dicionário que não é uma string de bytes.
+    KeyNotBytes,
+    /// Chaves de dicionário fora da ordem lexicográfica.
+    UnsortedKey,
+    /// Chave repetida no mesmo dicionário.
+    DuplicateKey,
+    /// Bytes sobrando após o fim do valor.
+    TrailingData,
+    /// Aninhamento acima de `MAX_DEPTH`.
+    TooDeep,
+    /// Erro vindo do serde (campo ausente, tipo incompatível...).
+    Custom(String),
+}
+
+/// Erro de bencode com a posição (offset em bytes) onde ocorreu, quando conhecida.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    position: Option<usize>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, position: usize) -> Self {
+        Self { kind, position: Some(position) }
+    }
+
+    pub fn custom(message: impl fmt::Display) -> Self {
+        Self { kind: ErrorKind::Custom(message.to_string()), position: None }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// Indica que a entrada apenas está incompleta (útil em leituras parciais).
+    pub fn is_eof(&self) -> bool {
+        self.kind == ErrorKind::UnexpectedEof
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::UnexpectedEof => write!(f, "fim inesperado da entrada")?,
+            ErrorKind::InvalidByte(b) => write!(f, "byte inesperado 0x{:02x}", b)?,
+            ErrorKind::LeadingZero => write!(f, "número com zero à esquerda")?,
+            ErrorKind::NegativeZero => write!(f, "zero negativo não é permitido")?,
+            ErrorKind::InvalidInteger => write!(f, "inteiro inválido")?,
+            ErrorKind::Overflow => write!(f, "número grande demais")?,
+            ErrorKind::KeyNotBytes => write!(f, "chave de dicionário deve ser uma string")?,
+            ErrorKind::UnsortedKey => write!(f, "chaves de dicionário fora de ordem")?,
+            ErrorKind::DuplicateKey => write!(f, "chave de dicionário duplicada")?,
+            ErrorKind::TrailingData => write!(f, "dados sobrando após o valor")?,
+            ErrorKind::TooDeep => write!(f, "aninhamento profundo demais")?,
+            ErrorKind::Custom(msg) => write!(f, "{}", msg)?,
+        }
+        if let Some(position) = self.position {
+            write!(f, " na posição {}", position)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::custom(msg)
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::custom(msg)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
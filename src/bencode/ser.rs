@@ -0,0 +1,320 @@
+﻿use super::{encode, Dict, Error, Result, Value};
+use serde::ser::{self, Serialize};
+
+/// Converte qualquer tipo serializável em um `Value`.
+///
+/// Campos `Option` com `None` são omitidos de structs e mapas, já que o
+/// bencode não tem representação para "nulo".
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value> {
+    value
+        .serialize(ValueSerializer)?
+        .ok_or_else(|| Error::custom("valor nulo não pode ser codificado em bencode"))
+}
+
+/// Serializa diretamente para bytes bencode canônicos.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    Ok(encode(&to_value(value)?))
+}
+
+/// Serializador interno; `None` representa um valor ausente.
+struct ValueSerializer;
+
+fn required(value: Option<Value>) -> Result<Value> {
+    value.ok_or_else(|| Error::custom("valor nulo dentro de lista"))
+}
+
+fn key_bytes<T: Serialize + ?Sized>(key: &T) -> Result<Vec<u8>> {
+    match key.serialize(ValueSerializer)? {
+        Some(Value::Bytes(b)) => Ok(b),
+        Some(Value::Integer(i)) => Ok(i.to_string().into_bytes()),
+        _ => Err(Error::custom("chave de dicionário deve ser uma string")),
+    }
+}
+
+fn int<T: TryInto<i64>>(v: T) -> Result<Option<Value>> {
+    v.try_into()
+        .map(|i| Some(Value::Integer(i)))
+        .map_err(|_| Error::custom("inteiro grande demais"))
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        int(v as i64)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        int(v)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok> {
+        Err(Error::custom("bencode não suporta números de ponto flutuante"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok> {
+        Err(Error::custom("bencode não suporta números de ponto flutuante"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok> {
+        Ok(Some(Value::from(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        Ok(Some(Value::from(v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        Ok(Some(Value::from(v)))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Self::Ok> {
+        Ok(Some(Value::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok> {
+        let mut dict = Dict::new();
+        if let Some(value) = value.serialize(ValueSerializer)? {
+            dict.insert(variant.as_bytes().to_vec(), value);
+        }
+        Ok(Some(Value::Dict(dict)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(SeqSerializer { items: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Ok(VariantSerializer { variant, inner: self.serialize_seq(Some(len))? })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(MapSerializer { dict: Dict::new(), pending_key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Ok(VariantSerializer { variant, inner: self.serialize_map(Some(len))? })
+    }
+}
+
+struct SeqSerializer {
+    items: Vec<Value>,
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(required(value.serialize(ValueSerializer)?)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(Value::List(self.items)))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    dict: Dict,
+    pending_key: Option<Vec<u8>>,
+}
+
+impl MapSerializer {
+    fn insert(&mut self, key: Vec<u8>, value: Option<Value>) {
+        if let Some(value) = value {
+            self.dict.insert(key, value);
+        }
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.pending_key = Some(key_bytes(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .pending_key
+            .take()
+            .ok_or_else(|| Error::custom("valor de mapa sem chave"))?;
+        let value = value.serialize(ValueSerializer)?;
+        self.insert(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(Value::Dict(self.dict)))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let value = value.serialize(ValueSerializer)?;
+        self.insert(key.as_bytes().to_vec(), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Variantes com dados viram `{ nome_da_variante: conteúdo }`.
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> VariantSerializer<S> {
+    fn wrap(variant: &'static str, value: Option<Value>) -> Result<Option<Value>> {
+        let mut dict = Dict::new();
+        dict.insert(variant.as_bytes().to_vec(), required(value)?);
+        Ok(Some(Value::Dict(dict)))
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Self::wrap(self.variant, ser::SerializeSeq::end(self.inner)?)
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Self::wrap(self.variant, ser::SerializeMap::end(self.inner)?)
+    }
+}
@@ -0,0 +1,83 @@
+﻿/// Conjunto de peças, no formato da mensagem `bitfield` (bit mais significativo = peça 0).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    /// Bitfield vazio para `len` peças.
+    pub fn new(len: usize) -> Self {
+        Self { bytes: vec![0; len.div_ceil(8)], len }
+    }
+
+    /// Bitfield com todas as peças marcadas.
+    pub fn full(len: usize) -> Self {
+        let mut bitfield = Self::new(len);
+        for index in 0..len {
+            bitfield.set(index);
+        }
+        bitfield
+    }
+
+    /// Interpreta os bytes recebidos de outro peer, rejeitando tamanho errado
+    /// ou bits ligados além da última peça.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Option<Self> {
+        if bytes.len() != len.div_ceil(8) {
+            return None;
+        }
+        let bitfield = Self { bytes: bytes.to_vec(), len };
+        let spare = bitfield.bytes.len() * 8 - len;
+        if spare > 0 && bitfield.bytes[bitfield.bytes.len() - 1] & ((1u8 << spare) - 1) != 0 {
+            return None;
+        }
+        Some(bitfield)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        index < self.len && self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        if index < self.len {
+            self.bytes[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        if index < self.len {
+            self.bytes[index / 8] &= !(0x80 >> (index % 8));
+        }
+    }
+
+    /// Quantidade de peças marcadas.
+    pub fn count(&self) -> usize {
+        self.bytes.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count() == self.len
+    }
+
+    /// Índices das peças marcadas.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |&i| self.has(i))
+    }
+
+    /// Indica se `other` tem alguma peça que falta neste bitfield.
+    pub fn lacks_any_of(&self, other: &Bitfield) -> bool {
+        self.bytes.iter().zip(&other.bytes).any(|(mine, theirs)| theirs & !mine != 0)
+    }
+}
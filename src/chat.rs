@@ -1,48 +1,102 @@
 ﻿use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{Mutex, broadcast, mpsc};
 use std::io;
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use crate::codec::{Decoder, Encoder, FramedRead, FramedWrite, LengthPrefixed};
+use tracing::{debug, info, Instrument};
+
+/// Tamanho máximo de uma mensagem de chat.
+const MAX_CHAT_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Mensagens guardadas para assinantes lentos antes de começarem a perdê-las.
+const CHAT_EVENTS_CAPACITY: usize = 64;
+
+/// Mensagens de chat em texto UTF-8, cada uma num frame com prefixo de tamanho.
+pub struct ChatCodec {
+    frames: LengthPrefixed,
+}
+
+impl Default for ChatCodec {
+    fn default() -> Self {
+        Self { frames: LengthPrefixed::new(MAX_CHAT_MESSAGE_LEN) }
+    }
+}
+
+impl Decoder for ChatCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<String>> {
+        match self.frames.decode(src)? {
+            Some(frame) => String::from_utf8(frame.to_vec())
+                .map(Some)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "mensagem de chat não é UTF-8")),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<String> for ChatCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: String, dst: &mut BytesMut) -> io::Result<()> {
+        self.frames.encode(message.as_bytes(), dst)
+    }
+}
 
 #[derive(Clone)]
 pub struct ChatServer {
     sender: Arc<Mutex<mpsc::Sender<(String, String)>>>,
+    /// Cópia das mensagens recebidas para outros leitores, como a interface web.
+    events: broadcast::Sender<(String, String)>,
 }
 
 impl ChatServer {
     pub fn new(sender: mpsc::Sender<(String, String)>) -> Self {
         Self {
             sender: Arc::new(Mutex::new(sender)),
+            events: broadcast::channel(CHAT_EVENTS_CAPACITY).0,
         }
     }
 
+    /// Recebe as mensagens que chegarem daqui em diante.
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, String)> {
+        self.events.subscribe()
+    }
+
     pub async fn start_chat_server(&self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).await?;
-        println!("Servidor de chat rodando na porta {}", port);
+        info!(port, "Servidor de chat rodando");
 
         loop {
-            let (mut socket, _) = listener.accept().await?;
+            let (socket, remote) = listener.accept().await?;
             let sender = Arc::clone(&self.sender);
+            let events = self.events.clone();
 
-            tokio::spawn(async move {
-                let mut buffer = [0; 1024];
-                while let Ok(n) = socket.read(&mut buffer).await {
-                    if n == 0 {
-                        break; // Se a conexão for fechada, sai do loop
+            let span = tracing::info_span!("chat", %remote);
+            tokio::spawn(
+                async move {
+                    let mut frames = FramedRead::new(socket, ChatCodec::default());
+                    // Encerra quando a conexão fecha ou chega um frame inválido
+                    while let Some(Ok(message)) = frames.next().await {
+                        debug!(len = message.len(), "Mensagem de chat recebida");
+                        // Envia a mensagem recebida para o canal
+                        let _ = events.send((String::from("peer"), message.clone()));
+                        let sender = sender.lock().await;
+                        sender.send((String::from("peer"), message)).await.unwrap();
                     }
-
-                    let message = String::from_utf8_lossy(&buffer[..n]).to_string();
-                    // Envia a mensagem recebida para o canal
-                    let sender = sender.lock().await;
-                    sender.send((String::from("peer"), message)).await.unwrap();
                 }
-            });
+                .instrument(span),
+            );
         }
     }
 }
 
 pub async fn start_chat_client(target_port: u16) -> Result<(), Box<dyn std::error::Error>> {
-    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", target_port)).await?;
+    let stream = TcpStream::connect(format!("127.0.0.1:{}", target_port)).await?;
+    let mut frames = FramedWrite::new(stream, ChatCodec::default());
     println!("Conectado ao chat na porta {}", target_port);
 
     println!("Digite seu nome de peer novamente:");
@@ -59,12 +113,19 @@ pub async fn start_chat_client(target_port: u16) -> Result<(), Box<dyn std::erro
         }
 
         let full_message = format!("{}: {}", peer_name, message);
-        stream.write_all(full_message.as_bytes()).await?;
+        frames.send(full_message).await?;
     }
 
     Ok(())
 }
 
+/// Envia uma única mensagem ao servidor de chat em `target_port` e desconecta.
+pub async fn send_message(target_port: u16, message: String) -> io::Result<()> {
+    let stream = TcpStream::connect(format!("127.0.0.1:{}", target_port)).await?;
+    let mut frames = FramedWrite::new(stream, ChatCodec::default());
+    frames.send(message).await
+}
+
 pub async fn message_receiver(mut receiver: mpsc::Receiver<(String, String)>) {
     while let Some((peer_name, message)) = receiver.recv().await {
         println!("📩 Mensagem recebida de {}: {}", peer_name, message);
@@ -0,0 +1,207 @@
+﻿//! Choker: decide a quais peers enviamos dados (BEP 3, "tit-for-tat").
+//!
+//! A cada rodada, os peers interessados que mais nos enviaram dados (ou, ao
+//! semear, os que mais receberam de nós) ocupam os slots regulares. Um slot
+//! extra, o unchoke otimista, gira entre os demais a cada 30 segundos, para
+//! descobrir peers melhores e dar a peers novos algo para trocar.
+
+use rand::seq::IteratorRandom;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Slots de unchoke por torrent, contando o otimista.
+pub const DEFAULT_UNCHOKE_SLOTS: usize = 4;
+
+/// Intervalo entre rodadas do choker.
+pub const RECHOKE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Intervalo entre trocas do unchoke otimista.
+pub const OPTIMISTIC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Estado de uma conexão no momento da rodada.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerSample {
+    pub addr: SocketAddr,
+    /// O peer declarou interesse nas nossas peças.
+    pub interested: bool,
+    /// Total de bytes recebidos do peer nesta conexão.
+    pub downloaded: u64,
+    /// Total de bytes enviados ao peer nesta conexão.
+    pub uploaded: u64,
+}
+
+pub struct Choker {
+    /// Totais da rodada anterior, para calcular as taxas.
+    previous: HashMap<SocketAddr, (u64, u64)>,
+    last_round: Instant,
+    optimistic: Option<SocketAddr>,
+    last_rotation: Option<Instant>,
+}
+
+impl Default for Choker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Choker {
+    pub fn new() -> Self {
+        Self { previous: HashMap::new(), last_round: Instant::now(), optimistic: None, last_rotation: None }
+    }
+
+    /// Executa uma rodada e devolve os peers que devem ficar unchoked.
+    ///
+    /// `seeding` troca o critério para a taxa de envio, já que um seeder não
+    /// recebe dados de ninguém.
+    pub fn rechoke(&mut self, peers: &[PeerSample], slots: usize, seeding: bool) -> HashSet<SocketAddr> {
+        let elapsed = self.last_round.elapsed().as_secs_f64().max(0.001);
+        self.last_round = Instant::now();
+
+        let mut rated: Vec<(SocketAddr, f64)> = peers
+            .iter()
+            .filter(|peer| peer.interested)
+            .map(|peer| {
+                let (downloaded, uploaded) = self.previous.get(&peer.addr).copied().unwrap_or((0, 0));
+                let transferred = if seeding {
+                    peer.uploaded.saturating_sub(uploaded)
+                } else {
+                    peer.downloaded.saturating_sub(downloaded)
+                };
+                (peer.addr, transferred as f64 / elapsed)
+            })
+            .collect();
+        self.previous = peers.iter().map(|peer| (peer.addr, (peer.downloaded, peer.uploaded))).collect();
+
+        // Maior taxa primeiro; o sort é estável, então empates mantêm a ordem das conexões
+        rated.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let regular = slots.saturating_sub(1);
+        let mut unchoked: HashSet<SocketAddr> = rated.iter().take(regular).map(|(addr, _)| *addr).collect();
+        if slots == 0 {
+            self.optimistic = None;
+            return unchoked;
+        }
+
+        // O otimista é trocado a cada OPTIMISTIC_INTERVAL, ou antes se saiu,
+        // perdeu o interesse ou conquistou um slot regular
+        let still_valid = self
+            .optimistic
+            .is_some_and(|addr| !unchoked.contains(&addr) && rated.iter().any(|(peer, _)| *peer == addr));
+        let rotation_due = self.last_rotation.is_none_or(|last| last.elapsed() >= OPTIMISTIC_INTERVAL);
+        if !still_valid || rotation_due {
+            let candidates = rated.iter().map(|(addr, _)| *addr).filter(|addr| !unchoked.contains(addr));
+            self.optimistic = candidates.choose(&mut rand::thread_rng());
+            self.last_rotation = Some(Instant::now());
+        }
+        unchoked.extend(self.optimistic);
+        unchoked
+    }
+
+    pub fn optimistic(&self) -> Option<SocketAddr> {
+        self.optimistic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn sample(port: u16, interested: bool, downloaded: u64, uploaded: u64) -> PeerSample {
+        PeerSample { addr: addr(port), interested, downloaded, uploaded }
+    }
+
+    /// Peers 1..=6 interessados, com download crescente e upload decrescente,
+    /// e o peer 7, o mais rápido, sem interesse.
+    fn swarm() -> Vec<PeerSample> {
+        let mut peers: Vec<PeerSample> = (1..=6u16).map(|port| sample(port, true, port as u64 * 1000, (7 - port) as u64 * 1000)).collect();
+        peers.push(sample(7, false, 100_000, 100_000));
+        peers
+    }
+
+    #[test]
+    fn fastest_interested_peers_get_regular_slots() {
+        let mut choker = Choker::new();
+        let unchoked = choker.rechoke(&swarm(), 4, false);
+        assert_eq!(unchoked.len(), 4);
+        for port in [6, 5, 4] {
+            assert!(unchoked.contains(&addr(port)));
+        }
+        let optimistic = choker.optimistic().unwrap();
+        assert!([addr(1), addr(2), addr(3)].contains(&optimistic));
+        assert!(unchoked.contains(&optimistic));
+        assert!(!unchoked.contains(&addr(7)));
+    }
+
+    #[test]
+    fn seeding_ranks_by_upload_rate() {
+        let mut choker = Choker::new();
+        let unchoked = choker.rechoke(&swarm(), 3, true);
+        assert!(unchoked.contains(&addr(1)));
+        assert!(unchoked.contains(&addr(2)));
+        assert!(!unchoked.contains(&addr(7)));
+        assert!([addr(3), addr(4), addr(5), addr(6)].contains(&choker.optimistic().unwrap()));
+    }
+
+    #[test]
+    fn rates_use_the_difference_since_the_previous_round() {
+        let mut choker = Choker::new();
+        choker.rechoke(&swarm(), 2, false);
+        // O peer 1 passa a ser o mais rápido desde a rodada anterior
+        let mut peers = swarm();
+        peers[0].downloaded += 50_000;
+        for peer in &mut peers[1..] {
+            peer.downloaded += 10;
+        }
+        let unchoked = choker.rechoke(&peers, 2, false);
+        assert!(unchoked.contains(&addr(1)));
+        assert_ne!(choker.optimistic(), Some(addr(1)));
+    }
+
+    #[test]
+    fn optimistic_is_kept_until_the_interval() {
+        let mut choker = Choker::new();
+        let peers = swarm();
+        choker.rechoke(&peers, 1, false);
+        let first = choker.optimistic().unwrap();
+        for _ in 0..20 {
+            let unchoked = choker.rechoke(&peers, 1, false);
+            assert_eq!(unchoked, HashSet::from([first]));
+        }
+
+        // Vencido o intervalo, o sorteio recomeça entre todos os interessados
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            choker.last_rotation = Some(Instant::now() - OPTIMISTIC_INTERVAL);
+            choker.rechoke(&peers, 1, false);
+            seen.insert(choker.optimistic().unwrap());
+        }
+        assert!(seen.len() > 1);
+        assert!(!seen.contains(&addr(7)));
+    }
+
+    #[test]
+    fn optimistic_is_replaced_when_it_loses_interest() {
+        let mut choker = Choker::new();
+        let mut peers = swarm();
+        choker.rechoke(&peers, 1, false);
+        let first = choker.optimistic().unwrap();
+        peers.iter_mut().find(|peer| peer.addr == first).unwrap().interested = false;
+        let unchoked = choker.rechoke(&peers, 1, false);
+        assert!(!unchoked.contains(&first));
+        assert_eq!(unchoked.len(), 1);
+    }
+
+    #[test]
+    fn zero_slots_unchokes_nobody() {
+        let mut choker = Choker::new();
+        choker.rechoke(&swarm(), 2, false);
+        assert!(choker.optimistic().is_some());
+        assert!(choker.rechoke(&swarm(), 0, false).is_empty());
+        assert_eq!(choker.optimistic(), None);
+        assert!(choker.rechoke(&[], 4, false).is_empty());
+    }
+}
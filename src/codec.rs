@@ -0,0 +1,69 @@
+﻿//! Camada de enquadramento compartilhada pelos protocolos TCP (peers, tracker
+//! HTTP e chat). Os bytes recebidos acumulam num buffer até formarem uma
+//! mensagem completa, então nunca se assume que um `read` traz uma mensagem
+//! inteira nem que ela começa no início da leitura.
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+
+pub use tokio_util::codec::{Decoder, Encoder, Framed, FramedRead, FramedWrite};
+
+/// Frames com prefixo de tamanho de 4 bytes big-endian, como no protocolo de peers.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthPrefixed {
+    max_frame: usize,
+}
+
+impl LengthPrefixed {
+    pub fn new(max_frame: usize) -> Self {
+        Self { max_frame }
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+}
+
+impl Decoder for LengthPrefixed {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        if src.len() < 4 {
+            src.reserve(4 - src.len());
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(src[..4].try_into().unwrap()) as usize;
+        if len > self.max_frame {
+            return Err(frame_too_large(len, self.max_frame));
+        }
+        if src.len() < 4 + len {
+            // Reserva o restante de uma vez para não crescer o buffer aos poucos
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        Ok(Some(src.split_to(len)))
+    }
+}
+
+impl Encoder<&[u8]> for LengthPrefixed {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        if frame.len() > self.max_frame {
+            return Err(frame_too_large(frame.len(), self.max_frame));
+        }
+        dst.reserve(4 + frame.len());
+        dst.put_u32(frame.len() as u32);
+        dst.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+pub fn frame_too_large(len: usize, max: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("frame de {} bytes excede o limite de {} bytes", len, max),
+    )
+}
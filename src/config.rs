@@ -0,0 +1,359 @@
+﻿//! Configuração do cliente: arquivo TOML, variáveis de ambiente e valores padrão.
+//!
+//! Precedência, da menor para a maior: valores padrão, o arquivo
+//! (`<diretório de configuração>/bittorrent-client/config.toml`), as
+//! variáveis `BT_*` e, por último, as opções de linha de comando.
+//!
+//! ```toml
+//! [peer]
+//! name = "seedbox"
+//! ip = "0.0.0.0"
+//! port = 6882
+//!
+//! [tracker]
+//! announce = ["http://tracker.exemplo:6881/announce"]
+//!
+//! [paths]
+//! download_dir = "/srv/torrents"
+//! allocation = "full" # ou "sparse", o padrão
+//!
+//! [limits]
+//! upload = 512 # KiB/s; 0 = sem limite
+//!
+//! [daemon]
+//! torrents = ["/srv/torrents/debian.iso.torrent", "magnet:?xt=urn:btih:..."]
+//! seed = ["/srv/compartilhados/relatorio.pdf"]
+//! log_file = "/var/log/bittorrent-client.log"
+//!
+//! [api]
+//! port = 6890 # sem porta, a API fica desligada
+//!
+//! [log]
+//! filter = "info,bittorrent_client::connection=debug"
+//! format = "json"
+//! ```
+
+use crate::choker;
+use crate::state;
+use crate::storage::Allocation;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Sem porta configurada, o peer escolhe uma em `6882..6882 + RANDOM_PORT_RANGE`.
+const FIRST_PEER_PORT: u16 = 6882;
+const RANDOM_PORT_RANGE: u16 = 1000;
+
+/// Sem porta de chat configurada, o chat escuta na porta do peer mais esta.
+pub const CHAT_PORT_OFFSET: u16 = 1000;
+
+/// Caminho padrão do arquivo de configuração.
+pub fn default_path() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("bittorrent-client").join("config.toml")
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// Variável de ambiente com valor que não pôde ser interpretado.
+    Env { name: &'static str, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "erro ao ler {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "configuração inválida em {}: {}", path.display(), e),
+            ConfigError::Env { name, value } => write!(f, "valor inválido em {}: {:?}", name, value),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub peer: PeerConfig,
+    pub tracker: TrackerConfig,
+    pub paths: PathsConfig,
+    pub limits: LimitsConfig,
+    pub daemon: DaemonConfig,
+    pub api: ApiConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerConfig {
+    /// Sem nome, o modo interativo pergunta.
+    pub name: Option<String>,
+    /// Endereço em que o peer escuta conexões.
+    pub ip: String,
+    /// Sem porta, uma é sorteada a cada execução.
+    pub port: Option<u16>,
+    /// Porta do chat; sem ela, a do peer mais [`CHAT_PORT_OFFSET`].
+    pub chat_port: Option<u16>,
+    pub dht: bool,
+    /// Nós usados para entrar na DHT, como `host:porta`.
+    pub dht_bootstrap: Vec<String>,
+    pub lsd: bool,
+    /// Interface dos anúncios LSD; `0.0.0.0` deixa o sistema escolher.
+    pub lsd_interface: Ipv4Addr,
+    pub unchoke_slots: usize,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            ip: "127.0.0.1".to_string(),
+            port: None,
+            chat_port: None,
+            dht: true,
+            dht_bootstrap: vec![format!("127.0.0.1:{}", TrackerConfig::default().dht_port)],
+            lsd: true,
+            // Os peers escutam em loopback por padrão, então os anúncios locais também ficam nele
+            lsd_interface: Ipv4Addr::LOCALHOST,
+            unchoke_slots: choker::DEFAULT_UNCHOKE_SLOTS,
+        }
+    }
+}
+
+impl PeerConfig {
+    /// A porta configurada ou, sem ela, uma sorteada.
+    pub fn port_or_random(&self) -> u16 {
+        self.port.unwrap_or_else(|| FIRST_PEER_PORT + rand::random::<u16>() % RANDOM_PORT_RANGE)
+    }
+
+    pub fn chat_port(&self, peer_port: u16) -> u16 {
+        self.chat_port.unwrap_or_else(|| peer_port.wrapping_add(CHAT_PORT_OFFSET))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    /// Porta do tracker HTTP e UDP no modo `tracker`.
+    pub port: u16,
+    /// Porta do nó DHT iniciado junto com o tracker.
+    pub dht_port: u16,
+    /// Trackers gravados nos .torrent que geramos.
+    pub announce: Vec<String>,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            port: 6881,
+            dht_port: 6880,
+            announce: vec![
+                "http://127.0.0.1:6881/announce".to_string(),
+                "udp://127.0.0.1:6881/announce".to_string(),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Onde os downloads são gravados.
+    pub download_dir: PathBuf,
+    /// Diretórios listados ao escolher arquivos para compartilhar ou .torrent para baixar.
+    pub share_dirs: Vec<PathBuf>,
+    /// Estado da sessão entre execuções (ver [`crate::state`]).
+    pub state_dir: PathBuf,
+    /// Como os arquivos de um download são criados no disco.
+    pub allocation: Allocation,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        let home = dirs::home_dir().unwrap_or_default();
+        Self {
+            download_dir: dirs::download_dir().unwrap_or_else(|| PathBuf::from("downloads")),
+            share_dirs: vec![home.join("Documents"), home.join("Downloads")],
+            state_dir: state::default_dir(),
+            allocation: Allocation::default(),
+        }
+    }
+}
+
+/// Limites de banda em KiB/s; 0 significa sem limite.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Somando todas as conexões.
+    pub upload: u64,
+    pub download: u64,
+    /// Para cada conexão.
+    pub peer_upload: u64,
+    pub peer_download: u64,
+}
+
+/// O que o modo `daemon` inicia, além dos torrents restaurados da sessão anterior.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Arquivos .torrent ou links magnet a baixar no diretório de download.
+    pub torrents: Vec<String>,
+    /// Arquivos locais a semear; o .torrent de cada um é gerado ao lado dele na primeira vez.
+    pub seed: Vec<PathBuf>,
+    /// Para onde vai a saída; sem ele, a saída padrão (o journal, sob o systemd).
+    pub log_file: Option<PathBuf>,
+}
+
+/// API HTTP de controle (ver [`crate::api`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Sem porta, a API não é iniciada.
+    pub port: Option<u16>,
+    /// Endereço de escuta; fora de loopback, qualquer um na rede pode tentar o token.
+    pub bind: IpAddr,
+    /// Sem token, um é gerado e gravado em `api.token` no diretório de estado.
+    pub token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self { port: None, bind: IpAddr::V4(Ipv4Addr::LOCALHOST), token: None }
+    }
+}
+
+/// Log do cliente.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Níveis por módulo, na sintaxe do `EnvFilter` do tracing-subscriber
+    /// (`warn`, `info,bittorrent_client::tracker=debug`...).
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { filter: "info".to_string(), format: LogFormat::Text }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Uma linha legível por evento.
+    #[default]
+    Text,
+    /// Um objeto JSON por linha, com os campos do evento e os spans em que ele ocorreu.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("formato de log desconhecido: {} (use text ou json)", s)),
+        }
+    }
+}
+
+impl Config {
+    /// Lê o arquivo em `path`; se ele não existe, usa os valores padrão.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+        };
+        Self::parse(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Aplica as variáveis `BT_*` do ambiente do processo.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_env_from(|name| std::env::var(name).ok())
+    }
+
+    /// Aplica as variáveis `BT_*` obtidas por `var`; as ausentes não alteram nada.
+    pub fn apply_env_from(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        let env = Env(var);
+        env.set_some("BT_PEER_NAME", &mut self.peer.name)?;
+        env.set("BT_PEER_IP", &mut self.peer.ip)?;
+        env.set_some("BT_PEER_PORT", &mut self.peer.port)?;
+        env.set_some("BT_CHAT_PORT", &mut self.peer.chat_port)?;
+        env.set_bool("BT_DHT", &mut self.peer.dht)?;
+        env.set_list("BT_DHT_BOOTSTRAP", &mut self.peer.dht_bootstrap);
+        env.set_bool("BT_LSD", &mut self.peer.lsd)?;
+        env.set("BT_LSD_INTERFACE", &mut self.peer.lsd_interface)?;
+        env.set("BT_UNCHOKE_SLOTS", &mut self.peer.unchoke_slots)?;
+        env.set("BT_TRACKER_PORT", &mut self.tracker.port)?;
+        env.set("BT_DHT_PORT", &mut self.tracker.dht_port)?;
+        env.set_list("BT_TRACKERS", &mut self.tracker.announce);
+        env.set("BT_DOWNLOAD_DIR", &mut self.paths.download_dir)?;
+        if let Some(value) = env.get("BT_SHARE_DIRS") {
+            self.paths.share_dirs = std::env::split_paths(&value).collect();
+        }
+        env.set("BT_STATE_DIR", &mut self.paths.state_dir)?;
+        env.set("BT_ALLOCATION", &mut self.paths.allocation)?;
+        env.set("BT_UPLOAD_LIMIT", &mut self.limits.upload)?;
+        env.set("BT_DOWNLOAD_LIMIT", &mut self.limits.download)?;
+        env.set_some("BT_LOG_FILE", &mut self.daemon.log_file)?;
+        env.set_some("BT_API_PORT", &mut self.api.port)?;
+        env.set("BT_API_BIND", &mut self.api.bind)?;
+        env.set_some("BT_API_TOKEN", &mut self.api.token)?;
+        env.set("BT_LOG", &mut self.log.filter)?;
+        env.set("BT_LOG_FORMAT", &mut self.log.format)?;
+        Ok(())
+    }
+}
+
+/// Leitura tipada das variáveis de ambiente.
+struct Env<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn get(&self, name: &str) -> Option<String> {
+        (self.0)(name).filter(|value| !value.is_empty())
+    }
+
+    fn set<T: FromStr>(&self, name: &'static str, target: &mut T) -> Result<(), ConfigError> {
+        if let Some(value) = self.get(name) {
+            *target = value.trim().parse().map_err(|_| ConfigError::Env { name, value })?;
+        }
+        Ok(())
+    }
+
+    fn set_some<T: FromStr>(&self, name: &'static str, target: &mut Option<T>) -> Result<(), ConfigError> {
+        if let Some(value) = self.get(name) {
+            *target = Some(value.trim().parse().map_err(|_| ConfigError::Env { name, value })?);
+        }
+        Ok(())
+    }
+
+    fn set_bool(&self, name: &'static str, target: &mut bool) -> Result<(), ConfigError> {
+        if let Some(value) = self.get(name) {
+            *target = match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                _ => return Err(ConfigError::Env { name, value }),
+            };
+        }
+        Ok(())
+    }
+
+    /// Lista separada por vírgulas.
+    fn set_list(&self, name: &str, target: &mut Vec<String>) {
+        if let Some(value) = self.get(name) {
+            *target = value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect();
+        }
+    }
+}
@@ -0,0 +1,484 @@
+﻿//! Conversa com um peer remoto depois do handshake: troca de bitfield,
+//! interesse, choke e transferência de blocos nos dois sentidos.
+
+use crate::bitfield::Bitfield;
+use crate::codec::{FramedRead, FramedWrite};
+use crate::extension::{self, ExtendedHandshake, MetadataMessage, Pex};
+use crate::picker::Block;
+use crate::ratelimit::{self, RateLimits};
+use crate::torrent::{BlockResult, PeerCounters, PeerSlot, Torrent};
+use crate::wire::{Handshake, Message, MessageCodec, MAX_REQUEST_LEN};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, trace, warn, Instrument};
+
+/// Intervalo de keep-alive; peers costumam desconectar após 2 minutos de silêncio.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(90);
+
+/// Intervalo mínimo entre mensagens ut_pex para o mesmo peer (BEP 11).
+const PEX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Quantos pedidos de bloco ficam pendentes por conexão, para manter o canal ocupado.
+const MAX_PENDING_REQUESTS: usize = 16;
+
+/// Blocos pedidos pelo peer que esperam na fila de envio. Cancel e choke os
+/// tiram daqui, e a tarefa de escrita descarta os que não estão mais no conjunto.
+type Uploads = Arc<Mutex<HashSet<Block>>>;
+
+struct Connection {
+    torrent: Arc<Torrent>,
+    remote: SocketAddr,
+    out: mpsc::Sender<Message>,
+    /// Tráfego e interesse do peer, lidos pelo choker.
+    counters: Arc<PeerCounters>,
+    remote_have: Bitfield,
+    am_choking: bool,
+    am_interested: bool,
+    peer_choking: bool,
+    /// Blocos pedidos a este peer e ainda não recebidos.
+    pending: HashSet<Block>,
+    uploads: Uploads,
+    /// Se o peer anunciou suporte ao protocolo de extensões no handshake.
+    extensions_enabled: bool,
+    /// Handshake estendido do peer, depois de recebido.
+    extensions: Option<ExtendedHandshake>,
+    /// Peers que já informamos a este peer via ut_pex.
+    pex_sent: HashSet<SocketAddr>,
+}
+
+/// Configuração do nosso lado, comum a todas as conexões.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Porta em que aceitamos conexões, informada no handshake estendido.
+    pub listen_port: u16,
+    /// Limites somados de todas as conexões.
+    pub global_limits: Arc<RateLimits>,
+    /// Taxas aplicadas a cada conexão individualmente.
+    pub peer_limits: Arc<RateLimits>,
+}
+
+/// Conduz a conexão até um dos lados fechar ou ambos terem o torrent completo.
+///
+/// `listen` é o endereço em que o peer aceita conexões, conhecido quando fomos
+/// nós que conectamos.
+pub async fn run(
+    stream: TcpStream,
+    torrent: Arc<Torrent>,
+    handshake: &Handshake,
+    listen: Option<SocketAddr>,
+    settings: &Settings,
+) -> io::Result<()> {
+    let remote = stream.peer_addr()?;
+    let Some(slot) = torrent.add_peer(remote, handshake.peer_id, listen).await else {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "já existe conexão com este peer"));
+    };
+    let extensions_enabled = handshake.supports_extensions();
+    // `client` é preenchido quando chega o handshake estendido
+    let span = tracing::info_span!(parent: torrent.span(), "peer", addr = %remote, client = tracing::field::Empty);
+    let result = exchange(stream, Arc::clone(&torrent), remote, slot, extensions_enabled, settings)
+        .instrument(span)
+        .await;
+    torrent.remove_peer(&remote).await;
+    // O slot de unchoke do peer fica livre para outro
+    torrent.request_rechoke();
+    result
+}
+
+async fn exchange(
+    stream: TcpStream,
+    torrent: Arc<Torrent>,
+    remote: SocketAddr,
+    slot: PeerSlot,
+    extensions_enabled: bool,
+    settings: &Settings,
+) -> io::Result<()> {
+    let (reader, writer) = stream.into_split();
+    // Limites desta conexão, que seguem as taxas por peer configuradas
+    let peer_limits = Arc::new(settings.peer_limits.sharing_rates());
+
+    // Leitura e escrita ficam em tarefas separadas para que um envio lento
+    // nunca impeça o recebimento (e vice-versa). Os limites de banda valem
+    // para os blocos de dados: segurar a leitura faz o TCP frear o remetente
+    let (in_tx, mut incoming) = mpsc::channel::<Message>(64);
+    let limits = (Arc::clone(&peer_limits), Arc::clone(&torrent), Arc::clone(&settings.global_limits));
+    let reader_task = tokio::spawn(async move {
+        let (peer, torrent, global) = limits;
+        let mut frames = FramedRead::new(reader, MessageCodec::default());
+        while let Some(message) = frames.next().await {
+            let message = message?;
+            if let Message::Piece { block, .. } = &message {
+                let limiters = [&peer.download, &torrent.limits.download, &global.download];
+                ratelimit::throttle(&limiters, block.len()).await;
+            }
+            if in_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        Ok::<(), io::Error>(())
+    });
+
+    let (out, mut out_rx) = mpsc::channel::<Message>(64);
+    let uploads = Uploads::default();
+    let limits = (peer_limits, Arc::clone(&torrent), Arc::clone(&settings.global_limits));
+    let queued = (Arc::clone(&uploads), Arc::clone(&slot.counters));
+    let writer_task = tokio::spawn(async move {
+        let (peer, torrent, global) = limits;
+        let (uploads, counters) = queued;
+        let mut frames = FramedWrite::new(writer, MessageCodec::default());
+        while let Some(message) = out_rx.recv().await {
+            let Message::Piece { index, begin, block } = &message else {
+                frames.send(message).await?;
+                continue;
+            };
+            let length = block.len();
+            // Cancelado ou descartado por um choke enquanto esperava: não gasta banda
+            if !uploads.lock().unwrap().remove(&Block { index: *index, begin: *begin, length: length as u32 }) {
+                continue;
+            }
+            let limiters = [&peer.upload, &torrent.limits.upload, &global.upload];
+            ratelimit::throttle(&limiters, length).await;
+            frames.send(message).await?;
+            torrent.uploaded.fetch_add(length as u64, Ordering::Relaxed);
+            counters.uploaded.fetch_add(length as u64, Ordering::Relaxed);
+        }
+        Ok::<(), io::Error>(())
+    });
+
+    let piece_count = torrent.piece_count();
+    let mut connection = Connection {
+        torrent: Arc::clone(&torrent),
+        remote,
+        out,
+        counters: slot.counters,
+        remote_have: Bitfield::new(piece_count),
+        am_choking: true,
+        am_interested: false,
+        peer_choking: true,
+        pending: HashSet::new(),
+        uploads,
+        extensions_enabled,
+        extensions: None,
+        pex_sent: HashSet::new(),
+    };
+
+    let result = connection.drive(&mut incoming, slot.unchoked, settings.listen_port).await;
+
+    torrent.peer_gone(remote, &connection.remote_have).await;
+    drop(connection);
+    reader_task.abort();
+    let _ = writer_task.await;
+    result
+}
+
+impl Connection {
+    async fn drive(
+        &mut self,
+        incoming: &mut mpsc::Receiver<Message>,
+        mut unchoked: watch::Receiver<bool>,
+        listen_port: u16,
+    ) -> io::Result<()> {
+        if self.extensions_enabled {
+            self.send_extended_handshake(listen_port).await?;
+        }
+        let have = self.torrent.bitfield().await;
+        if have.count() > 0 {
+            self.send(Message::Bitfield(have.as_bytes().to_vec())).await?;
+        }
+
+        let mut have_events = self.torrent.subscribe_have();
+        let mut lost_events = self.torrent.subscribe_lost();
+        let mut block_events = self.torrent.subscribe_blocks();
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        keepalive.tick().await;
+        let mut pex_timer = tokio::time::interval(PEX_INTERVAL);
+        pex_timer.tick().await;
+        let torrent = Arc::clone(&self.torrent);
+
+        loop {
+            tokio::select! {
+                message = incoming.recv() => match message {
+                    Some(message) => self.handle(message).await?,
+                    None => return Ok(()),
+                },
+                event = have_events.recv() => match event {
+                    Ok(index) => {
+                        self.send(Message::Have(index)).await?;
+                        self.update_interest().await?;
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "Avisos de peças perdidos, reenviando os que faltam ao peer");
+                        self.resend_haves().await?;
+                        self.update_interest().await?;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                event = lost_events.recv() => match event {
+                    Ok(_) | Err(RecvError::Lagged(_)) => self.update_interest().await?,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                Ok(block) = block_events.recv() => {
+                    // Endgame: o bloco chegou por outra conexão
+                    if self.pending.remove(&block) {
+                        self.send(Message::Cancel { index: block.index, begin: block.begin, length: block.length })
+                            .await?;
+                        self.request_blocks().await?;
+                    }
+                }
+                Ok(()) = unchoked.changed() => {
+                    let choking = !*unchoked.borrow_and_update();
+                    if choking != self.am_choking {
+                        debug!(choking, "Choker mudou o estado do peer");
+                        self.am_choking = choking;
+                        // Com o choke, o peer considera descartados os pedidos anteriores
+                        if choking {
+                            self.uploads.lock().unwrap().clear();
+                        }
+                        self.send(if choking { Message::Choke } else { Message::Unchoke }).await?;
+                    }
+                }
+                _ = keepalive.tick() => self.send(Message::KeepAlive).await?,
+                _ = pex_timer.tick() => self.send_pex().await?,
+                _ = torrent.stopped() => return Ok(()),
+            }
+
+            if self.remote_have.is_complete() && self.torrent.is_complete().await {
+                debug!("Peer e nós temos o torrent completo, encerrando conexão");
+                return Ok(());
+            }
+        }
+    }
+
+    /// Envia `have` de todas as peças que temos e o peer não, para quando
+    /// avisos de peças concluídas se perderam no canal.
+    async fn resend_haves(&self) -> io::Result<()> {
+        let have = self.torrent.bitfield().await;
+        for index in have.iter().filter(|&index| !self.remote_have.has(index)) {
+            self.send(Message::Have(index as u32)).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, message: Message) -> io::Result<()> {
+        self.out
+            .send(message)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "conexão encerrada"))
+    }
+
+    async fn handle(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            Message::KeepAlive | Message::Unknown { .. } => {}
+            Message::Choke => {
+                // O peer descarta os pedidos pendentes; serão refeitos no unchoke
+                debug!(pending = self.pending.len(), "Peer nos deu choke");
+                self.peer_choking = true;
+                self.pending.clear();
+                self.torrent.cancel_requests(self.remote).await;
+            }
+            Message::Unchoke => {
+                debug!("Peer nos deu unchoke");
+                self.peer_choking = false;
+                self.request_blocks().await?;
+            }
+            // Quem recebe unchoke é decidido pelo choker; só o avisamos da mudança
+            Message::Interested => {
+                self.counters.interested.store(true, Ordering::Relaxed);
+                self.torrent.request_rechoke();
+            }
+            Message::NotInterested => {
+                self.counters.interested.store(false, Ordering::Relaxed);
+                self.torrent.request_rechoke();
+            }
+            Message::Have(index) => {
+                if index as usize >= self.remote_have.len() {
+                    return Err(invalid("have com índice inválido"));
+                }
+                if !self.remote_have.has(index as usize) {
+                    self.remote_have.set(index as usize);
+                    self.torrent.peer_have(index as usize).await;
+                }
+                self.update_interest().await?;
+            }
+            Message::Bitfield(bytes) => {
+                let remote_have = Bitfield::from_bytes(&bytes, self.torrent.piece_count())
+                    .ok_or_else(|| invalid("bitfield com tamanho inválido"))?;
+                self.torrent.peer_bitfield(&self.remote_have, &remote_have).await;
+                self.remote_have = remote_have;
+                self.update_interest().await?;
+            }
+            Message::Request { index, begin, length } => self.serve(index, begin, length).await?,
+            Message::Piece { index, begin, block } => self.receive(index, begin, block).await?,
+            Message::Cancel { index, begin, length } => {
+                self.uploads.lock().unwrap().remove(&Block { index, begin, length });
+            }
+            Message::Extended { id, payload } => self.handle_extended(id, &payload).await?,
+        }
+        Ok(())
+    }
+
+    async fn send_extended_handshake(&self, listen_port: u16) -> io::Result<()> {
+        let mut handshake = ExtendedHandshake {
+            port: Some(listen_port),
+            client: Some(extension::CLIENT_NAME.to_string()),
+            metadata_size: Some(self.torrent.metainfo.info_bytes().len()),
+            ..Default::default()
+        };
+        handshake.extensions.insert(extension::UT_METADATA.to_string(), extension::UT_METADATA_ID);
+        if !self.torrent.is_private() {
+            handshake.extensions.insert(extension::UT_PEX.to_string(), extension::UT_PEX_ID);
+        }
+        self.send(Message::Extended { id: extension::HANDSHAKE_ID, payload: handshake.encode() }).await
+    }
+
+    async fn handle_extended(&mut self, id: u8, payload: &[u8]) -> io::Result<()> {
+        if !self.extensions_enabled {
+            return Err(invalid("mensagem estendida sem suporte anunciado no handshake"));
+        }
+        match id {
+            extension::HANDSHAKE_ID => {
+                let handshake = ExtendedHandshake::decode(payload)?;
+                if let Some(client) = &handshake.client {
+                    tracing::Span::current().record("client", client.as_str());
+                }
+                if let Some(port) = handshake.port {
+                    self.torrent.set_listen_addr(&self.remote, SocketAddr::new(self.remote.ip(), port)).await;
+                }
+                let first = self.extensions.is_none();
+                self.extensions = Some(handshake);
+                // A primeira mensagem ut_pex vai logo após o handshake; as próximas, a cada minuto
+                if first {
+                    self.send_pex().await?;
+                }
+            }
+            extension::UT_PEX_ID if !self.torrent.is_private() => {
+                let pex = Pex::decode(payload)?;
+                self.torrent.add_pex_peers(pex.added).await;
+            }
+            extension::UT_METADATA_ID => {
+                if let MetadataMessage::Request { piece } = MetadataMessage::decode(payload)? {
+                    self.serve_metadata(piece).await?;
+                }
+            }
+            // Extensão que não anunciamos; ignora
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Responde a um pedido de pedaço do dicionário `info` (BEP 9).
+    async fn serve_metadata(&self, piece: usize) -> io::Result<()> {
+        let Some(id) = self.extensions.as_ref().and_then(|ext| ext.extension_id(extension::UT_METADATA)) else {
+            return Ok(());
+        };
+        let info = self.torrent.metainfo.info_bytes();
+        let start = piece.saturating_mul(extension::METADATA_PIECE_LEN);
+        let response = if start < info.len() {
+            let end = (start + extension::METADATA_PIECE_LEN).min(info.len());
+            MetadataMessage::Data { piece, total_size: info.len(), data: info[start..end].to_vec() }
+        } else {
+            MetadataMessage::Reject { piece }
+        };
+        self.send(Message::Extended { id, payload: response.encode() }).await
+    }
+
+    /// Envia ao peer as mudanças na lista de peers conectados desde a última mensagem.
+    async fn send_pex(&mut self) -> io::Result<()> {
+        let Some(id) = self.extensions.as_ref().and_then(|ext| ext.extension_id(extension::UT_PEX)) else {
+            return Ok(());
+        };
+        if self.torrent.is_private() {
+            return Ok(());
+        }
+        let own = self.extensions.as_ref().and_then(|ext| ext.port).map(|port| SocketAddr::new(self.remote.ip(), port));
+        let current: HashSet<SocketAddr> = self
+            .torrent
+            .listen_addrs()
+            .await
+            .into_iter()
+            .filter(|addr| *addr != self.remote && Some(*addr) != own)
+            .collect();
+
+        let pex = Pex {
+            added: current.difference(&self.pex_sent).copied().take(extension::MAX_PEX_PEERS).collect(),
+            dropped: self.pex_sent.difference(&current).copied().take(extension::MAX_PEX_PEERS).collect(),
+        };
+        if pex.is_empty() {
+            return Ok(());
+        }
+        self.pex_sent.extend(&pex.added);
+        for addr in &pex.dropped {
+            self.pex_sent.remove(addr);
+        }
+        self.send(Message::Extended { id, payload: pex.encode() }).await
+    }
+
+    /// Declara interesse se o peer tem alguma peça que ainda nos falta.
+    async fn update_interest(&mut self) -> io::Result<()> {
+        let have = self.torrent.bitfield().await;
+        let interested = have.lacks_any_of(&self.remote_have);
+        if interested != self.am_interested {
+            self.am_interested = interested;
+            let message = if interested { Message::Interested } else { Message::NotInterested };
+            self.send(message).await?;
+        }
+        if interested && !self.peer_choking {
+            self.request_blocks().await?;
+        }
+        Ok(())
+    }
+
+    /// Completa a fila de pedidos pendentes com blocos escolhidos pelo picker.
+    async fn request_blocks(&mut self) -> io::Result<()> {
+        if self.peer_choking || !self.am_interested {
+            return Ok(());
+        }
+        let room = MAX_PENDING_REQUESTS.saturating_sub(self.pending.len());
+        let blocks = self.torrent.pick_blocks(self.remote, &self.remote_have, room).await;
+        for block in blocks {
+            trace!(piece = block.index, begin = block.begin, length = block.length, "Bloco pedido");
+            self.pending.insert(block);
+            self.send(Message::Request { index: block.index, begin: block.begin, length: block.length })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn serve(&mut self, index: u32, begin: u32, length: u32) -> io::Result<()> {
+        if self.am_choking || length > MAX_REQUEST_LEN || !self.torrent.has_piece(index as usize).await {
+            return Ok(());
+        }
+        let block = self.torrent.read_block(index as usize, begin, length).await?;
+        // Os contadores de envio só sobem quando a tarefa de escrita manda o bloco
+        self.uploads.lock().unwrap().insert(Block { index, begin, length: block.len() as u32 });
+        self.send(Message::Piece { index, begin, block }).await
+    }
+
+    async fn receive(&mut self, index: u32, begin: u32, data: Vec<u8>) -> io::Result<()> {
+        let block = Block { index, begin, length: data.len() as u32 };
+        self.counters.downloaded.fetch_add(data.len() as u64, Ordering::Relaxed);
+        if !self.pending.remove(&block) {
+            // Bloco que não pedimos, ou já cancelado; apenas ignora
+            trace!(piece = index, begin, "Bloco não pedido ignorado");
+            return Ok(());
+        }
+        trace!(piece = index, begin, length = block.length, "Bloco recebido");
+        match self.torrent.receive_block(self.remote, block, &data).await? {
+            BlockResult::PieceVerified(index) => debug!(piece = index, "Peça recebida"),
+            BlockResult::PieceFailed(index) => warn!(piece = index, "Peça com hash inválido, descartada"),
+            BlockResult::Ignored | BlockResult::Stored => {}
+        }
+        self.request_blocks().await
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
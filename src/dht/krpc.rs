@@ -0,0 +1,305 @@
+﻿//! Mensagens KRPC: dicionários bencode trocados em datagramas UDP (BEP 5).
+
+use super::routing::NodeId;
+use crate::announce::parse_compact_peers;
+use crate::bencode::{self, Dict, Value};
+use crate::metainfo::InfoHash;
+use std::net::{IpAddr, SocketAddr};
+
+/// Códigos de erro definidos pelo BEP 5.
+pub const ERROR_GENERIC: i64 = 201;
+pub const ERROR_PROTOCOL: i64 = 203;
+pub const ERROR_METHOD_UNKNOWN: i64 = 204;
+
+/// Tamanho de um nó no formato compacto: id, IPv4 e porta.
+const COMPACT_NODE_LEN: usize = 26;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Ping,
+    FindNode { target: NodeId },
+    GetPeers { info_hash: InfoHash },
+    AnnouncePeer { info_hash: InfoHash, port: u16, implied_port: bool, token: Vec<u8> },
+}
+
+impl Query {
+    fn method(&self) -> &'static str {
+        match self {
+            Query::Ping => "ping",
+            Query::FindNode { .. } => "find_node",
+            Query::GetPeers { .. } => "get_peers",
+            Query::AnnouncePeer { .. } => "announce_peer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    pub id: NodeId,
+    pub nodes: Vec<(NodeId, SocketAddr)>,
+    /// Peers do torrent consultado em `get_peers`.
+    pub values: Vec<SocketAddr>,
+    pub token: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Query { id: NodeId, query: Query },
+    Response(Response),
+    Error { code: i64, message: String },
+}
+
+/// Datagrama inválido. Com o transaction id legível, o erro pode ser respondido.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub transaction: Option<Vec<u8>>,
+    pub code: i64,
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub transaction: Vec<u8>,
+    pub body: Body,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut dict = Dict::new();
+        dict.insert(b"t".to_vec(), Value::Bytes(self.transaction.clone()));
+        match &self.body {
+            Body::Query { id, query } => {
+                let mut args = Dict::new();
+                args.insert(b"id".to_vec(), Value::from(&id.0[..]));
+                match query {
+                    Query::Ping => {}
+                    Query::FindNode { target } => {
+                        args.insert(b"target".to_vec(), Value::from(&target.0[..]));
+                    }
+                    Query::GetPeers { info_hash } => {
+                        args.insert(b"info_hash".to_vec(), Value::from(&info_hash.as_bytes()[..]));
+                    }
+                    Query::AnnouncePeer { info_hash, port, implied_port, token } => {
+                        args.insert(b"info_hash".to_vec(), Value::from(&info_hash.as_bytes()[..]));
+                        args.insert(b"port".to_vec(), Value::Integer(*port as i64));
+                        args.insert(b"implied_port".to_vec(), Value::Integer(*implied_port as i64));
+                        args.insert(b"token".to_vec(), Value::Bytes(token.clone()));
+                    }
+                }
+                dict.insert(b"y".to_vec(), Value::from("q"));
+                dict.insert(b"q".to_vec(), Value::from(query.method()));
+                dict.insert(b"a".to_vec(), Value::Dict(args));
+            }
+            Body::Response(response) => {
+                let mut values = Dict::new();
+                values.insert(b"id".to_vec(), Value::from(&response.id.0[..]));
+                if !response.nodes.is_empty() {
+                    values.insert(b"nodes".to_vec(), Value::Bytes(encode_nodes(&response.nodes)));
+                }
+                if !response.values.is_empty() {
+                    let peers = response.values.iter().filter_map(encode_peer).map(Value::Bytes).collect();
+                    values.insert(b"values".to_vec(), Value::List(peers));
+                }
+                if let Some(token) = &response.token {
+                    values.insert(b"token".to_vec(), Value::Bytes(token.clone()));
+                }
+                dict.insert(b"y".to_vec(), Value::from("r"));
+                dict.insert(b"r".to_vec(), Value::Dict(values));
+            }
+            Body::Error { code, message } => {
+                dict.insert(b"y".to_vec(), Value::from("e"));
+                dict.insert(
+                    b"e".to_vec(),
+                    Value::List(vec![Value::Integer(*code), Value::from(message.as_str())]),
+                );
+            }
+        }
+        bencode::encode(&Value::Dict(dict))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let anonymous = |reason| DecodeError { transaction: None, code: ERROR_PROTOCOL, reason };
+        let value = bencode::decode(bytes).map_err(|_| anonymous("bencode inválido"))?;
+        let transaction = value
+            .get("t")
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| anonymous("mensagem sem transaction id"))?
+            .to_vec();
+        let fail = |reason| DecodeError { transaction: Some(transaction.clone()), code: ERROR_PROTOCOL, reason };
+
+        let body = match value.get("y").and_then(Value::as_str) {
+            Some("q") => {
+                let args = value.get("a").ok_or_else(|| fail("consulta sem argumentos"))?;
+                let id = node_id(args, "id").ok_or_else(|| fail("consulta sem id"))?;
+                let info_hash = || {
+                    args.get("info_hash")
+                        .and_then(Value::as_bytes)
+                        .and_then(InfoHash::from_bytes)
+                        .ok_or_else(|| fail("info_hash inválido"))
+                };
+                let query = match value.get("q").and_then(Value::as_str) {
+                    Some("ping") => Query::Ping,
+                    Some("find_node") => Query::FindNode {
+                        target: node_id(args, "target").ok_or_else(|| fail("target inválido"))?,
+                    },
+                    Some("get_peers") => Query::GetPeers { info_hash: info_hash()? },
+                    Some("announce_peer") => Query::AnnouncePeer {
+                        info_hash: info_hash()?,
+                        port: args
+                            .get("port")
+                            .and_then(Value::as_int)
+                            .and_then(|port| u16::try_from(port).ok())
+                            .ok_or_else(|| fail("porta inválida"))?,
+                        implied_port: args.get("implied_port").and_then(Value::as_int).unwrap_or(0) != 0,
+                        token: args
+                            .get("token")
+                            .and_then(Value::as_bytes)
+                            .ok_or_else(|| fail("announce sem token"))?
+                            .to_vec(),
+                    },
+                    _ => {
+                        return Err(DecodeError {
+                            code: ERROR_METHOD_UNKNOWN,
+                            ..fail("método desconhecido")
+                        })
+                    }
+                };
+                Body::Query { id, query }
+            }
+            Some("r") => {
+                let values = value.get("r").ok_or_else(|| fail("resposta sem corpo"))?;
+                Body::Response(Response {
+                    id: node_id(values, "id").ok_or_else(|| fail("resposta sem id"))?,
+                    nodes: values.get("nodes").and_then(Value::as_bytes).map(decode_nodes).unwrap_or_default(),
+                    values: values
+                        .get("values")
+                        .and_then(Value::as_list)
+                        .map(|list| {
+                            list.iter()
+                                .filter_map(Value::as_bytes)
+                                .flat_map(|peer| parse_compact_peers(peer, 4))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    token: values.get("token").and_then(Value::as_bytes).map(<[u8]>::to_vec),
+                })
+            }
+            Some("e") => {
+                let error = value.get("e").and_then(Value::as_list).ok_or_else(|| fail("erro malformado"))?;
+                Body::Error {
+                    code: error.first().and_then(Value::as_int).unwrap_or(ERROR_GENERIC),
+                    message: error.get(1).and_then(Value::as_str).unwrap_or_default().to_string(),
+                }
+            }
+            _ => return Err(fail("tipo de mensagem desconhecido")),
+        };
+        Ok(Message { transaction, body })
+    }
+}
+
+fn node_id(dict: &Value, key: &str) -> Option<NodeId> {
+    dict.get(key).and_then(Value::as_bytes).and_then(NodeId::from_bytes)
+}
+
+/// Nós em formato compacto; apenas IPv4, como no BEP 5.
+pub fn encode_nodes(nodes: &[(NodeId, SocketAddr)]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nodes.len() * COMPACT_NODE_LEN);
+    for (id, addr) in nodes {
+        if let Some(peer) = encode_peer(addr) {
+            out.extend_from_slice(&id.0);
+            out.extend_from_slice(&peer);
+        }
+    }
+    out
+}
+
+pub fn decode_nodes(bytes: &[u8]) -> Vec<(NodeId, SocketAddr)> {
+    bytes
+        .chunks_exact(COMPACT_NODE_LEN)
+        .filter_map(|chunk| {
+            let id = NodeId::from_bytes(&chunk[..20])?;
+            let addr = parse_compact_peers(&chunk[20..], 4).pop()?;
+            Some((id, addr))
+        })
+        .collect()
+}
+
+fn encode_peer(addr: &SocketAddr) -> Option<Vec<u8>> {
+    let IpAddr::V4(ip) = addr.ip() else {
+        return None;
+    };
+    let mut out = ip.octets().to_vec();
+    out.extend_from_slice(&addr.port().to_be_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(body: Body) {
+        let message = Message { transaction: b"aa".to_vec(), body };
+        assert_eq!(Message::decode(&message.encode()), Ok(message));
+    }
+
+    #[test]
+    fn queries_round_trip() {
+        let id = NodeId([1; 20]);
+        let info_hash = InfoHash([2; 20]);
+        round_trip(Body::Query { id, query: Query::Ping });
+        round_trip(Body::Query { id, query: Query::FindNode { target: NodeId([3; 20]) } });
+        round_trip(Body::Query { id, query: Query::GetPeers { info_hash } });
+        round_trip(Body::Query {
+            id,
+            query: Query::AnnouncePeer { info_hash, port: 51413, implied_port: true, token: b"tok".to_vec() },
+        });
+    }
+
+    #[test]
+    fn responses_and_errors_round_trip() {
+        let peer = SocketAddr::from(([10, 0, 0, 1], 6881));
+        round_trip(Body::Response(Response { id: NodeId([1; 20]), ..Default::default() }));
+        round_trip(Body::Response(Response {
+            id: NodeId([1; 20]),
+            nodes: vec![(NodeId([4; 20]), peer), (NodeId([5; 20]), SocketAddr::from(([127, 0, 0, 1], 1)))],
+            values: vec![peer, SocketAddr::from(([192, 168, 1, 2], 80))],
+            token: Some(b"abcdefgh".to_vec()),
+        }));
+        round_trip(Body::Error { code: ERROR_GENERIC, message: "falhou".to_string() });
+    }
+
+    #[test]
+    fn decodes_bep5_example() {
+        let ping = Message::decode(b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe").unwrap();
+        assert_eq!(ping.transaction, b"aa");
+        assert_eq!(ping.body, Body::Query { id: NodeId(*b"abcdefghij0123456789"), query: Query::Ping });
+        // A codificação é canônica: as chaves saem ordenadas
+        assert_eq!(ping.encode(), b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe");
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let error = Message::decode(b"garbage").unwrap_err();
+        assert_eq!(error.transaction, None);
+        assert_eq!(Message::decode(b"d1:y1:qe").unwrap_err().transaction, None);
+
+        let error = Message::decode(b"d1:ad2:id20:abcdefghij0123456789e1:q3:foo1:t2:aa1:y1:qe").unwrap_err();
+        assert_eq!(error.transaction, Some(b"aa".to_vec()));
+        assert_eq!(error.code, ERROR_METHOD_UNKNOWN);
+
+        let error = Message::decode(b"d1:ad2:id3:abce1:q4:ping1:t2:aa1:y1:qe").unwrap_err();
+        assert_eq!((error.code, error.reason), (ERROR_PROTOCOL, "consulta sem id"));
+        let error = Message::decode(b"d1:t2:aa1:y1:xe").unwrap_err();
+        assert_eq!(error.reason, "tipo de mensagem desconhecido");
+    }
+
+    #[test]
+    fn compact_nodes_skip_ipv6_and_partial_entries() {
+        let v4 = (NodeId([1; 20]), SocketAddr::from(([1, 2, 3, 4], 5)));
+        let v6 = (NodeId([2; 20]), "[::1]:6881".parse().unwrap());
+        let mut bytes = encode_nodes(&[v4, v6]);
+        assert_eq!(bytes.len(), COMPACT_NODE_LEN);
+        bytes.extend_from_slice(&[0; 10]);
+        assert_eq!(decode_nodes(&bytes), vec![v4]);
+    }
+}
@@ -0,0 +1,504 @@
+﻿//! DHT mainline (BEP 5): nó Kademlia que descobre peers pelo info-hash sem
+//! depender de um tracker.
+//!
+//! O nó responde a `ping`, `find_node`, `get_peers` e `announce_peer`, mantém
+//! uma tabela de roteamento de k-buckets e guarda os peers anunciados nele.
+//! Os nós de bootstrap são configuráveis, o que permite montar uma rede de
+//! teste inteira em loopback.
+
+mod krpc;
+mod routing;
+
+pub use krpc::{Body, DecodeError, Message, Query, Response};
+pub use routing::{Node, NodeId, RoutingTable, K};
+
+use crate::metainfo::InfoHash;
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// Roteadores públicos usados quando nenhum bootstrap é configurado.
+pub const DEFAULT_BOOTSTRAP: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// Consultas simultâneas em cada rodada de uma busca iterativa.
+const ALPHA: usize = 3;
+
+/// Os tokens de `get_peers` valem por duas rotações do segredo.
+const TOKEN_ROTATION: Duration = Duration::from_secs(5 * 60);
+
+/// Tempo que um peer anunciado fica guardado sem anunciar de novo.
+const PEER_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Intervalo entre buscas de manutenção da tabela de roteamento.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Máximo de peers devolvidos numa resposta de `get_peers`.
+const MAX_VALUES: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct DhtConfig {
+    /// Endereço UDP local do nó.
+    pub bind: SocketAddr,
+    /// Nós (`host:porta`) usados para entrar na rede.
+    pub bootstrap: Vec<String>,
+    /// Tempo de espera por cada resposta.
+    pub query_timeout: Duration,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 6881)),
+            bootstrap: DEFAULT_BOOTSTRAP.iter().map(|node| node.to_string()).collect(),
+            query_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DhtError {
+    Io(io::Error),
+    Timeout,
+    /// O nó remoto respondeu com um erro KRPC.
+    Remote { code: i64, message: String },
+}
+
+impl fmt::Display for DhtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DhtError::Io(e) => write!(f, "erro de rede: {}", e),
+            DhtError::Timeout => write!(f, "nó não respondeu"),
+            DhtError::Remote { code, message } => write!(f, "erro {} do nó remoto: {}", code, message),
+        }
+    }
+}
+
+impl std::error::Error for DhtError {}
+
+impl From<io::Error> for DhtError {
+    fn from(e: io::Error) -> Self {
+        DhtError::Io(e)
+    }
+}
+
+type PendingQuery = (SocketAddr, oneshot::Sender<Result<Response, DhtError>>);
+
+struct Inner {
+    id: NodeId,
+    socket: UdpSocket,
+    config: DhtConfig,
+    table: Mutex<RoutingTable>,
+    pending: Mutex<HashMap<Vec<u8>, PendingQuery>>,
+    /// Peers anunciados neste nó, por torrent.
+    announced: Mutex<HashMap<InfoHash, HashMap<SocketAddr, Instant>>>,
+    /// Segredo atual e anterior usados para gerar tokens.
+    secrets: Mutex<[[u8; 16]; 2]>,
+    next_transaction: AtomicU16,
+}
+
+/// Resultado de uma busca iterativa.
+struct Lookup {
+    peers: Vec<SocketAddr>,
+    /// Nós mais próximos que responderam, com o token recebido.
+    closest: Vec<(NodeId, SocketAddr, Option<Vec<u8>>)>,
+}
+
+#[derive(Clone)]
+pub struct Dht {
+    inner: Arc<Inner>,
+}
+
+impl Dht {
+    /// Abre o socket do nó e inicia as tarefas de recepção e manutenção.
+    pub async fn bind(config: DhtConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind(config.bind).await?;
+        let id = NodeId::random();
+        let dht = Self {
+            inner: Arc::new(Inner {
+                id,
+                socket,
+                config,
+                table: Mutex::new(RoutingTable::new(id)),
+                pending: Mutex::new(HashMap::new()),
+                announced: Mutex::new(HashMap::new()),
+                secrets: Mutex::new([rand::random(), rand::random()]),
+                next_transaction: AtomicU16::new(rand::random()),
+            }),
+        };
+        info!(addr = %dht.local_addr()?, %id, "DHT rodando");
+
+        let receiver = dht.clone();
+        tokio::spawn(async move { receiver.receive_loop().await });
+        let maintainer = dht.clone();
+        tokio::spawn(async move { maintainer.maintenance_loop().await });
+        Ok(dht)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.inner.id
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.socket.local_addr()
+    }
+
+    pub async fn node_count(&self) -> usize {
+        self.inner.table.lock().await.len()
+    }
+
+    /// Entra na rede: contata os nós de bootstrap e busca o próprio id para
+    /// preencher a tabela. Retorna quantos nós ficaram conhecidos.
+    pub async fn bootstrap(&self) -> usize {
+        let mut tasks = JoinSet::new();
+        for node in &self.inner.config.bootstrap {
+            let Ok(addrs) = tokio::net::lookup_host(node.as_str()).await else {
+                warn!(%node, "Nó de bootstrap não encontrado");
+                continue;
+            };
+            for addr in addrs.filter(SocketAddr::is_ipv4) {
+                let dht = self.clone();
+                tasks.spawn(async move { dht.query(addr, Query::Ping).await });
+            }
+        }
+        while tasks.join_next().await.is_some() {}
+
+        self.lookup(self.id(), None).await;
+        self.node_count().await
+    }
+
+    pub async fn ping(&self, addr: SocketAddr) -> Result<NodeId, DhtError> {
+        self.query(addr, Query::Ping).await.map(|response| response.id)
+    }
+
+    /// Busca peers do torrent nos nós mais próximos do info-hash.
+    pub async fn get_peers(&self, info_hash: InfoHash) -> Vec<SocketAddr> {
+        self.lookup(NodeId::from(info_hash), Some(info_hash)).await.peers
+    }
+
+    /// Busca peers do torrent e anuncia que estamos nele, na porta TCP `port`,
+    /// aos nós mais próximos do info-hash.
+    pub async fn announce(&self, info_hash: InfoHash, port: u16) -> Vec<SocketAddr> {
+        let lookup = self.lookup(NodeId::from(info_hash), Some(info_hash)).await;
+        let mut tasks = JoinSet::new();
+        for (_, addr, token) in lookup.closest {
+            let Some(token) = token else {
+                continue;
+            };
+            let dht = self.clone();
+            let query = Query::AnnouncePeer { info_hash, port, implied_port: false, token };
+            tasks.spawn(async move { dht.query(addr, query).await });
+        }
+        while tasks.join_next().await.is_some() {}
+        lookup.peers
+    }
+
+    /// Busca iterativa: consulta os nós conhecidos mais próximos de `target`,
+    /// `ALPHA` por vez, até que os `K` mais próximos já tenham respondido ou falhado.
+    async fn lookup(&self, target: NodeId, info_hash: Option<InfoHash>) -> Lookup {
+        let own_addr = self.local_addr().ok();
+        let mut candidates: Vec<(NodeId, SocketAddr)> = self
+            .inner
+            .table
+            .lock()
+            .await
+            .closest(&target, K)
+            .into_iter()
+            .map(|node| (node.id, node.addr))
+            .collect();
+        let mut queried = HashSet::new();
+        let mut closest = Vec::new();
+        let mut peers = HashSet::new();
+
+        loop {
+            candidates.sort_by_key(|(id, _)| id.distance(&target));
+            candidates.dedup_by_key(|(id, _)| *id);
+            let batch: Vec<(NodeId, SocketAddr)> = candidates
+                .iter()
+                .take(K)
+                .filter(|(_, addr)| !queried.contains(addr))
+                .take(ALPHA)
+                .copied()
+                .collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            let mut tasks = JoinSet::new();
+            for (_, addr) in batch {
+                queried.insert(addr);
+                let dht = self.clone();
+                let query = match info_hash {
+                    Some(info_hash) => Query::GetPeers { info_hash },
+                    None => Query::FindNode { target },
+                };
+                tasks.spawn(async move { (addr, dht.query(addr, query).await) });
+            }
+            while let Some(Ok((addr, result))) = tasks.join_next().await {
+                match result {
+                    Ok(response) => {
+                        closest.push((response.id, addr, response.token));
+                        peers.extend(response.values);
+                        candidates.extend(
+                            response
+                                .nodes
+                                .into_iter()
+                                .filter(|(id, addr)| *id != self.id() && Some(*addr) != own_addr),
+                        );
+                    }
+                    Err(_) => candidates.retain(|(_, candidate)| *candidate != addr),
+                }
+            }
+        }
+
+        closest.sort_by_key(|(id, _, _)| id.distance(&target));
+        closest.truncate(K);
+        Lookup { peers: peers.into_iter().collect(), closest }
+    }
+
+    /// Envia uma consulta e espera a resposta com o mesmo transaction id.
+    async fn query(&self, addr: SocketAddr, query: Query) -> Result<Response, DhtError> {
+        let transaction = self.inner.next_transaction.fetch_add(1, Ordering::Relaxed).to_be_bytes().to_vec();
+        let (sender, receiver) = oneshot::channel();
+        self.inner.pending.lock().await.insert(transaction.clone(), (addr, sender));
+
+        let message = Message { transaction: transaction.clone(), body: Body::Query { id: self.id(), query } };
+        if let Err(e) = self.inner.socket.send_to(&message.encode(), addr).await {
+            self.inner.pending.lock().await.remove(&transaction);
+            return Err(e.into());
+        }
+
+        let result = match tokio::time::timeout(self.inner.config.query_timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            _ => {
+                self.inner.pending.lock().await.remove(&transaction);
+                Err(DhtError::Timeout)
+            }
+        };
+        match &result {
+            Ok(response) => {
+                self.inner.table.lock().await.insert(response.id, addr);
+            }
+            Err(DhtError::Timeout) => self.inner.table.lock().await.mark_failed(&addr),
+            Err(_) => {}
+        }
+        result
+    }
+
+    async fn receive_loop(&self) {
+        let mut buffer = vec![0u8; 65536];
+        loop {
+            let (n, from) = match self.inner.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                // Em alguns sistemas um ICMP de porta fechada aparece como erro no recv
+                Err(_) => continue,
+            };
+            match Message::decode(&buffer[..n]) {
+                Ok(message) => self.handle(message, from).await,
+                Err(error) => {
+                    if let Some(transaction) = error.transaction {
+                        self.send_error(transaction, error.code, error.reason, from).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, message: Message, from: SocketAddr) {
+        match message.body {
+            Body::Query { id, query } => {
+                self.inner.table.lock().await.insert(id, from);
+                let body = match self.answer(query, from).await {
+                    Ok(response) => Body::Response(response),
+                    Err((code, reason)) => Body::Error { code, message: reason.to_string() },
+                };
+                let reply = Message { transaction: message.transaction, body };
+                let _ = self.inner.socket.send_to(&reply.encode(), from).await;
+            }
+            Body::Response(response) => {
+                if let Some(sender) = self.take_pending(&message.transaction, from).await {
+                    let _ = sender.send(Ok(response));
+                }
+            }
+            Body::Error { code, message: text } => {
+                if let Some(sender) = self.take_pending(&message.transaction, from).await {
+                    let _ = sender.send(Err(DhtError::Remote { code, message: text }));
+                }
+            }
+        }
+    }
+
+    /// Retira a consulta pendente, desde que a resposta venha do nó consultado.
+    async fn take_pending(
+        &self,
+        transaction: &[u8],
+        from: SocketAddr,
+    ) -> Option<oneshot::Sender<Result<Response, DhtError>>> {
+        let mut pending = self.inner.pending.lock().await;
+        match pending.get(transaction) {
+            Some((addr, _)) if *addr == from => pending.remove(transaction).map(|(_, sender)| sender),
+            _ => None,
+        }
+    }
+
+    async fn answer(&self, query: Query, from: SocketAddr) -> Result<Response, (i64, &'static str)> {
+        let mut response = Response { id: self.id(), ..Default::default() };
+        match query {
+            Query::Ping => {}
+            Query::FindNode { target } => response.nodes = self.closest_nodes(&target).await,
+            Query::GetPeers { info_hash } => {
+                response.token = Some(self.token(from.ip(), 0).await);
+                let announced = self.inner.announced.lock().await;
+                match announced.get(&info_hash).filter(|peers| !peers.is_empty()) {
+                    Some(peers) => response.values = peers.keys().take(MAX_VALUES).copied().collect(),
+                    None => {
+                        drop(announced);
+                        response.nodes = self.closest_nodes(&NodeId::from(info_hash)).await;
+                    }
+                }
+            }
+            Query::AnnouncePeer { info_hash, port, implied_port, token } => {
+                if token != self.token(from.ip(), 0).await && token != self.token(from.ip(), 1).await {
+                    return Err((krpc::ERROR_PROTOCOL, "token inválido"));
+                }
+                let port = if implied_port { from.port() } else { port };
+                self.inner
+                    .announced
+                    .lock()
+                    .await
+                    .entry(info_hash)
+                    .or_default()
+                    .insert(SocketAddr::new(from.ip(), port), Instant::now());
+            }
+        }
+        Ok(response)
+    }
+
+    async fn closest_nodes(&self, target: &NodeId) -> Vec<(NodeId, SocketAddr)> {
+        let table = self.inner.table.lock().await;
+        table.closest(target, K).into_iter().map(|node| (node.id, node.addr)).collect()
+    }
+
+    /// Token de `get_peers` para um IP: hash do IP com o segredo atual (0) ou anterior (1).
+    async fn token(&self, ip: IpAddr, secret: usize) -> Vec<u8> {
+        let secret = self.inner.secrets.lock().await[secret];
+        let mut hasher = Sha1::new();
+        hasher.update(secret);
+        match ip {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+        hasher.finalize()[..8].to_vec()
+    }
+
+    /// Troca o segredo atual por um novo; o atual passa a ser o anterior e
+    /// tokens gerados duas rotações atrás deixam de valer.
+    async fn rotate_secrets(&self) {
+        let mut secrets = self.inner.secrets.lock().await;
+        secrets[1] = secrets[0];
+        secrets[0] = rand::random();
+    }
+
+    async fn send_error(&self, transaction: Vec<u8>, code: i64, reason: &str, to: SocketAddr) {
+        let message = Message { transaction, body: Body::Error { code, message: reason.to_string() } };
+        let _ = self.inner.socket.send_to(&message.encode(), to).await;
+    }
+
+    /// Gira os segredos dos tokens, expira peers anunciados e renova a tabela.
+    async fn maintenance_loop(&self) {
+        let mut rotation = tokio::time::interval(TOKEN_ROTATION);
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        rotation.tick().await;
+        refresh.tick().await;
+        loop {
+            tokio::select! {
+                _ = rotation.tick() => {
+                    self.rotate_secrets().await;
+                    let mut announced = self.inner.announced.lock().await;
+                    for peers in announced.values_mut() {
+                        peers.retain(|_, seen| seen.elapsed() < PEER_TTL);
+                    }
+                    announced.retain(|_, peers| !peers.is_empty());
+                }
+                _ = refresh.tick() => {
+                    if self.inner.table.lock().await.is_empty() {
+                        self.bootstrap().await;
+                    } else {
+                        self.lookup(NodeId::random(), None).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn node(bootstrap: Vec<String>) -> Dht {
+        let config = DhtConfig {
+            bind: SocketAddr::from(([127, 0, 0, 1], 0)),
+            bootstrap,
+            query_timeout: Duration::from_millis(500),
+        };
+        Dht::bind(config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn announce_is_found_by_another_node() {
+        let first = node(Vec::new()).await;
+        let bootstrap = vec![first.local_addr().unwrap().to_string()];
+        let mut nodes = vec![first];
+        for _ in 0..4 {
+            let dht = node(bootstrap.clone()).await;
+            assert!(dht.bootstrap().await >= 1);
+            nodes.push(dht);
+        }
+
+        let info_hash = InfoHash([7; 20]);
+        assert!(nodes[4].get_peers(info_hash).await.is_empty());
+        nodes[1].announce(info_hash, 6881).await;
+        let peers = nodes[4].get_peers(info_hash).await;
+        assert_eq!(peers, vec![SocketAddr::from(([127, 0, 0, 1], 6881))]);
+    }
+
+    #[tokio::test]
+    async fn token_expires_after_two_rotations() {
+        let server = node(Vec::new()).await;
+        let client = node(Vec::new()).await;
+        let addr = server.local_addr().unwrap();
+        let info_hash = InfoHash([9; 20]);
+        let get_token = || async {
+            client.query(addr, Query::GetPeers { info_hash }).await.unwrap().token.unwrap()
+        };
+        let announce = |token| client.query(addr, Query::AnnouncePeer { info_hash, port: 6881, implied_port: false, token });
+
+        // Um token da rotação anterior ainda vale
+        let token = get_token().await;
+        server.rotate_secrets().await;
+        assert!(announce(token).await.is_ok());
+
+        let token = get_token().await;
+        server.rotate_secrets().await;
+        server.rotate_secrets().await;
+        match announce(token.clone()).await {
+            Err(DhtError::Remote { code, .. }) => assert_eq!(code, krpc::ERROR_PROTOCOL),
+            other => panic!("announce com token vencido aceito: {:?}", other.map(|_| ())),
+        }
+        assert!(announce(b"forjado".to_vec()).await.is_err());
+        assert!(announce(get_token().await).await.is_ok());
+    }
+}
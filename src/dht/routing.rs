@@ -0,0 +1,196 @@
+﻿//! Tabela de roteamento Kademlia: um bucket de até `K` nós para cada
+//! distância (número de bits iniciais em comum com o nosso id).
+
+use crate::metainfo::InfoHash;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Nós por bucket e tamanho das listas de nós nas respostas.
+pub const K: usize = 8;
+
+/// Consultas sem resposta seguidas até um nó ser considerado ruim.
+const MAX_FAILURES: u32 = 2;
+
+const ID_BITS: usize = 160;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub [u8; 20]);
+
+impl NodeId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Self)
+    }
+
+    /// Distância XOR; comparar distâncias é comparar os arrays.
+    pub fn distance(&self, other: &NodeId) -> [u8; 20] {
+        let mut distance = [0u8; 20];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        distance
+    }
+
+    /// Quantidade de bits iniciais em comum; `None` se os ids são iguais.
+    fn common_prefix(&self, other: &NodeId) -> Option<usize> {
+        let distance = self.distance(other);
+        let index = distance.iter().position(|&byte| byte != 0)?;
+        Some(index * 8 + distance[index].leading_zeros() as usize)
+    }
+}
+
+impl From<InfoHash> for NodeId {
+    fn from(info_hash: InfoHash) -> Self {
+        Self(*info_hash.as_bytes())
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({})", hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    pub last_seen: Instant,
+    failures: u32,
+}
+
+impl Node {
+    fn is_good(&self) -> bool {
+        self.failures < MAX_FAILURES
+    }
+}
+
+pub struct RoutingTable {
+    own_id: NodeId,
+    buckets: Vec<Vec<Node>>,
+}
+
+impl RoutingTable {
+    pub fn new(own_id: NodeId) -> Self {
+        Self { own_id, buckets: vec![Vec::new(); ID_BITS] }
+    }
+
+    /// Registra um nó que respondeu ou nos consultou.
+    ///
+    /// Com o bucket cheio, o nó só entra no lugar de um nó ruim; nós antigos
+    /// que continuam respondendo têm preferência, como recomenda o Kademlia.
+    pub fn insert(&mut self, id: NodeId, addr: SocketAddr) -> bool {
+        let Some(index) = self.own_id.common_prefix(&id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(node) = bucket.iter_mut().find(|node| node.id == id) {
+            node.addr = addr;
+            node.last_seen = Instant::now();
+            node.failures = 0;
+            return true;
+        }
+        let node = Node { id, addr, last_seen: Instant::now(), failures: 0 };
+        if bucket.len() < K {
+            bucket.push(node);
+            return true;
+        }
+        match bucket.iter().position(|node| !node.is_good()) {
+            Some(position) => {
+                bucket[position] = node;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Conta uma consulta sem resposta para o nó no endereço `addr`.
+    pub fn mark_failed(&mut self, addr: &SocketAddr) {
+        for node in self.buckets.iter_mut().flatten().filter(|node| node.addr == *addr) {
+            node.failures += 1;
+        }
+    }
+
+    /// Os `count` nós bons mais próximos de `target`.
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.buckets.iter().flatten().filter(|node| node.is_good()).cloned().collect();
+        nodes.sort_by_key(|node| node.id.distance(target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(first: u8) -> NodeId {
+        let mut bytes = [0; 20];
+        bytes[0] = first;
+        NodeId(bytes)
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn closest_orders_by_xor_distance() {
+        let mut table = RoutingTable::new(id(0));
+        for first in [0x80, 0x40, 0x20, 0x10, 0x11] {
+            assert!(table.insert(id(first), addr(first as u16)));
+        }
+        assert!(!table.insert(id(0), addr(1)), "o próprio id não entra na tabela");
+        let closest: Vec<NodeId> = table.closest(&id(0x12), 3).into_iter().map(|node| node.id).collect();
+        assert_eq!(closest, vec![id(0x10), id(0x11), id(0x20)]);
+    }
+
+    #[test]
+    fn full_bucket_only_replaces_bad_nodes() {
+        let mut table = RoutingTable::new(id(0));
+        // Todos com o primeiro bit 1: mesmo bucket
+        for n in 0..K as u8 {
+            assert!(table.insert(id(0x80 | n), addr(n as u16)));
+        }
+        assert!(!table.insert(id(0xff), addr(100)));
+        assert_eq!(table.len(), K);
+
+        for _ in 0..MAX_FAILURES {
+            table.mark_failed(&addr(3));
+        }
+        assert!(table.closest(&id(0x83), K).iter().all(|node| node.id != id(0x83)));
+        assert!(table.insert(id(0xff), addr(100)));
+        assert_eq!(table.len(), K);
+        assert!(table.closest(&id(0xff), 1).iter().any(|node| node.id == id(0xff)));
+    }
+
+    #[test]
+    fn reinsert_updates_address_and_clears_failures() {
+        let mut table = RoutingTable::new(id(0));
+        table.insert(id(0x80), addr(1));
+        for _ in 0..MAX_FAILURES {
+            table.mark_failed(&addr(1));
+        }
+        assert!(table.closest(&id(0x80), K).is_empty());
+        table.insert(id(0x80), addr(2));
+        assert_eq!(table.closest(&id(0x80), K)[0].addr, addr(2));
+    }
+}
@@ -0,0 +1,223 @@
+﻿//! Protocolo de extensões (BEP 10), troca de peers, ut_pex (BEP 11), e troca
+//! de metadados, ut_metadata (BEP 9).
+//!
+//! As extensões usam a mensagem 20 do protocolo de peers. O primeiro byte do
+//! corpo é 0 no handshake estendido; nas demais mensagens é o id que o
+//! destinatário escolheu para a extensão no handshake dele.
+
+use crate::announce::parse_compact_peers;
+use crate::bencode::{self, Dict, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+/// Id do handshake estendido dentro da mensagem 20.
+pub const HANDSHAKE_ID: u8 = 0;
+
+/// Identificação do cliente enviada no handshake estendido.
+pub const CLIENT_NAME: &str = concat!("bittorrent_client ", env!("CARGO_PKG_VERSION"));
+
+/// Nome da extensão de troca de peers.
+pub const UT_PEX: &str = "ut_pex";
+
+/// Id que usamos para receber mensagens ut_pex.
+pub const UT_PEX_ID: u8 = 1;
+
+/// Máximo de peers adicionados ou removidos numa mensagem ut_pex.
+pub const MAX_PEX_PEERS: usize = 50;
+
+/// Nome da extensão de troca de metadados.
+pub const UT_METADATA: &str = "ut_metadata";
+
+/// Id que usamos para receber mensagens ut_metadata.
+pub const UT_METADATA_ID: u8 = 2;
+
+/// Os metadados (o dicionário `info`) são trocados em pedaços de 16 KiB.
+pub const METADATA_PIECE_LEN: usize = 16 * 1024;
+
+/// Maior dicionário `info` que aceitamos baixar de um peer.
+pub const MAX_METADATA_SIZE: usize = 8 * 1024 * 1024;
+
+/// Flag de `added.f`: o peer aceita conexões de entrada.
+const PEX_CONNECTABLE: u8 = 0x10;
+
+/// Handshake estendido: as extensões suportadas e dados sobre o cliente.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtendedHandshake {
+    /// Nome da extensão e o id com que o remetente quer recebê-la.
+    pub extensions: BTreeMap<String, u8>,
+    /// Porta em que o remetente aceita conexões.
+    pub port: Option<u16>,
+    pub client: Option<String>,
+    /// Tamanho do dicionário `info`, para quem oferece ut_metadata.
+    pub metadata_size: Option<usize>,
+}
+
+impl ExtendedHandshake {
+    /// Id a usar ao enviar a extensão `name` a quem mandou este handshake.
+    pub fn extension_id(&self, name: &str) -> Option<u8> {
+        self.extensions.get(name).copied().filter(|&id| id != 0)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut m = Dict::new();
+        for (name, id) in &self.extensions {
+            m.insert(name.as_bytes().to_vec(), Value::Integer(*id as i64));
+        }
+        let mut dict = Dict::new();
+        dict.insert(b"m".to_vec(), Value::Dict(m));
+        if let Some(port) = self.port {
+            dict.insert(b"p".to_vec(), Value::Integer(port as i64));
+        }
+        if let Some(client) = &self.client {
+            dict.insert(b"v".to_vec(), Value::from(client.as_str()));
+        }
+        if let Some(size) = self.metadata_size {
+            dict.insert(b"metadata_size".to_vec(), Value::Integer(size as i64));
+        }
+        bencode::encode(&Value::Dict(dict))
+    }
+
+    pub fn decode(payload: &[u8]) -> io::Result<Self> {
+        let value = bencode::decode(payload).map_err(|e| invalid(&format!("handshake estendido inválido: {}", e)))?;
+        let m = value.get("m").and_then(Value::as_dict).ok_or_else(|| invalid("handshake estendido sem 'm'"))?;
+        // Ids fora de 0..=255 não podem ser usados; 0 desabilita a extensão
+        let extensions = m
+            .iter()
+            .filter_map(|(name, id)| {
+                let name = String::from_utf8(name.clone()).ok()?;
+                let id = u8::try_from(id.as_int()?).ok()?;
+                Some((name, id))
+            })
+            .collect();
+        Ok(Self {
+            extensions,
+            port: value.get("p").and_then(Value::as_int).and_then(|port| u16::try_from(port).ok()).filter(|&port| port != 0),
+            client: value.get("v").and_then(Value::as_str).map(str::to_string),
+            metadata_size: value
+                .get("metadata_size")
+                .and_then(Value::as_int)
+                .and_then(|size| usize::try_from(size).ok())
+                .filter(|&size| size > 0 && size <= MAX_METADATA_SIZE),
+        })
+    }
+}
+
+/// Mensagem ut_pex: peers conectados desde a última mensagem e os que saíram.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pex {
+    pub added: Vec<SocketAddr>,
+    pub dropped: Vec<SocketAddr>,
+}
+
+impl Pex {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.dropped.is_empty()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (added, added6) = split_compact(&self.added);
+        let (dropped, dropped6) = split_compact(&self.dropped);
+        // Só anunciamos peers que aceitam conexões, então todos levam a mesma flag
+        let flags = |count: usize| Value::Bytes(vec![PEX_CONNECTABLE; count]);
+
+        let mut dict = Dict::new();
+        dict.insert(b"added.f".to_vec(), flags(added.len() / 6));
+        dict.insert(b"added".to_vec(), Value::Bytes(added));
+        dict.insert(b"added6.f".to_vec(), flags(added6.len() / 18));
+        dict.insert(b"added6".to_vec(), Value::Bytes(added6));
+        dict.insert(b"dropped".to_vec(), Value::Bytes(dropped));
+        dict.insert(b"dropped6".to_vec(), Value::Bytes(dropped6));
+        bencode::encode(&Value::Dict(dict))
+    }
+
+    /// Interpreta uma mensagem ut_pex, limitando cada lista a [`MAX_PEX_PEERS`].
+    pub fn decode(payload: &[u8]) -> io::Result<Self> {
+        let value = bencode::decode(payload).map_err(|e| invalid(&format!("mensagem ut_pex inválida: {}", e)))?;
+        let peers = |key4: &str, key6: &str| {
+            let mut peers = value.get(key4).and_then(Value::as_bytes).map(|b| parse_compact_peers(b, 4)).unwrap_or_default();
+            peers.extend(value.get(key6).and_then(Value::as_bytes).map(|b| parse_compact_peers(b, 16)).unwrap_or_default());
+            peers.retain(|addr| addr.port() != 0 && !addr.ip().is_unspecified());
+            peers.truncate(MAX_PEX_PEERS);
+            peers
+        };
+        Ok(Self { added: peers("added", "added6"), dropped: peers("dropped", "dropped6") })
+    }
+}
+
+/// Mensagem ut_metadata. Em `Data`, o pedaço segue o dicionário bencode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request { piece: usize },
+    Data { piece: usize, total_size: usize, data: Vec<u8> },
+    Reject { piece: usize },
+}
+
+impl MetadataMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let (msg_type, piece) = match self {
+            MetadataMessage::Request { piece } => (0, piece),
+            MetadataMessage::Data { piece, .. } => (1, piece),
+            MetadataMessage::Reject { piece } => (2, piece),
+        };
+        let mut dict = Dict::new();
+        dict.insert(b"msg_type".to_vec(), Value::Integer(msg_type));
+        dict.insert(b"piece".to_vec(), Value::Integer(*piece as i64));
+        if let MetadataMessage::Data { total_size, .. } = self {
+            dict.insert(b"total_size".to_vec(), Value::Integer(*total_size as i64));
+        }
+        let mut out = bencode::encode(&Value::Dict(dict));
+        if let MetadataMessage::Data { data, .. } = self {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> io::Result<Self> {
+        let (value, consumed) = bencode::decode_prefix(payload)
+            .map_err(|e| invalid(&format!("mensagem ut_metadata inválida: {}", e)))?
+            .ok_or_else(|| invalid("mensagem ut_metadata incompleta"))?;
+        let int = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_int)
+                .and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| invalid("mensagem ut_metadata sem campos obrigatórios"))
+        };
+        let piece = int("piece")?;
+        match int("msg_type")? {
+            0 => Ok(MetadataMessage::Request { piece }),
+            1 => Ok(MetadataMessage::Data { piece, total_size: int("total_size")?, data: payload[consumed..].to_vec() }),
+            2 => Ok(MetadataMessage::Reject { piece }),
+            _ => Err(invalid("tipo de mensagem ut_metadata desconhecido")),
+        }
+    }
+}
+
+/// Quantidade de pedaços de 16 KiB de um dicionário `info` de `size` bytes.
+pub fn metadata_pieces(size: usize) -> usize {
+    size.div_ceil(METADATA_PIECE_LEN)
+}
+
+/// Separa os endereços nas listas compactas de IPv4 e IPv6.
+fn split_compact(addrs: &[SocketAddr]) -> (Vec<u8>, Vec<u8>) {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for addr in addrs {
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                v4.extend_from_slice(&ip.octets());
+                v4.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            IpAddr::V6(ip) => {
+                v6.extend_from_slice(&ip.octets());
+                v6.extend_from_slice(&addr.port().to_be_bytes());
+            }
+        }
+    }
+    (v4, v6)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,230 @@
+﻿//! Servidor HTTP/1.1 mínimo, suficiente para o tracker e as APIs locais.
+
+use crate::codec::{Decoder, Encoder};
+use bytes::{Buf, BytesMut};
+use std::io;
+
+/// Tamanho máximo da linha de requisição mais cabeçalhos.
+pub const MAX_HEADER_LEN: usize = 16 * 1024;
+
+/// Tamanho máximo do corpo aceito.
+pub const MAX_BODY_LEN: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Parâmetros da query string já decodificados; valores podem ser binários.
+    pub query: Vec<(String, Vec<u8>)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn query_param(&self, name: &str) -> Option<&[u8]> {
+        self.query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_slice())
+    }
+
+    pub fn query_str(&self, name: &str) -> Option<&str> {
+        self.query_param(name).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// Todos os valores de um parâmetro repetido (ex.: vários `info_hash` no scrape).
+    pub fn query_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.query.iter().filter(move |(k, _)| k == name).map(|(_, v)| v.as_slice())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Codec HTTP/1.1 do lado do servidor: decodifica requisições e codifica respostas.
+///
+/// A requisição só é entregue quando os cabeçalhos e todo o corpo indicado
+/// por `Content-Length` estão no buffer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpCodec;
+
+impl Decoder for HttpCodec {
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Request>> {
+        let Some(head_len) = find_head_end(src) else {
+            if src.len() > MAX_HEADER_LEN {
+                return Err(invalid("cabeçalhos grandes demais"));
+            }
+            return Ok(None);
+        };
+        if head_len > MAX_HEADER_LEN {
+            return Err(invalid("cabeçalhos grandes demais"));
+        }
+        let mut request = parse_head(&String::from_utf8_lossy(&src[..head_len]))?;
+
+        let body_len = match request.header("Content-Length") {
+            Some(length) => length.parse::<usize>().map_err(|_| invalid("Content-Length inválido"))?,
+            None => 0,
+        };
+        if body_len > MAX_BODY_LEN {
+            return Err(invalid("corpo grande demais"));
+        }
+        if src.len() < head_len + body_len {
+            src.reserve(head_len + body_len - src.len());
+            return Ok(None);
+        }
+        src.advance(head_len);
+        request.body = src.split_to(body_len).to_vec();
+        Ok(Some(request))
+    }
+}
+
+impl Encoder<Response> for HttpCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, response: Response, dst: &mut BytesMut) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+        dst.reserve(head.len() + response.body.len());
+        dst.extend_from_slice(head.as_bytes());
+        dst.extend_from_slice(&response.body);
+        Ok(())
+    }
+}
+
+/// Posição logo após a linha em branco que encerra os cabeçalhos.
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    (0..buffer.len()).find_map(|i| match &buffer[i..] {
+        [b'\r', b'\n', b'\r', b'\n', ..] => Some(i + 4),
+        [b'\n', b'\n', ..] => Some(i + 2),
+        _ => None,
+    })
+}
+
+fn parse_head(head: &str) -> io::Result<Request> {
+    let mut lines = head.lines();
+    let line = lines.next().unwrap_or_default();
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid("linha de requisição inválida"));
+    };
+    let method = method.to_string();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (percent_decode_str(path), parse_query(query)),
+        None => (percent_decode_str(target), Vec::new()),
+    };
+
+    let mut headers = Vec::new();
+    for line in lines.map(|line| line.trim_end_matches('\r')).take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("cabeçalho inválido"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(Request { method, path, query, headers, body: Vec::new() })
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        _ => "Unknown",
+    }
+}
+
+fn parse_query(query: &str) -> Vec<(String, Vec<u8>)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode_str(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decodifica `%XX` (e `+` como espaço) em bytes arbitrários.
+pub fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => out.push(b'%'),
+            },
+            b'+' => out.push(b' '),
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+fn percent_decode_str(input: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(input)).to_string()
+}
+
+/// Codifica bytes arbitrários para uso em URL, preservando apenas os caracteres não reservados.
+pub fn percent_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,28 @@
+﻿pub mod announce;
+pub mod api;
+pub mod bencode;
+pub mod bitfield;
+pub mod chat;
+pub mod choker;
+pub mod codec;
+pub mod config;
+pub mod connection;
+pub mod dht;
+pub mod extension;
+pub mod http;
+pub mod lsd;
+pub mod magnet;
+pub mod metadata;
+pub mod metainfo;
+pub mod peer;
+pub mod picker;
+pub mod ratelimit;
+pub mod resume;
+pub mod session;
+pub mod state;
+pub mod storage;
+pub mod torrent;
+pub mod tracker;
+pub mod transmission;
+pub mod websocket;
+pub mod wire;
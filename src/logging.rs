@@ -0,0 +1,47 @@
+﻿//! Log do cliente: os eventos do `tracing` emitidos pela biblioteca, filtrados
+//! por nível e módulo e gravados na saída de erros, em texto ou JSON.
+
+use bittorrent_client::config::{LogConfig, LogFormat};
+use std::io::{self, IsTerminal};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+/// Para onde a saída de erros vai, o que decide o formato das linhas de texto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// Terminal, journal ou pipe: cores só se for um terminal.
+    Stderr,
+    /// Arquivo de log do daemon, que só passa a receber a saída depois.
+    File,
+    /// Painel de log da interface de terminal, que já mostra as linhas em ordem
+    /// e tem pouca largura: sem cores nem carimbo de tempo.
+    Pane,
+}
+
+/// Instala o subscriber global; falha se o filtro não puder ser interpretado.
+pub fn init(config: &LogConfig, destination: Destination) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&config.filter)
+        .map_err(|e| format!("filtro de log inválido {:?}: {}", config.filter, e))?;
+    let layer: Box<dyn Layer<Registry> + Send + Sync> = match (config.format, destination) {
+        (LogFormat::Json, _) => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(io::stderr)
+            .boxed(),
+        (LogFormat::Text, Destination::Pane) => fmt::layer()
+            .without_time()
+            .with_target(false)
+            .with_ansi(false)
+            .with_writer(io::stderr)
+            .boxed(),
+        (LogFormat::Text, _) => fmt::layer()
+            .with_ansi(destination == Destination::Stderr && io::stderr().is_terminal())
+            .with_writer(io::stderr)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .try_init()
+        .map_err(|e| format!("erro ao iniciar o log: {}", e))
+}
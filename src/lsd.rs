@@ -0,0 +1,306 @@
+﻿//! Local Service Discovery (BEP 14): anúncios multicast que permitem a peers
+//! da mesma rede local se encontrarem sem tracker nem DHT.
+//!
+//! Cada anúncio é um datagrama no formato de uma requisição HTTP:
+//!
+//! ```text
+//! BT-SEARCH * HTTP/1.1
+//! Host: 239.192.152.143:6771
+//! Port: <porta de escuta>
+//! Infohash: <info-hash em hexadecimal>
+//! cookie: <identificador do remetente>
+//! ```
+
+use crate::metainfo::InfoHash;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+
+/// Grupo multicast IPv4 definido pelo BEP 14.
+pub const LSD_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 192, 152, 143), 6771);
+
+/// Intervalo recomendado entre anúncios periódicos do mesmo torrent.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Um torrent não é anunciado mais de uma vez por minuto.
+const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Info-hashes por datagrama, para não passar de ~1400 bytes.
+const MAX_HASHES_PER_ANNOUNCE: usize = 20;
+
+const MAX_DATAGRAM_LEN: usize = 1400;
+
+#[derive(Debug, Clone)]
+pub struct LsdConfig {
+    /// Grupo multicast e porta onde os anúncios são enviados e recebidos.
+    pub group: SocketAddrV4,
+    /// Interface usada para entrar no grupo e enviar; `0.0.0.0` deixa o
+    /// sistema escolher. Em testes, `127.0.0.1` mantém tudo em loopback.
+    pub interface: Ipv4Addr,
+    /// Entrega os anúncios também aos outros clientes da própria máquina.
+    pub loopback: bool,
+}
+
+impl Default for LsdConfig {
+    fn default() -> Self {
+        Self { group: LSD_GROUP, interface: Ipv4Addr::UNSPECIFIED, loopback: true }
+    }
+}
+
+/// Anúncio BT-SEARCH recebido ou a enviar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announce {
+    pub port: u16,
+    pub info_hashes: Vec<InfoHash>,
+    /// Identifica o remetente, para descartarmos os nossos próprios anúncios.
+    pub cookie: Option<String>,
+}
+
+impl Announce {
+    pub fn encode(&self, group: SocketAddrV4) -> Vec<u8> {
+        let mut out = format!("BT-SEARCH * HTTP/1.1\r\nHost: {}\r\nPort: {}\r\n", group, self.port);
+        for info_hash in &self.info_hashes {
+            out.push_str(&format!("Infohash: {}\r\n", info_hash.to_hex()));
+        }
+        if let Some(cookie) = &self.cookie {
+            out.push_str(&format!("cookie: {}\r\n", cookie));
+        }
+        out.push_str("\r\n\r\n");
+        out.into_bytes()
+    }
+
+    /// Interpreta um datagrama; `None` se não for um BT-SEARCH válido.
+    pub fn parse(datagram: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(datagram).ok()?;
+        let mut lines = text.split("\r\n");
+        if lines.next()?.trim() != "BT-SEARCH * HTTP/1.1" {
+            return None;
+        }
+        let mut port = None;
+        let mut info_hashes = Vec::new();
+        let mut cookie = None;
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "port" => port = value.parse::<u16>().ok().filter(|&port| port != 0),
+                "infohash" => info_hashes.extend(InfoHash::from_hex(value)),
+                "cookie" => cookie = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        if info_hashes.is_empty() {
+            return None;
+        }
+        Some(Self { port: port?, info_hashes, cookie })
+    }
+}
+
+/// Peer da rede local que anunciou um torrent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalPeer {
+    pub info_hash: InfoHash,
+    pub addr: SocketAddr,
+}
+
+#[derive(Clone)]
+pub struct Lsd {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    socket: UdpSocket,
+    config: LsdConfig,
+    /// Porta em que aceitamos conexões de peers.
+    port: u16,
+    cookie: String,
+    last_announce: Mutex<HashMap<InfoHash, Instant>>,
+    peers: broadcast::Sender<LocalPeer>,
+}
+
+impl Lsd {
+    /// Entra no grupo multicast e inicia a recepção de anúncios. `port` é a
+    /// porta de escuta de peers anunciada aos demais.
+    pub async fn bind(config: LsdConfig, port: u16) -> io::Result<Self> {
+        let socket = multicast_socket(&config)?;
+        let (peers, _) = broadcast::channel(64);
+        let lsd = Self {
+            inner: Arc::new(Inner {
+                socket,
+                config,
+                port,
+                cookie: hex::encode(rand::random::<[u8; 8]>()),
+                last_announce: Mutex::new(HashMap::new()),
+                peers,
+            }),
+        };
+        info!(group = %lsd.inner.config.group, "LSD escutando");
+
+        let receiver = lsd.clone();
+        tokio::spawn(async move { receiver.receive_loop().await });
+        Ok(lsd)
+    }
+
+    /// Recebe os peers locais que anunciarem algum torrent.
+    pub fn subscribe(&self) -> broadcast::Receiver<LocalPeer> {
+        self.inner.peers.subscribe()
+    }
+
+    /// Anuncia os torrents na rede local, pulando os anunciados há menos de um minuto.
+    pub async fn announce(&self, info_hashes: &[InfoHash]) -> io::Result<()> {
+        let due: Vec<InfoHash> = {
+            let mut last_announce = self.inner.last_announce.lock().await;
+            let now = Instant::now();
+            info_hashes
+                .iter()
+                .copied()
+                .filter(|info_hash| match last_announce.get(info_hash) {
+                    Some(last) if now.duration_since(*last) < MIN_ANNOUNCE_INTERVAL => false,
+                    _ => {
+                        last_announce.insert(*info_hash, now);
+                        true
+                    }
+                })
+                .collect()
+        };
+        for chunk in due.chunks(MAX_HASHES_PER_ANNOUNCE) {
+            let announce = Announce {
+                port: self.inner.port,
+                info_hashes: chunk.to_vec(),
+                cookie: Some(self.inner.cookie.clone()),
+            };
+            let datagram = announce.encode(self.inner.config.group);
+            self.inner.socket.send_to(&datagram, self.inner.config.group).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive_loop(&self) {
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, from) = match self.inner.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!(error = %e, "Erro no LSD");
+                    continue;
+                }
+            };
+            let Some(announce) = Announce::parse(&buffer[..len]) else {
+                continue;
+            };
+            if announce.cookie.as_deref() == Some(self.inner.cookie.as_str()) {
+                continue;
+            }
+            // O endereço do peer é o IP de origem com a porta do anúncio
+            let addr = SocketAddr::new(from.ip(), announce.port);
+            for info_hash in announce.info_hashes {
+                let _ = self.inner.peers.send(LocalPeer { info_hash, addr });
+            }
+        }
+    }
+}
+
+/// Socket UDP no grupo multicast. Com `SO_REUSEADDR`, vários clientes da
+/// mesma máquina podem escutar a porta do LSD ao mesmo tempo.
+fn multicast_socket(config: &LsdConfig) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.group.port())).into())?;
+    socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+    socket.set_multicast_if_v4(&config.interface)?;
+    socket.set_multicast_loop_v4(config.loopback)?;
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info_hash(byte: u8) -> InfoHash {
+        InfoHash([byte; 20])
+    }
+
+    #[test]
+    fn announce_round_trip() {
+        let announce = Announce {
+            port: 51413,
+            info_hashes: vec![info_hash(0xab), info_hash(0x01)],
+            cookie: Some("c0ffee".to_string()),
+        };
+        let datagram = announce.encode(LSD_GROUP);
+        assert!(datagram.starts_with(b"BT-SEARCH * HTTP/1.1\r\nHost: 239.192.152.143:6771\r\nPort: 51413\r\n"));
+        assert_eq!(Announce::parse(&datagram), Some(announce));
+
+        let anonymous = Announce { port: 6881, info_hashes: vec![info_hash(2)], cookie: None };
+        assert_eq!(Announce::parse(&anonymous.encode(LSD_GROUP)), Some(anonymous));
+    }
+
+    #[test]
+    fn parse_accepts_any_header_case() {
+        let datagram = format!(
+            "BT-SEARCH * HTTP/1.1\r\nhost: x\r\nPORT:  6881 \r\ninfohash: {}\r\nX-Other: 1\r\n\r\n\r\n",
+            info_hash(3).to_hex()
+        );
+        let announce = Announce::parse(datagram.as_bytes()).unwrap();
+        assert_eq!((announce.port, announce.info_hashes, announce.cookie), (6881, vec![info_hash(3)], None));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_datagrams() {
+        let hash = info_hash(4).to_hex();
+        let rejected = [
+            String::new(),
+            format!("M-SEARCH * HTTP/1.1\r\nPort: 6881\r\nInfohash: {}\r\n\r\n", hash),
+            format!("BT-SEARCH * HTTP/1.1\r\nInfohash: {}\r\n\r\n", hash),
+            format!("BT-SEARCH * HTTP/1.1\r\nPort: 0\r\nInfohash: {}\r\n\r\n", hash),
+            format!("BT-SEARCH * HTTP/1.1\r\nPort: 70000\r\nInfohash: {}\r\n\r\n", hash),
+            "BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\n\r\n".to_string(),
+            "BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\nInfohash: xyz\r\n\r\n".to_string(),
+            // Cabeçalhos depois da linha em branco não contam
+            format!("BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\n\r\nInfohash: {}\r\n", hash),
+        ];
+        for datagram in rejected {
+            assert_eq!(Announce::parse(datagram.as_bytes()), None, "{:?}", datagram);
+        }
+        assert_eq!(Announce::parse(&[0xff, 0xfe, b'\r', b'\n']), None);
+    }
+
+    #[tokio::test]
+    async fn instances_discover_each_other_on_loopback() {
+        let config = LsdConfig {
+            group: SocketAddrV4::new(*LSD_GROUP.ip(), 20000 + rand::random::<u16>() % 20000),
+            interface: Ipv4Addr::LOCALHOST,
+            loopback: true,
+        };
+        let first = Lsd::bind(config.clone(), 6001).await.unwrap();
+        let second = Lsd::bind(config, 6002).await.unwrap();
+        let mut first_peers = first.subscribe();
+        let mut second_peers = second.subscribe();
+
+        first.announce(&[info_hash(5)]).await.unwrap();
+        let peer = tokio::time::timeout(Duration::from_secs(5), second_peers.recv()).await.unwrap().unwrap();
+        assert_eq!(peer, LocalPeer { info_hash: info_hash(5), addr: SocketAddr::from(([127, 0, 0, 1], 6001)) });
+
+        second.announce(&[info_hash(6)]).await.unwrap();
+        let peer = tokio::time::timeout(Duration::from_secs(5), first_peers.recv()).await.unwrap().unwrap();
+        assert_eq!(peer, LocalPeer { info_hash: info_hash(6), addr: SocketAddr::from(([127, 0, 0, 1], 6002)) });
+
+        // Cada um recebe o próprio anúncio pelo loopback, mas o descarta pelo cookie
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(first_peers.try_recv().is_err());
+        assert!(second_peers.try_recv().is_err());
+
+        // Repetido antes de um minuto, o anúncio não sai
+        first.announce(&[info_hash(5)]).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), second_peers.recv()).await.is_err());
+    }
+}
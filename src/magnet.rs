@@ -0,0 +1,206 @@
+﻿//! Links magnet (BEP 9): identificam um torrent pelo info-hash, sem o .torrent.
+//!
+//! Formato: `magnet:?xt=urn:btih:<hash>&dn=<nome>&tr=<tracker>&x.pe=<ip:porta>`,
+//! com o hash em hexadecimal (40 caracteres) ou base32 (32 caracteres).
+
+use crate::http::{percent_decode, percent_encode};
+use crate::metainfo::{InfoHash, Metainfo};
+use std::fmt;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    pub info_hash: InfoHash,
+    /// Nome sugerido para exibição (`dn`).
+    pub name: Option<String>,
+    /// Trackers (`tr`), na ordem do link.
+    pub trackers: Vec<String>,
+    /// Peers informados diretamente no link (`x.pe`).
+    pub peers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    NotMagnet,
+    /// Nenhum `xt` com `urn:btih:`.
+    MissingInfoHash,
+    InvalidInfoHash(String),
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagnetError::NotMagnet => write!(f, "não é um link magnet"),
+            MagnetError::MissingInfoHash => write!(f, "link magnet sem info-hash (xt=urn:btih)"),
+            MagnetError::InvalidInfoHash(hash) => write!(f, "info-hash inválido no link magnet: {}", hash),
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+impl Magnet {
+    pub fn parse(uri: &str) -> Result<Self, MagnetError> {
+        let query = uri
+            .trim()
+            .strip_prefix("magnet:?")
+            .ok_or(MagnetError::NotMagnet)?;
+
+        let mut info_hash = None;
+        let mut name = None;
+        let mut trackers = Vec::new();
+        let mut peers = Vec::new();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = String::from_utf8_lossy(&percent_decode(value)).to_string();
+            match key {
+                // Pode haver vários `xt` (outras redes); vale o primeiro BitTorrent
+                "xt" if info_hash.is_none() => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(parse_info_hash(hash)?);
+                    }
+                }
+                "dn" => name = Some(value),
+                "tr" if !value.is_empty() && !trackers.contains(&value) => trackers.push(value),
+                // Só endereços IP; nomes de host exigiriam resolução bloqueante aqui
+                "x.pe" => peers.extend(value.parse::<SocketAddr>().ok()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.ok_or(MagnetError::MissingInfoHash)?,
+            name,
+            trackers,
+            peers,
+        })
+    }
+
+    /// Monta o link de um torrent existente, com o nome e os trackers.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        Self {
+            info_hash: metainfo.info_hash,
+            name: Some(metainfo.info.name.clone()),
+            trackers: metainfo.trackers(),
+            peers: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "magnet:?xt=urn:btih:{}", self.info_hash.to_hex())?;
+        if let Some(name) = &self.name {
+            write!(f, "&dn={}", percent_encode(name.as_bytes()))?;
+        }
+        for tracker in &self.trackers {
+            write!(f, "&tr={}", percent_encode(tracker.as_bytes()))?;
+        }
+        for peer in &self.peers {
+            write!(f, "&x.pe={}", percent_encode(peer.to_string().as_bytes()))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_info_hash(hash: &str) -> Result<InfoHash, MagnetError> {
+    let parsed = match hash.len() {
+        40 => InfoHash::from_hex(hash),
+        32 => base32_decode(hash).as_deref().and_then(InfoHash::from_bytes),
+        _ => None,
+    };
+    parsed.ok_or_else(|| MagnetError::InvalidInfoHash(hash.to_string()))
+}
+
+/// Base32 do RFC 4648, sem padding; maiúsculas e minúsculas são aceitas.
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = buffer << 5 | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+    const BASE32: &str = "YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK";
+
+    fn hash() -> InfoHash {
+        InfoHash::from_hex(HEX).unwrap()
+    }
+
+    #[test]
+    fn hex_and_base32_hashes_decode_to_the_same_bytes() {
+        for encoded in [HEX.to_string(), HEX.to_uppercase(), BASE32.to_string(), BASE32.to_lowercase()] {
+            let magnet = Magnet::parse(&format!("magnet:?xt=urn:btih:{}", encoded)).unwrap();
+            assert_eq!(magnet.info_hash, hash(), "{}", encoded);
+        }
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn rejects_invalid_hashes() {
+        for encoded in [&HEX[..39], &BASE32[..31], "", "0".repeat(41).as_str(), &"zz".repeat(20), &"1".repeat(32), &"=".repeat(32)] {
+            assert_eq!(
+                Magnet::parse(&format!("magnet:?xt=urn:btih:{}", encoded)),
+                Err(MagnetError::InvalidInfoHash(encoded.to_string())),
+                "{}",
+                encoded
+            );
+        }
+        assert_eq!(Magnet::parse("magnet:?dn=nome"), Err(MagnetError::MissingInfoHash));
+        assert_eq!(Magnet::parse("magnet:?xt=urn:sha1:abc"), Err(MagnetError::MissingInfoHash));
+        assert_eq!(Magnet::parse(&format!("http://x/?xt=urn:btih:{}", HEX)), Err(MagnetError::NotMagnet));
+    }
+
+    #[test]
+    fn first_bittorrent_xt_wins() {
+        let other = "ab".repeat(20);
+        let uri = format!("magnet:?xt=urn:btmh:1220{}&xt=urn:btih:{}&xt=urn:btih:{}", "00".repeat(32), HEX, other);
+        assert_eq!(Magnet::parse(&uri).unwrap().info_hash, hash());
+    }
+
+    #[test]
+    fn decodes_percent_encoded_fields() {
+        let uri = format!(
+            "  magnet:?xt=urn:btih:{}&dn=Meu+Arquivo%20%C3%A9%26&tr=udp%3A%2F%2Ftracker.exemplo%3A6969%2Fannounce&tr=http%3A%2F%2Ft%2Fa%3Fk%3D1&tr=&tr=udp%3A%2F%2Ftracker.exemplo%3A6969%2Fannounce&x.pe=10.0.0.1%3A6881&x.pe=host%3A1\n",
+            HEX
+        );
+        let magnet = Magnet::parse(&uri).unwrap();
+        assert_eq!(magnet.name.as_deref(), Some("Meu Arquivo é&"));
+        assert_eq!(magnet.trackers, ["udp://tracker.exemplo:6969/announce", "http://t/a?k=1"]);
+        assert_eq!(magnet.peers, [SocketAddr::from(([10, 0, 0, 1], 6881))]);
+    }
+
+    #[test]
+    fn display_round_trips_through_parse() {
+        let magnet = Magnet {
+            info_hash: hash(),
+            name: Some("Nome com espaço & símbolos=?".to_string()),
+            trackers: vec!["http://t.exemplo/announce?a=1&b=2".to_string(), "udp://u.exemplo:80".to_string()],
+            peers: vec![SocketAddr::from(([192, 168, 0, 2], 51413)), "[::1]:6881".parse().unwrap()],
+        };
+        let uri = magnet.to_string();
+        assert!(uri.starts_with(&format!("magnet:?xt=urn:btih:{}&dn=", HEX)));
+        assert_eq!(Magnet::parse(&uri).unwrap(), magnet);
+
+        let bare = Magnet { info_hash: hash(), name: None, trackers: Vec::new(), peers: Vec::new() };
+        assert_eq!(bare.to_string(), format!("magnet:?xt=urn:btih:{}", HEX));
+        assert_eq!(Magnet::parse(&bare.to_string()).unwrap(), bare);
+    }
+}
@@ -1,38 +1,750 @@
-﻿mod peer;
-mod tracker;
-mod chat;
-
-use crate::peer::{Peer, list_local_files};
-use crate::tracker::Tracker;
-use crate::chat::{ChatServer, start_chat_client, message_receiver};
-use tokio::sync::Mutex;
+﻿use bittorrent_client::announce::Event;
+use bittorrent_client::api::{self, ApiServer};
+use bittorrent_client::bitfield::Bitfield;
+use bittorrent_client::config::{self, Config, LogFormat};
+use bittorrent_client::magnet::{Magnet, MagnetError};
+use bittorrent_client::metainfo::{InfoHash, Metainfo};
+use bittorrent_client::peer::{Peer, list_local_files};
+use bittorrent_client::ratelimit::{RateLimiter, RateLimits};
+use bittorrent_client::session::{Session, SessionError, TorrentState};
+use bittorrent_client::state::SessionState;
+use bittorrent_client::torrent::Torrent;
+use bittorrent_client::tracker::Tracker;
+use bittorrent_client::dht::{Dht, DhtConfig};
+use bittorrent_client::lsd::{Lsd, LsdConfig};
+use bittorrent_client::chat::{ChatServer, start_chat_client, message_receiver};
+use clap::{Args, Parser, Subcommand};
 use std::sync::Arc;
-use std::env;
 use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+mod logging;
+mod tui;
+
+/// Intervalo entre as linhas de progresso do subcomando `download`.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Intervalo entre os resumos dos torrents no log do daemon.
+const DAEMON_STATUS_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Parser)]
+#[command(version, about = "Cliente BitTorrent com tracker, DHT e chat entre peers")]
+struct Cli {
+    /// Arquivo de configuração TOML [padrão: <config>/bittorrent-client/config.toml]
+    #[arg(long, global = true, env = "BT_CONFIG")]
+    config: Option<PathBuf>,
+    /// Níveis do log por módulo, como `info,bittorrent_client::connection=debug`
+    #[arg(long, global = true)]
+    log: Option<String>,
+    /// Formato do log: text ou json
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Inicia o tracker HTTP e UDP e o nó DHT de bootstrap
+    Tracker {
+        #[arg(long)]
+        port: Option<u16>,
+        #[arg(long)]
+        dht_port: Option<u16>,
+    },
+    /// Modo interativo: compartilha, baixa e conversa com outros peers
+    Peer(PeerArgs),
+    /// Gera um arquivo .torrent de um arquivo local
+    Create {
+        path: PathBuf,
+        /// Onde gravar o .torrent [padrão: ao lado do arquivo]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// URL de announce; pode ser repetida [padrão: os trackers da configuração]
+        #[arg(long = "tracker")]
+        trackers: Vec<String>,
+        /// Tamanho das peças em bytes [padrão: escolhido pelo tamanho do arquivo]
+        #[arg(long)]
+        piece_length: Option<u64>,
+    },
+    /// Baixa um torrent (arquivo .torrent ou link magnet) e sai ao terminar
+    Download {
+        /// Caminho de um arquivo .torrent ou link magnet
+        source: String,
+        /// Continua semeando depois do download, até Ctrl+C
+        #[arg(long)]
+        seed: bool,
+        #[command(flatten)]
+        peer: PeerArgs,
+    },
+    /// Roda sem terminal: restaura a sessão, inicia os torrents da seção
+    /// [daemon] da configuração e termina com SIGTERM ou Ctrl+C
+    Daemon {
+        /// Grava a saída neste arquivo em vez da saída padrão
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+        #[command(flatten)]
+        peer: PeerArgs,
+    },
+    /// Interface de terminal em tela cheia, com os torrents, peers, log e chat
+    Tui(PeerArgs),
+    /// Semeia arquivos locais ou .torrent já baixados, até Ctrl+C
+    Seed {
+        /// Arquivos a compartilhar; um .torrent é procurado no diretório de download
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        peer: PeerArgs,
+    },
+}
+
+/// Opções comuns aos modos que iniciam um peer.
+#[derive(Args)]
+struct PeerArgs {
+    /// Nome do peer
+    #[arg(long)]
+    name: Option<String>,
+    /// Endereço de escuta
+    #[arg(long)]
+    ip: Option<String>,
+    /// Porta de escuta [padrão: sorteada entre 6882 e 7881]
+    #[arg(long)]
+    port: Option<u16>,
+    /// Onde gravar os downloads
+    #[arg(long)]
+    download_dir: Option<PathBuf>,
+    /// Não entra na DHT
+    #[arg(long)]
+    no_dht: bool,
+    /// Não anuncia nem procura peers na rede local
+    #[arg(long)]
+    no_lsd: bool,
+    /// Inicia a API HTTP de controle nesta porta
+    #[arg(long)]
+    api_port: Option<u16>,
+}
+
+impl PeerArgs {
+    fn apply(self, config: &mut Config) {
+        if self.name.is_some() {
+            config.peer.name = self.name;
+        }
+        if let Some(ip) = self.ip {
+            config.peer.ip = ip;
+        }
+        if self.port.is_some() {
+            config.peer.port = self.port;
+        }
+        if let Some(download_dir) = self.download_dir {
+            config.paths.download_dir = download_dir;
+        }
+        config.peer.dht &= !self.no_dht;
+        config.peer.lsd &= !self.no_lsd;
+        if self.api_port.is_some() {
+            config.api.port = self.api_port;
+        }
+    }
+}
+
+/// Gera o metainfo de um arquivo local sem bloquear o runtime.
+async fn create_metainfo(
+    path: PathBuf,
+    piece_length: Option<u64>,
+    trackers: Vec<String>,
+) -> Result<Metainfo, Box<dyn std::error::Error>> {
+    let metainfo = tokio::task::spawn_blocking(move || Metainfo::create(&path, piece_length, trackers)).await??;
+    Ok(metainfo)
+}
+
+/// Gera o .torrent de um arquivo local, salva ao lado dele e o prepara para semear.
+async fn seed_file(path: PathBuf, trackers: Vec<String>) -> Result<Arc<Torrent>, Box<dyn std::error::Error>> {
+    info!(path = %path.display(), "Calculando hashes");
+    let metainfo = create_metainfo(path.clone(), None, trackers).await?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let torrent_path = path.with_file_name(format!("{}.torrent", file_name));
+    metainfo.save(&torrent_path)?;
+    info!(
+        path = %torrent_path.display(),
+        info_hash = %metainfo.info_hash,
+        magnet = %Magnet::from_metainfo(&metainfo),
+        pieces = metainfo.info.piece_count(),
+        piece_length = metainfo.info.piece_length,
+        "Torrent salvo"
+    );
+
+    let save_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let have = Bitfield::full(metainfo.info.piece_count());
+    Ok(Arc::new(Torrent::new(Arc::new(metainfo), save_dir, have)))
+}
+
+/// Origem de um download: arquivo .torrent ou link magnet.
+enum TorrentSource {
+    File(PathBuf),
+    Magnet(Magnet),
+}
+
+impl TorrentSource {
+    /// Link magnet ou caminho de um arquivo .torrent.
+    fn parse(source: &str) -> Result<Self, MagnetError> {
+        if source.starts_with("magnet:") {
+            Magnet::parse(source).map(TorrentSource::Magnet)
+        } else {
+            Ok(TorrentSource::File(PathBuf::from(source)))
+        }
+    }
+
+    /// Adiciona o torrent à sessão, para baixar em `download_dir`.
+    async fn add_to(self, session: &Session, download_dir: PathBuf) -> Result<InfoHash, Box<dyn std::error::Error>> {
+        match self {
+            TorrentSource::File(torrent_path) => {
+                let metainfo = Metainfo::load(&torrent_path)
+                    .map_err(|e| format!("erro ao abrir {}: {}", torrent_path.display(), e))?;
+                println!("Arquivo será salvo em: {}", download_dir.join(&metainfo.info.name).display());
+                Ok(session.add(metainfo, download_dir).await?)
+            }
+            TorrentSource::Magnet(magnet) => Ok(session.add_magnet(magnet, download_dir).await?),
+        }
+    }
+}
+
+/// Pergunta qual torrent abrir: um dos .torrent encontrados nos diretórios
+/// padrão, um caminho digitado ou um link magnet.
+fn choose_torrent(share_dirs: &[PathBuf]) -> Option<TorrentSource> {
+    let torrents: Vec<(String, PathBuf)> = list_local_files(share_dirs)
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(".torrent"))
+        .collect();
+
+    println!("\nArquivos .torrent encontrados:");
+    for (index, (file_name, path)) in torrents.iter().enumerate() {
+        println!("{}: {} ({})", index, file_name, path.display());
+    }
+
+    print!("\nDigite o número do torrent, o caminho de um arquivo .torrent ou um link magnet: ");
+    io::stdout().flush().unwrap();
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice).unwrap();
+    let choice = choice.trim();
+
+    match choice.parse::<usize>() {
+        Ok(index) => torrents.get(index).map(|(_, path)| TorrentSource::File(path.clone())),
+        Err(_) if !choice.is_empty() => TorrentSource::parse(choice)
+            .map_err(|e| println!("{}", e))
+            .ok(),
+        Err(_) => None,
+    }
+}
+
+/// Lista os torrents da sessão e pergunta qual usar.
+async fn choose_session_torrent(session: &Session) -> Option<InfoHash> {
+    let statuses = session.list().await;
+    println!("\nTorrents:");
+    for (index, status) in statuses.iter().enumerate() {
+        println!("{}: {} [{}] ({})", index, status.name, status.info_hash, status.state);
+    }
+    print!("\nEscolha o número do torrent: ");
+    io::stdout().flush().unwrap();
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice).unwrap();
+    match choice.trim().parse::<usize>() {
+        Ok(index) if index < statuses.len() => Some(statuses[index].info_hash),
+        _ => {
+            println!("Índice inválido!");
+            None
+        }
+    }
+}
+
+/// Lê um novo limite em KiB/s para `limiter`; Enter mantém o atual e 0 remove o limite.
+fn read_limit(label: &str, limiter: &RateLimiter) {
+    let current = match limiter.rate() {
+        0 => "sem limite".to_string(),
+        rate => format!("{} KiB/s", rate / 1024),
+    };
+    print!("{} em KiB/s (atual: {}, 0 = sem limite): ", label, current);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let input = input.trim();
+    if input.is_empty() {
+        return;
+    }
+    match input.parse::<u64>() {
+        Ok(kib) => {
+            if let Err(e) = limiter.set_rate_kib(kib) {
+                println!("{}, limite mantido.", e);
+            }
+        }
+        Err(_) => println!("Valor inválido, limite mantido."),
+    }
+}
+
+fn read_limits(limits: &RateLimits) {
+    read_limit("Envio", &limits.upload);
+    read_limit("Recebimento", &limits.download);
+}
+
+fn print_commands() {
+    println!("- 'list': lista peers conectados");
+    println!("- 'files': lista os torrents que estão sendo baixados ou semeados");
+    println!("- 'chat': inicia chat com outro peer");
+    println!("- 'download': adiciona um torrent a baixar, a partir de um arquivo .torrent ou link magnet");
+    println!("- 'pause': pausa um torrent");
+    println!("- 'resume': retoma um torrent pausado ou com erro");
+    println!("- 'remove': tira um torrent da sessão, opcionalmente apagando os arquivos");
+    println!("- 'create-torrent': gera um arquivo .torrent de um arquivo local e o semeia");
+    println!("- 'recheck': reverifica no disco as peças de um torrent");
+    println!("- 'slots': altera quantos peers recebem dados ao mesmo tempo (unchoke)");
+    println!("- 'limit': altera os limites de banda (global, por torrent ou por peer)");
+    println!("- 'exit': sair");
+}
 
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Uso: cargo run -- tracker | cargo run -- peer");
-        return;
+    let cli = Cli::parse();
+    let config_path = cli.config.unwrap_or_else(config::default_path);
+    let mut config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = config.apply_env() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    if let Some(filter) = cli.log {
+        config.log.filter = filter;
+    }
+    if let Some(format) = cli.log_format {
+        config.log.format = format;
+    }
+    let destination = match &cli.command {
+        Command::Tui(_) => logging::Destination::Pane,
+        Command::Daemon { log_file, .. } if log_file.is_some() || config.daemon.log_file.is_some() => {
+            logging::Destination::File
+        }
+        _ => logging::Destination::Stderr,
+    };
+    if let Err(e) = logging::init(&config.log, destination) {
+        eprintln!("{}", e);
+        std::process::exit(2);
     }
-    let mode = &args[1];
 
-    if mode == "tracker" {
-        let tracker = Arc::new(Mutex::new(Tracker::new()));
-        println!("Iniciando o tracker...");
-        tracker.lock().await.start(6881).await.unwrap();
-    } else if mode == "peer" {
-        print!("Digite seu nome de peer: ");
-        io::stdout().flush().unwrap();
-        let mut peer_name = String::new();
-        io::stdin().read_line(&mut peer_name).unwrap();
-        let peer_name = peer_name.trim().to_string();
+    let result = match cli.command {
+        Command::Tracker { port, dht_port } => {
+            config.tracker.port = port.unwrap_or(config.tracker.port);
+            config.tracker.dht_port = dht_port.unwrap_or(config.tracker.dht_port);
+            run_tracker(config).await
+        }
+        Command::Peer(args) => {
+            args.apply(&mut config);
+            run_peer(config).await
+        }
+        Command::Create { path, output, trackers, piece_length } => {
+            let trackers = if trackers.is_empty() { config.tracker.announce } else { trackers };
+            run_create(path, output, trackers, piece_length).await
+        }
+        Command::Download { source, seed, peer } => {
+            peer.apply(&mut config);
+            run_download(config, source, seed).await
+        }
+        Command::Daemon { log_file, peer } => {
+            peer.apply(&mut config);
+            if log_file.is_some() {
+                config.daemon.log_file = log_file;
+            }
+            run_daemon(config).await
+        }
+        Command::Tui(peer) => {
+            peer.apply(&mut config);
+            run_tui(config).await
+        }
+        Command::Seed { paths, peer } => {
+            peer.apply(&mut config);
+            run_seed(config, paths).await
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Erro: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run_tracker(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let tracker = Tracker::new();
+    info!("Iniciando o tracker");
+    // O tracker UDP compartilha os swarms com o HTTP, na mesma porta
+    let udp_tracker = tracker.clone();
+    let port = config.tracker.port;
+    tokio::spawn(async move {
+        if let Err(e) = udp_tracker.start_udp(port).await {
+            error!(error = %e, "Erro no tracker UDP");
+        }
+    });
+    let dht_config = DhtConfig {
+        bind: SocketAddr::from(([0, 0, 0, 0], config.tracker.dht_port)),
+        bootstrap: Vec::new(),
+        ..DhtConfig::default()
+    };
+    if let Err(e) = Dht::bind(dht_config).await {
+        error!(error = %e, "Erro ao iniciar o nó DHT");
+    }
+    tracker.start(port).await
+}
+
+/// Cria o peer com DHT, LSD, slots e limites da configuração.
+async fn build_peer(config: &Config, name: String) -> Peer {
+    let port = config.peer.port_or_random();
+    let mut peer = Peer::new(config.peer.ip.clone(), port, name).with_unchoke_slots(config.peer.unchoke_slots);
+    let limits = [
+        (&peer.global_limits.upload, config.limits.upload),
+        (&peer.global_limits.download, config.limits.download),
+        (&peer.peer_limits.upload, config.limits.peer_upload),
+        (&peer.peer_limits.download, config.limits.peer_download),
+    ];
+    for (limiter, kib) in limits {
+        if let Err(e) = limiter.set_rate_kib(kib) {
+            warn!(error = %e, "Limite da configuração ignorado");
+        }
+    }
+
+    if config.peer.dht {
+        let dht_config = DhtConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], port)),
+            bootstrap: config.peer.dht_bootstrap.clone(),
+            ..DhtConfig::default()
+        };
+        match Dht::bind(dht_config).await {
+            Ok(dht) => {
+                info!(nodes = dht.bootstrap().await, "DHT conectada");
+                peer = peer.with_dht(dht);
+            }
+            Err(e) => warn!(error = %e, "DHT desabilitada"),
+        }
+    }
+    if config.peer.lsd {
+        let lsd_config = LsdConfig { interface: config.peer.lsd_interface, ..LsdConfig::default() };
+        match Lsd::bind(lsd_config, port).await {
+            Ok(lsd) => peer = peer.with_lsd(lsd),
+            Err(e) => warn!(error = %e, "LSD desabilitado"),
+        }
+    }
+    peer
+}
+
+/// Sessão sem estado gravado, já aceitando conexões, para os subcomandos não interativos.
+async fn start_session(config: &Config) -> Session {
+    let name = config.peer.name.clone().unwrap_or_else(|| "peer".to_string());
+    let session = Session::with_allocation(build_peer(config, name).await, config.paths.allocation);
+    let server = session.clone();
+    tokio::spawn(async move {
+        if let Err(e) = server.start_server().await {
+            error!(error = %e, "Erro no servidor de peers");
+            std::process::exit(1);
+        }
+    });
+    session
+}
+
+/// Inicia a API HTTP de controle e a interface web, se houver porta configurada.
+/// Retorna o endereço da interface com o token, para quem está no terminal.
+async fn start_api(config: &Config, session: &Session, chat: Option<ChatServer>) -> io::Result<Option<String>> {
+    let Some(port) = config.api.port else {
+        return Ok(None);
+    };
+    let token = match &config.api.token {
+        Some(token) => token.clone(),
+        None => {
+            let token = api::load_or_create_token(&config.paths.state_dir).await?;
+            info!(path = %config.paths.state_dir.join("api.token").display(), "Token da API gravado");
+            token
+        }
+    };
+    let mut server = ApiServer::new(session.clone(), token.clone(), config.paths.download_dir.clone());
+    if let Some(chat) = chat {
+        server = server.with_chat(chat);
+    }
+    let addr = SocketAddr::new(config.api.bind, port);
+    tokio::spawn(async move {
+        if let Err(e) = server.start(addr).await {
+            error!(error = %e, "Erro na API");
+            std::process::exit(1);
+        }
+    });
+    Ok(Some(format!("http://{}/#token={}", addr, token)))
+}
+
+async fn run_create(
+    path: PathBuf,
+    output: Option<PathBuf>,
+    trackers: Vec<String>,
+    piece_length: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Calculando hashes de {}...", path.display());
+    let metainfo = create_metainfo(path.clone(), piece_length, trackers).await?;
+    let output = output.unwrap_or_else(|| {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        path.with_file_name(format!("{}.torrent", file_name))
+    });
+    metainfo.save(&output)?;
+    println!("Torrent salvo em: {}", output.display());
+    println!("Info-hash: {}", metainfo.info_hash);
+    println!("Link magnet: {}", Magnet::from_metainfo(&metainfo));
+    Ok(())
+}
+
+async fn run_download(config: Config, source: String, seed: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let source = TorrentSource::parse(&source)?;
+    let session = start_session(&config).await;
+    start_api(&config, &session, None).await?;
+    let info_hash = source.add_to(&session, config.paths.download_dir.clone()).await?;
+    let mut state = session.subscribe(&info_hash).await.ok_or("torrent removido da sessão")?;
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+    loop {
+        match state.borrow_and_update().clone() {
+            TorrentState::Seeding => break,
+            TorrentState::Error(e) => return Err(e.into()),
+            _ => {}
+        }
+        tokio::select! {
+            changed = state.changed() => changed?,
+            _ = progress.tick() => {
+                if let Some(status) = session.list().await.into_iter().find(|status| status.info_hash == info_hash) {
+                    println!("{}: {}/{} peças, {} peers", status.state, status.pieces.0, status.pieces.1, status.peers);
+                }
+            }
+        }
+    }
+    println!("Download concluído em {}", config.paths.download_dir.display());
+
+    if seed {
+        println!("Semeando; Ctrl+C para sair.");
+        shutdown_signal().await?;
+    }
+    session.shutdown().await;
+    Ok(())
+}
+
+/// Adiciona um caminho a semear: um .torrent, com o conteúdo no diretório de
+/// download, ou um arquivo local. O .torrent de um arquivo local é gerado ao
+/// lado dele na primeira vez e reaproveitado depois, sem recalcular os hashes.
+async fn add_seed_path(session: &Session, config: &Config, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|extension| extension == "torrent") {
+        return TorrentSource::File(path).add_to(session, config.paths.download_dir.clone()).await.map(|_| ());
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let torrent_path = path.with_file_name(format!("{}.torrent", file_name));
+    match Metainfo::load(&torrent_path) {
+        Ok(metainfo) if metainfo.info.name == file_name => {
+            let save_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            session.add(metainfo, save_dir).await?;
+        }
+        _ => {
+            let torrent = seed_file(path, config.tracker.announce.clone()).await?;
+            session.add_torrent(torrent).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Espera Ctrl+C ou, no Unix, SIGTERM (o sinal de parada do systemd e do Docker).
+async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => Ok(()),
+            result = tokio::signal::ctrl_c() => result,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Passa a gravar a saída padrão e a de erros no fim de `path`.
+#[cfg(unix)]
+fn redirect_output(path: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    for target in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: os dois descritores são válidos; dup2 apenas os faz apontar para o arquivo
+        if unsafe { libc::dup2(file.as_raw_fd(), target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn redirect_output(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "log em arquivo só é suportado no Unix"))
+}
+
+async fn run_seed(config: Config, paths: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let session = start_session(&config).await;
+    start_api(&config, &session, None).await?;
+    for path in paths {
+        add_seed_path(&session, &config, path).await?;
+    }
+    println!("Semeando; Ctrl+C para sair.");
+    shutdown_signal().await?;
+    session.shutdown().await;
+    Ok(())
+}
+
+/// Estado da execução anterior; um arquivo ilegível vale como sessão nova.
+async fn load_session_state(state_dir: &Path) -> SessionState {
+    match SessionState::load(state_dir).await {
+        Ok(saved) => saved.unwrap_or_default(),
+        Err(e) => {
+            warn!(error = %e, "Estado anterior ignorado");
+            SessionState::default()
+        }
+    }
+}
 
-        // Lista arquivos dos diretórios padrão
-        let files = list_local_files(None);
+async fn run_tui(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // Aberta antes de tudo, para que as mensagens da partida já caiam no painel de log
+    let screen = tui::Screen::open()?;
+    let state_dir = config.paths.state_dir.clone();
+    let saved = load_session_state(&state_dir).await;
+    let name = config.peer.name.clone().or_else(|| saved.peer_name.clone()).unwrap_or_else(|| "peer".to_string());
+    let peer = build_peer(&config, name).await;
+    let peer_port = peer.port;
+    let session = Session::restore(peer, config.paths.allocation, state_dir, saved).await;
+    let server = session.clone();
+    tokio::spawn(async move {
+        if let Err(e) = server.start_server().await {
+            error!(error = %e, "Erro no servidor de peers");
+        }
+    });
+
+    let (sender, receiver) = mpsc::channel(100);
+    let chat_server = ChatServer::new(sender);
+    let chat_port = config.peer.chat_port(peer_port);
+    let chat = chat_server.clone();
+    tokio::spawn(async move {
+        if let Err(e) = chat.start_chat_server(chat_port).await {
+            error!(error = %e, "Erro no servidor de chat");
+        }
+    });
+    start_api(&config, &session, Some(chat_server)).await?;
+
+    tui::run(screen, session.clone(), config, receiver).await?;
+    info!("Encerrando: anunciando stopped e gravando a retomada");
+    session.shutdown().await;
+    Ok(())
+}
+
+async fn run_daemon(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(log_file) = &config.daemon.log_file {
+        redirect_output(log_file).map_err(|e| format!("erro ao abrir o log {}: {}", log_file.display(), e))?;
+    }
+    info!(pid = std::process::id(), "Daemon iniciado");
+
+    let state_dir = config.paths.state_dir.clone();
+    let saved = load_session_state(&state_dir).await;
+    let name = config.peer.name.clone().or_else(|| saved.peer_name.clone()).unwrap_or_else(|| "daemon".to_string());
+    let restored = saved.torrents.len();
+    let peer = build_peer(&config, name).await;
+    let session = Session::restore(peer, config.paths.allocation, state_dir, saved).await;
+    info!(restored, "Torrents restaurados da sessão anterior");
+    let server = session.clone();
+    tokio::spawn(async move {
+        if let Err(e) = server.start_server().await {
+            error!(error = %e, "Erro no servidor de peers");
+            std::process::exit(1);
+        }
+    });
+    start_api(&config, &session, None).await?;
+
+    // Os torrents da configuração que já vieram da sessão anterior são ignorados
+    let configured = config.daemon.torrents.iter().map(|source| {
+        TorrentSource::parse(source).map(|source| (source, config.paths.download_dir.clone()))
+    });
+    for source in configured {
+        let result = match source {
+            Ok((source, download_dir)) => source.add_to(&session, download_dir).await.map(|_| ()),
+            Err(e) => Err(e.into()),
+        };
+        log_add_error(result);
+    }
+    for path in &config.daemon.seed {
+        log_add_error(add_seed_path(&session, &config, path.clone()).await);
+    }
+
+    let mut status = tokio::time::interval(DAEMON_STATUS_INTERVAL);
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    loop {
+        tokio::select! {
+            result = &mut signal => {
+                result?;
+                break;
+            }
+            _ = status.tick() => {
+                for status in session.list().await {
+                    info!(
+                        torrent = %status.name,
+                        info_hash = %status.info_hash,
+                        state = %status.state,
+                        pieces = status.pieces.0,
+                        piece_count = status.pieces.1,
+                        peers = status.peers,
+                        "Estado do torrent"
+                    );
+                }
+            }
+        }
+    }
+
+    info!("Encerrando: anunciando stopped e gravando a retomada");
+    session.shutdown().await;
+    info!("Daemon encerrado");
+    Ok(())
+}
+
+fn log_add_error(result: Result<(), Box<dyn std::error::Error>>) {
+    if let Err(e) = result {
+        if !matches!(e.downcast_ref::<SessionError>(), Some(SessionError::AlreadyAdded(_))) {
+            error!(error = %e, "Erro ao adicionar torrent");
+        }
+    }
+}
+
+async fn run_peer(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let state_dir = config.paths.state_dir.clone();
+    let saved = load_session_state(&state_dir).await;
+
+    let peer_name = match config.peer.name.clone().or_else(|| saved.peer_name.clone()) {
+        Some(name) => {
+            println!("Bem-vindo, {}!", name);
+            name
+        }
+        None => {
+            print!("Digite seu nome de peer: ");
+            io::stdout().flush().unwrap();
+            let mut peer_name = String::new();
+            io::stdin().read_line(&mut peer_name).unwrap();
+            peer_name.trim().to_string()
+        }
+    };
+
+    // Com torrents restaurados, nada novo é compartilhado na partida
+    let mut file_choice = None;
+    let files = list_local_files(&config.paths.share_dirs);
+    if saved.torrents.is_empty() {
+        // Lista arquivos dos diretórios de compartilhamento
         println!("\nArquivos disponíveis para compartilhar:");
         for (index, (file_name, path)) in files.iter().enumerate() {
             println!("{}: {} ({})", index, file_name, path.display());
@@ -40,135 +752,256 @@ async fn main() {
 
         print!("\nEscolha o número do arquivo para compartilhar: ");
         io::stdout().flush().unwrap();
-        let mut file_choice = String::new();
-        io::stdin().read_line(&mut file_choice).unwrap();
-        let file_choice: usize = file_choice.trim().parse().unwrap_or(0);
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice).unwrap();
+        file_choice = Some(choice.trim().parse().unwrap_or(0));
+    }
 
-        let shared_files = if file_choice < files.len() {
-            println!("Compartilhando arquivo: {}", files[file_choice].1.display());
-            vec![files[file_choice].1.to_string_lossy().to_string()]
-        } else {
-            println!("Índice inválido, nenhum arquivo será compartilhado.");
-            vec![]
-        };
+    let peer = build_peer(&config, peer_name).await;
+    let peer_port = peer.port;
+    let restored = saved.torrents.len();
+    let session = Session::restore(peer, config.paths.allocation, state_dir, saved).await;
+    let peer = session.peer();
+    if restored > 0 {
+        println!("{} torrents restaurados da sessão anterior; veja com 'files'.", restored);
+    }
 
-        let peer_port: u16 = 6882 + rand::random::<u16>() % 1000;
-        let peer = Arc::new(Peer::new(
-            "127.0.0.1".to_string(),
-            peer_port,
-            shared_files,
-            peer_name.clone(),
-        ));
-
-        // Registrar o peer no tracker
-        peer.register_with_tracker("127.0.0.1", 6881).await.unwrap();
-
-        let peer_clone = Arc::clone(&peer);
-        tokio::spawn(async move {
-            peer_clone.start_server().await.unwrap();
-        });
-
-        // Criação do canal para comunicação das mensagens
-        let (sender, receiver) = mpsc::channel(100);
-        let chat_server = ChatServer::new(sender);
-
-        tokio::spawn(async move {
-            chat_server.start_chat_server(peer_port + 1000).await.unwrap();
-        });
-
-        // Escuta de mensagens em paralelo
-        tokio::spawn(async move {
-            message_receiver(receiver).await;
-        });
-
-        // Comandos no terminal
-        println!("\nComandos disponíveis:");
-        println!("- 'list': lista peers conectados");
-        println!("- 'files': lista arquivos disponíveis na rede");
-        println!("- 'chat': inicia chat com outro peer");
-        println!("- 'download': baixa um arquivo");
-        println!("- 'exit': sair");
-
-        loop {
-            let mut command = String::new();
-            io::stdin().read_line(&mut command).unwrap();
-            let command = command.trim().to_string();
-
-            match command.as_str() {
-                "list" => {
-                    let peers = peer.get_peers_from_tracker("127.0.0.1", 6881).await.unwrap();
-                    println!("Peers conectados: {:?}", peers);
-                }
-                "files" => {
-                    let peers = peer.get_peers_from_tracker("127.0.0.1", 6881).await.unwrap();
-                    match peer.list_network_files(peers).await {
-                        Ok(files) => {
-                            println!("\nArquivos disponíveis na rede:");
-                            for (index, (file, peer_addr)) in files.iter().enumerate() {
-                                println!("{}: {} (disponível em {})", index, file, peer_addr);
-                            }
+    if let Some(file_choice) = file_choice.filter(|&index| index < files.len()) {
+        let path = files[file_choice].1.clone();
+        match seed_file(path.clone(), config.tracker.announce.clone()).await {
+            Ok(torrent) => {
+                println!("Compartilhando arquivo: {}", path.display());
+                if let Err(e) = session.add_torrent(torrent).await {
+                    println!("{}", e);
+                }
+            }
+            Err(e) => println!("Erro ao gerar o torrent: {}", e),
+        }
+    } else if file_choice.is_some() {
+        println!("Índice inválido, nenhum arquivo será compartilhado.");
+    }
+
+    let server = session.clone();
+    tokio::spawn(async move {
+        if let Err(e) = server.start_server().await {
+            error!(error = %e, "Erro no servidor de peers");
+            std::process::exit(1);
+        }
+    });
+
+    // Criação do canal para comunicação das mensagens
+    let (sender, receiver) = mpsc::channel(100);
+    let chat_server = ChatServer::new(sender);
+    if let Some(url) = start_api(&config, &session, Some(chat_server.clone())).await? {
+        println!("Interface web: {}", url);
+    }
+
+    let chat_port = config.peer.chat_port(peer_port);
+    println!("Chat escutando na porta {}", chat_port);
+    tokio::spawn(async move {
+        if let Err(e) = chat_server.start_chat_server(chat_port).await {
+            error!(error = %e, "Erro no servidor de chat");
+        }
+    });
+
+    // Escuta de mensagens em paralelo
+    tokio::spawn(async move {
+        message_receiver(receiver).await;
+    });
+
+    // Comandos no terminal
+    println!("\nComandos disponíveis:");
+    print_commands();
+
+    loop {
+        let mut command = String::new();
+        io::stdin().read_line(&mut command).unwrap();
+        let command = command.trim().to_string();
+
+        match command.as_str() {
+            "list" => {
+                let torrents: Vec<Arc<Torrent>> = peer.torrents.lock().await.values().cloned().collect();
+                for torrent in torrents {
+                    println!("\n{} [{}]", torrent.metainfo.info.name, torrent.info_hash());
+                    println!("Peers conectados: {:?}", torrent.peer_addrs().await);
+                    match peer.announce(&torrent, Event::None).await {
+                        Ok(response) => println!(
+                            "Peers no tracker: {:?} ({} completos, {} baixando)",
+                            response.peers,
+                            response.complete.unwrap_or(0),
+                            response.incomplete.unwrap_or(0)
+                        ),
+                        Err(e) => println!("Erro ao consultar o tracker: {}", e),
+                    }
+                }
+            }
+            "files" => {
+                println!("\nTorrents:");
+                for status in session.list().await {
+                    println!(
+                        "{} [{}] {}: {}/{} peças, {} peers (enviados {} bytes, recebidos {} bytes)",
+                        status.name,
+                        status.info_hash,
+                        status.state,
+                        status.pieces.0,
+                        status.pieces.1,
+                        status.peers,
+                        status.total_uploaded,
+                        status.total_downloaded,
+                    );
+                }
+            }
+            "chat" => {
+                print!("Digite a porta de chat do peer: ");
+                io::stdout().flush().unwrap();
+                let mut input = String::new();
+                io::stdin().read_line(&mut input).unwrap();
+                let Some(chat_port) = input.trim().parse::<u16>().ok().filter(|&port| port != 0) else {
+                    println!("Porta inválida!");
+                    continue;
+                };
+                if let Err(e) = start_chat_client(chat_port).await {
+                    println!("Erro no chat: {}", e);
+                }
+            }
+            "download" => {
+                let Some(source) = choose_torrent(&config.paths.share_dirs) else {
+                    println!("Nenhum torrent escolhido.");
+                    continue;
+                };
+                let added = source.add_to(&session, config.paths.download_dir.clone()).await;
+                match added {
+                    Ok(info_hash) => println!("Torrent {} adicionado; acompanhe com 'files'.", info_hash),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "pause" => {
+                if let Some(info_hash) = choose_session_torrent(&session).await {
+                    match session.pause(&info_hash).await {
+                        Ok(()) => println!("Torrent pausado."),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+            "resume" => {
+                if let Some(info_hash) = choose_session_torrent(&session).await {
+                    match session.resume(&info_hash).await {
+                        Ok(()) => println!("Torrent retomado."),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+            "remove" => {
+                let Some(info_hash) = choose_session_torrent(&session).await else {
+                    continue;
+                };
+                print!("Apagar também os arquivos baixados? (s/N): ");
+                io::stdout().flush().unwrap();
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer).unwrap();
+                let delete_files = answer.trim().eq_ignore_ascii_case("s");
+                match session.remove(&info_hash, delete_files).await {
+                    Ok(()) => println!("Torrent removido."),
+                    Err(e) => println!("Erro ao remover o torrent: {}", e),
+                }
+            }
+            "recheck" => {
+                let Some(info_hash) = choose_session_torrent(&session).await else {
+                    continue;
+                };
+                let Some(torrent) = session.torrent(&info_hash).await else {
+                    println!("O torrent ainda não foi aberto.");
+                    continue;
+                };
+                println!("Reverificando {}...", torrent.metainfo.info.name);
+                match torrent.recheck().await {
+                    Ok(result) => {
+                        println!("{} de {} peças válidas", result.valid, result.checked);
+                        if !result.bad.is_empty() {
+                            println!("Peças inválidas, marcadas para baixar de novo: {:?}", result.bad);
+                        }
+                        if !result.recovered.is_empty() {
+                            println!("Peças encontradas no disco: {:?}", result.recovered);
                         }
-                        Err(e) => println!("Erro ao listar arquivos: {}", e)
                     }
+                    Err(e) => println!("Erro na reverificação: {}", e),
                 }
-                "chat" => {
-                    print!("Digite o número da porta do peer para iniciar o chat: ");
-                    io::stdout().flush().unwrap();
-                    let mut target_port_str = String::new();
-                    io::stdin().read_line(&mut target_port_str).unwrap();
-                    let target_port: u16 = target_port_str.trim().parse().unwrap();
-                    
-                    start_chat_client(target_port + 1000).await.unwrap();
-                }
-                "download" => {
-                    let peers = peer.get_peers_from_tracker("127.0.0.1", 6881).await.unwrap();
-                    
-                    // Primeiro lista os arquivos disponíveis
-                    match peer.list_network_files(peers.clone()).await {
-                        Ok(files) => {
-                            println!("\nArquivos disponíveis para download:");
-                            for (index, (file, peer_addr)) in files.iter().enumerate() {
-                                println!("{}: {} (em {})", index, file, peer_addr);
-                            }
-                            
-                            print!("\nDigite o número do arquivo que deseja baixar: ");
-                            io::stdout().flush().unwrap();
-                            let mut choice = String::new();
-                            io::stdin().read_line(&mut choice).unwrap();
-                            let choice = choice.trim();
-                            
-                            if let Ok(index) = choice.parse::<usize>() {
-                                if index < files.len() {
-                                    let (file_name, peer_addr) = &files[index];
-                                    println!("Iniciando download de {} do peer {}", file_name, peer_addr);
-                                    match (*peer).download_blocks_from_peers(vec![peer_addr.clone()], file_name).await {
-                                        Ok(_) => println!("Download concluído com sucesso!"),
-                                        Err(e) => println!("Erro no download: {}", e)
-                                    }
-                                } else {
-                                    println!("Índice inválido!");
+            }
+            "slots" => {
+                print!("Slots de unchoke por torrent (atual: {}): ", peer.unchoke_slots());
+                io::stdout().flush().unwrap();
+                let mut choice = String::new();
+                io::stdin().read_line(&mut choice).unwrap();
+                match choice.trim().parse::<usize>() {
+                    Ok(slots) => {
+                        peer.set_unchoke_slots(slots);
+                        println!("Slots de unchoke: {}", slots);
+                    }
+                    Err(_) => println!("Número inválido!"),
+                }
+            }
+            "limit" => {
+                let statuses = session.list().await;
+                println!("\nNíveis de limite:");
+                println!("global: todas as conexões");
+                println!("peer: cada conexão individualmente");
+                for (index, status) in statuses.iter().enumerate() {
+                    println!("{}: {} [{}]", index, status.name, status.info_hash);
+                }
+
+                print!("\nEscolha 'global', 'peer' ou o número do torrent: ");
+                io::stdout().flush().unwrap();
+                let mut choice = String::new();
+                io::stdin().read_line(&mut choice).unwrap();
+
+                match choice.trim() {
+                    "global" => read_limits(&peer.global_limits),
+                    "peer" => read_limits(&peer.peer_limits),
+                    choice => match choice.parse::<usize>() {
+                        Ok(index) if index < statuses.len() => match session.torrent(&statuses[index].info_hash).await {
+                            Some(torrent) => read_limits(&torrent.limits),
+                            None => println!("O torrent ainda não foi aberto."),
+                        },
+                        _ => println!("Opção inválida!"),
+                    },
+                }
+            }
+            "create-torrent" => {
+                let files = list_local_files(&config.paths.share_dirs);
+                println!("\nArquivos locais:");
+                for (index, (file_name, path)) in files.iter().enumerate() {
+                    println!("{}: {} ({})", index, file_name, path.display());
+                }
+
+                print!("\nEscolha o número do arquivo: ");
+                io::stdout().flush().unwrap();
+                let mut choice = String::new();
+                io::stdin().read_line(&mut choice).unwrap();
+
+                match choice.trim().parse::<usize>() {
+                    Ok(index) if index < files.len() => {
+                        match seed_file(files[index].1.clone(), config.tracker.announce.clone()).await {
+                            Ok(torrent) => {
+                                if let Err(e) = session.add_torrent(torrent).await {
+                                    println!("{}", e);
                                 }
-                            } else {
-                                println!("Por favor, digite um número válido!");
                             }
+                            Err(e) => println!("Erro ao gerar o torrent: {}", e),
                         }
-                        Err(e) => println!("Erro ao listar arquivos: {}", e)
                     }
+                    _ => println!("Índice inválido!"),
                 }
-                "exit" => {
-                    peer.unregister_from_tracker("127.0.0.1", 6881).await.unwrap();
-                    println!("Desconectando do tracker...");
-                    break;
-                }
-                _ => {
-                    println!("Comando desconhecido. Comandos disponíveis:");
-                    println!("- 'list': lista peers conectados");
-                    println!("- 'files': lista arquivos disponíveis na rede");
-                    println!("- 'chat': inicia chat com outro peer");
-                    println!("- 'download': baixa um arquivo");
-                    println!("- 'exit': sair");
-                }
+            }
+            "exit" => {
+                println!("Desconectando do tracker...");
+                session.shutdown().await;
+                break;
+            }
+            _ => {
+                println!("Comando desconhecido. Comandos disponíveis:");
+                print_commands();
             }
         }
     }
-} 
\ No newline at end of file
+    Ok(())
+}
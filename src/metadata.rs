@@ -0,0 +1,103 @@
+﻿//! Download do dicionário `info` de um peer via ut_metadata (BEP 9), usado
+//! quando o torrent foi aberto por um link magnet e só temos o info-hash.
+
+use crate::codec::Framed;
+use crate::extension::{self, ExtendedHandshake, MetadataMessage};
+use crate::metainfo::InfoHash;
+use crate::wire::{Message, MessageCodec};
+use futures_util::{SinkExt, StreamExt};
+use sha1::{Digest, Sha1};
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Tempo máximo de espera por cada mensagem do peer.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Metadados sendo montados a partir dos pedaços recebidos.
+struct Assembly {
+    data: Vec<u8>,
+    received: Vec<bool>,
+}
+
+/// Pede os metadados ao peer numa conexão já com handshake e confere o hash.
+///
+/// Retorna os bytes do dicionário `info`; mensagens que não são de metadados
+/// (bitfield, have...) são ignoradas.
+pub async fn fetch(stream: TcpStream, info_hash: InfoHash, listen_port: u16) -> io::Result<Vec<u8>> {
+    let mut framed = Framed::new(stream, MessageCodec::default());
+    let mut handshake = ExtendedHandshake {
+        port: Some(listen_port),
+        client: Some(extension::CLIENT_NAME.to_string()),
+        ..Default::default()
+    };
+    handshake.extensions.insert(extension::UT_METADATA.to_string(), extension::UT_METADATA_ID);
+    framed.send(Message::Extended { id: extension::HANDSHAKE_ID, payload: handshake.encode() }).await?;
+
+    let mut remote_id = None;
+    let mut assembly: Option<Assembly> = None;
+    loop {
+        let message = timeout(MESSAGE_TIMEOUT, framed.next())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "peer não enviou os metadados a tempo"))?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "peer encerrou a conexão"))??;
+        let Message::Extended { id, payload } = message else {
+            continue;
+        };
+
+        match id {
+            extension::HANDSHAKE_ID if assembly.is_none() => {
+                let remote = ExtendedHandshake::decode(&payload)?;
+                let (Some(id), Some(size)) = (remote.extension_id(extension::UT_METADATA), remote.metadata_size) else {
+                    return Err(invalid("peer não oferece ut_metadata"));
+                };
+                let pieces = extension::metadata_pieces(size);
+                for piece in 0..pieces {
+                    let request = MetadataMessage::Request { piece };
+                    framed.send(Message::Extended { id, payload: request.encode() }).await?;
+                }
+                remote_id = Some(id);
+                assembly = Some(Assembly { data: vec![0; size], received: vec![false; pieces] });
+            }
+            extension::UT_METADATA_ID => match MetadataMessage::decode(&payload)? {
+                MetadataMessage::Data { piece, total_size, data } => {
+                    let Some(assembly) = assembly.as_mut() else {
+                        continue;
+                    };
+                    if total_size != assembly.data.len() || piece >= assembly.received.len() {
+                        return Err(invalid("pedaço de metadados inconsistente"));
+                    }
+                    let start = piece * extension::METADATA_PIECE_LEN;
+                    let end = (start + extension::METADATA_PIECE_LEN).min(total_size);
+                    if data.len() != end - start {
+                        return Err(invalid("pedaço de metadados com tamanho inválido"));
+                    }
+                    assembly.data[start..end].copy_from_slice(&data);
+                    assembly.received[piece] = true;
+
+                    if assembly.received.iter().all(|&received| received) {
+                        let hash: [u8; 20] = Sha1::digest(&assembly.data).into();
+                        if hash != info_hash.0 {
+                            return Err(invalid("metadados recebidos não conferem com o info-hash"));
+                        }
+                        return Ok(std::mem::take(&mut assembly.data));
+                    }
+                }
+                MetadataMessage::Reject { .. } => return Err(invalid("peer recusou o pedido de metadados")),
+                // Ainda não temos os metadados para oferecer
+                MetadataMessage::Request { piece } => {
+                    if let Some(id) = remote_id {
+                        let reject = MetadataMessage::Reject { piece };
+                        framed.send(Message::Extended { id, payload: reject.encode() }).await?;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,460 @@
+﻿use crate::bencode::{self, Decoder, Token};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Tamanho de um hash SHA-1.
+pub const HASH_LEN: usize = 20;
+
+/// Identificador de um torrent: SHA-1 do dicionário `info` codificado.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHash(pub [u8; HASH_LEN]);
+
+impl InfoHash {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(InfoHash)
+    }
+
+    pub fn from_hex(s: &str) -> Option<Self> {
+        hex::decode(s).ok().and_then(|b| Self::from_bytes(&b))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_LEN] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InfoHash({})", self.to_hex())
+    }
+}
+
+/// Arquivo de um torrent com múltiplos arquivos.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub length: u64,
+    pub path: Vec<String>,
+}
+
+/// Dicionário `info` do .torrent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Info {
+    pub name: String,
+    #[serde(rename = "piece length")]
+    pub piece_length: u64,
+    /// Hashes SHA-1 de todas as peças, concatenados.
+    #[serde(with = "serde_bytes")]
+    pub pieces: Vec<u8>,
+    /// Presente apenas em torrents de arquivo único.
+    pub length: Option<u64>,
+    /// Presente apenas em torrents com múltiplos arquivos.
+    pub files: Option<Vec<FileEntry>>,
+    pub private: Option<u8>,
+}
+
+impl Info {
+    /// Tamanho total do conteúdo em bytes.
+    pub fn total_length(&self) -> u64 {
+        match (&self.files, self.length) {
+            (Some(files), _) => files.iter().map(|f| f.length).sum(),
+            (None, Some(length)) => length,
+            (None, None) => 0,
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len() / HASH_LEN
+    }
+
+    /// Hash esperado de uma peça.
+    pub fn piece_hash(&self, index: usize) -> Option<&[u8]> {
+        self.pieces.get(index * HASH_LEN..(index + 1) * HASH_LEN)
+    }
+
+    /// Tamanho de uma peça; a última pode ser menor que `piece_length`.
+    pub fn piece_size(&self, index: usize) -> u64 {
+        let start = index as u64 * self.piece_length;
+        self.total_length().saturating_sub(start).min(self.piece_length)
+    }
+
+    pub fn is_multi_file(&self) -> bool {
+        self.files.is_some()
+    }
+
+    /// Lista os arquivos com caminhos relativos ao diretório de destino.
+    ///
+    /// Em torrents com múltiplos arquivos o `name` é o diretório raiz.
+    pub fn file_list(&self) -> Vec<(PathBuf, u64)> {
+        match &self.files {
+            Some(files) => files
+                .iter()
+                .map(|f| {
+                    let mut path = PathBuf::from(&self.name);
+                    path.extend(&f.path);
+                    (path, f.length)
+                })
+                .collect(),
+            None => vec![(PathBuf::from(&self.name), self.length.unwrap_or(0))],
+        }
+    }
+
+    fn validate(&self) -> Result<(), MetainfoError> {
+        if !is_safe_component(&self.name) {
+            return Err(MetainfoError::Invalid("nome inválido"));
+        }
+        if self.piece_length == 0 {
+            return Err(MetainfoError::Invalid("'piece length' deve ser positivo"));
+        }
+        if !self.pieces.len().is_multiple_of(HASH_LEN) {
+            return Err(MetainfoError::Invalid("'pieces' não é múltiplo de 20 bytes"));
+        }
+        match (&self.files, self.length) {
+            (Some(files), None) => {
+                if files.is_empty() {
+                    return Err(MetainfoError::Invalid("'files' está vazio"));
+                }
+                for file in files {
+                    if file.path.is_empty() || !file.path.iter().all(|c| is_safe_component(c)) {
+                        return Err(MetainfoError::Invalid("caminho de arquivo inválido"));
+                    }
+                }
+            }
+            (None, Some(_)) => {}
+            _ => return Err(MetainfoError::Invalid("é preciso exatamente um entre 'length' e 'files'")),
+        }
+        let expected = self.total_length().div_ceil(self.piece_length);
+        if expected != self.piece_count() as u64 {
+            return Err(MetainfoError::Invalid("quantidade de peças não confere com o tamanho"));
+        }
+        Ok(())
+    }
+}
+
+/// Impede que um .torrent malicioso escreva fora do diretório de destino.
+fn is_safe_component(component: &str) -> bool {
+    let mut components = Path::new(component).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawMetainfo {
+    announce: Option<String>,
+    #[serde(rename = "announce-list")]
+    announce_list: Option<Vec<Vec<String>>>,
+    comment: Option<String>,
+    #[serde(rename = "created by")]
+    created_by: Option<String>,
+    #[serde(rename = "creation date")]
+    creation_date: Option<i64>,
+    info: Info,
+}
+
+/// Conteúdo de um arquivo .torrent.
+#[derive(Debug, Clone)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    /// Trackers em camadas (BEP 12).
+    pub announce_list: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub creation_date: Option<i64>,
+    pub info: Info,
+    pub info_hash: InfoHash,
+    /// Bytes originais do dicionário `info`, usados no cálculo do hash.
+    info_bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum MetainfoError {
+    Io(io::Error),
+    Bencode(bencode::Error),
+    MissingInfo,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for MetainfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetainfoError::Io(e) => write!(f, "erro de E/S: {}", e),
+            MetainfoError::Bencode(e) => write!(f, "bencode inválido: {}", e),
+            MetainfoError::MissingInfo => write!(f, "dicionário 'info' ausente"),
+            MetainfoError::Invalid(msg) => write!(f, "metainfo inválido: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MetainfoError {}
+
+impl From<io::Error> for MetainfoError {
+    fn from(e: io::Error) -> Self {
+        MetainfoError::Io(e)
+    }
+}
+
+impl From<bencode::Error> for MetainfoError {
+    fn from(e: bencode::Error) -> Self {
+        MetainfoError::Bencode(e)
+    }
+}
+
+impl Metainfo {
+    /// Interpreta o conteúdo de um arquivo .torrent.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MetainfoError> {
+        let info_range = find_info(data)?.ok_or(MetainfoError::MissingInfo)?;
+        let raw: RawMetainfo = bencode::from_bytes(data)?;
+        raw.info.validate()?;
+
+        let info_bytes = data[info_range].to_vec();
+        Ok(Self {
+            announce: raw.announce,
+            announce_list: raw.announce_list.unwrap_or_default(),
+            comment: raw.comment,
+            created_by: raw.created_by,
+            creation_date: raw.creation_date,
+            info: raw.info,
+            info_hash: hash_info(&info_bytes),
+            info_bytes,
+        })
+    }
+
+    /// Monta um metainfo a partir de um dicionário `info` já codificado.
+    pub fn from_info_bytes(info_bytes: Vec<u8>) -> Result<Self, MetainfoError> {
+        let info: Info = bencode::from_bytes(&info_bytes)?;
+        info.validate()?;
+        Ok(Self {
+            announce: None,
+            announce_list: Vec::new(),
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            info,
+            info_hash: hash_info(&info_bytes),
+            info_bytes,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MetainfoError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Codifica o .torrent preservando os bytes originais do `info`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let raw = RawMetainfo {
+            announce: self.announce.clone(),
+            announce_list: (!self.announce_list.is_empty()).then(|| self.announce_list.clone()),
+            comment: self.comment.clone(),
+            created_by: self.created_by.clone(),
+            creation_date: self.creation_date,
+            info: self.info.clone(),
+        };
+        let mut value = bencode::to_value(&raw).expect("metainfo sempre é serializável");
+        if let bencode::Value::Dict(dict) = &mut value {
+            // Como só aceitamos bencode canônico, decodificar e recodificar o `info`
+            // reproduz exatamente os bytes originais (e portanto o mesmo hash)
+            let info = bencode::decode(&self.info_bytes).expect("info já validado");
+            dict.insert(b"info".to_vec(), info);
+        }
+        bencode::encode(&value)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn info_bytes(&self) -> &[u8] {
+        &self.info_bytes
+    }
+
+    /// Todos os trackers conhecidos, na ordem de preferência e sem repetições.
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers: Vec<String> = Vec::new();
+        for url in self.announce_list.iter().flatten().chain(self.announce.iter()) {
+            if !trackers.contains(url) {
+                trackers.push(url.clone());
+            }
+        }
+        trackers
+    }
+
+    /// Gera o metainfo de um arquivo ou diretório, calculando o hash de cada peça.
+    ///
+    /// Leitura síncrona; em contexto assíncrono use `spawn_blocking`.
+    pub fn create(path: &Path, piece_length: Option<u64>, trackers: Vec<String>) -> Result<Self, MetainfoError> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or(MetainfoError::Invalid("caminho sem nome de arquivo"))?;
+
+        let (sources, files) = if path.is_dir() {
+            let mut sources = Vec::new();
+            collect_files(path, path, &mut sources)?;
+            sources.sort();
+            let mut files = Vec::new();
+            for (relative, full) in &sources {
+                files.push(FileEntry {
+                    length: fs::metadata(full)?.len(),
+                    path: relative.clone(),
+                });
+            }
+            let sources = sources.into_iter().map(|(_, full)| full).collect();
+            (sources, Some(files))
+        } else {
+            (vec![path.to_path_buf()], None)
+        };
+
+        let total: u64 = match &files {
+            Some(files) => files.iter().map(|f| f.length).sum(),
+            None => fs::metadata(path)?.len(),
+        };
+        if total == 0 {
+            return Err(MetainfoError::Invalid("não há dados para compartilhar"));
+        }
+        let piece_length = piece_length.unwrap_or_else(|| suggested_piece_length(total));
+        if piece_length == 0 {
+            return Err(MetainfoError::Invalid("'piece length' deve ser positivo"));
+        }
+        let pieces = hash_pieces(&sources, piece_length)?;
+
+        let info = Info {
+            name,
+            piece_length,
+            pieces,
+            length: files.is_none().then_some(total),
+            files,
+            private: None,
+        };
+        let info_bytes = bencode::to_bytes(&info)?;
+
+        let creation_date = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .ok();
+
+        Ok(Self {
+            announce: trackers.first().cloned(),
+            announce_list: if trackers.len() > 1 { trackers.into_iter().map(|t| vec![t]).collect() } else { Vec::new() },
+            comment: None,
+            created_by: Some(format!("bittorrent_client/{}", env!("CARGO_PKG_VERSION"))),
+            creation_date,
+            info,
+            info_hash: hash_info(&info_bytes),
+            info_bytes,
+        })
+    }
+}
+
+/// Tamanho de peça que resulta em aproximadamente 1500 peças, entre 16 KiB e 16 MiB.
+pub fn suggested_piece_length(total_length: u64) -> u64 {
+    let mut piece_length = 16 * 1024;
+    while piece_length < 16 * 1024 * 1024 && total_length / piece_length > 1500 {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+fn hash_info(info_bytes: &[u8]) -> InfoHash {
+    InfoHash(Sha1::digest(info_bytes).into())
+}
+
+/// Localiza o intervalo de bytes do valor `info` no dicionário principal.
+fn find_info(data: &[u8]) -> Result<Option<std::ops::Range<usize>>, bencode::Error> {
+    let mut decoder = Decoder::new(data);
+    if decoder.next_token()? != Token::Dict {
+        return Ok(None);
+    }
+    loop {
+        match decoder.next_token()? {
+            Token::Bytes(b"info") => return decoder.skip_value().map(Some),
+            Token::Bytes(_) => {
+                decoder.skip_value()?;
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<(Vec<String>, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(root, &path, out)?;
+        } else if file_type.is_file() {
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            out.push((relative, path));
+        }
+    }
+    Ok(())
+}
+
+/// Calcula os hashes das peças lendo os arquivos em sequência, como um fluxo contínuo.
+fn hash_pieces(sources: &[PathBuf], piece_length: u64) -> io::Result<Vec<u8>> {
+    let mut pieces = Vec::new();
+    let mut hasher = Sha1::new();
+    let mut filled: u64 = 0;
+    let mut buffer = vec![0; 64 * 1024];
+
+    for source in sources {
+        let mut file = File::open(source)?;
+        loop {
+            let want = (piece_length - filled).min(buffer.len() as u64) as usize;
+            let n = file.read(&mut buffer[..want])?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            filled += n as u64;
+            if filled == piece_length {
+                pieces.extend_from_slice(&hasher.finalize_reset());
+                filled = 0;
+            }
+        }
+    }
+
+    if filled > 0 {
+        pieces.extend_from_slice(&hasher.finalize());
+    }
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_rejects_zero_piece_length_and_empty_payload() {
+        let dir = std::env::temp_dir().join(format!("bt-metainfo-{}", hex::encode(rand::random::<[u8; 8]>())));
+        fs::create_dir_all(dir.join("vazio")).unwrap();
+        fs::write(dir.join("zero.bin"), b"").unwrap();
+        fs::write(dir.join("dados.bin"), b"0123456789").unwrap();
+
+        let invalid = |path: &Path, piece_length| matches!(Metainfo::create(path, piece_length, Vec::new()), Err(MetainfoError::Invalid(_)));
+        assert!(invalid(&dir.join("dados.bin"), Some(0)));
+        assert!(invalid(&dir.join("zero.bin"), None));
+        assert!(invalid(&dir.join("vazio"), Some(4)));
+
+        let metainfo = Metainfo::create(&dir.join("dados.bin"), Some(4), Vec::new()).unwrap();
+        assert_eq!(metainfo.info.piece_count(), 3);
+        assert_eq!(metainfo.info.total_length(), 10);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,304 +1,604 @@
 ﻿use tokio::net::{TcpStream, TcpListener};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::fs::File;
-use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::timeout;
+use std::collections::HashMap;
 use std::fs::read_dir;
-use sha2::{Sha256, Digest};
-use hex;
+use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::announce::{self, AnnounceError, AnnounceRequest, AnnounceResponse, Event};
+use crate::choker::{self, Choker};
+use crate::connection::{self, Settings};
+use crate::dht::Dht;
+use crate::lsd::{self, Lsd};
+use crate::magnet::Magnet;
+use crate::metadata;
+use crate::metainfo::{InfoHash, Metainfo};
+use crate::ratelimit::RateLimits;
+use crate::torrent::Torrent;
+use crate::wire::{self, Handshake, PeerId};
+use tracing::{debug, info, warn, Instrument};
+
+/// Tempo máximo para conectar e trocar handshakes com outro peer.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Espera antes de tentar de novo quando nenhum tracker responde, em segundos.
+const RETRY_INTERVAL: u32 = 60;
+
+/// Quantos peers são consultados ao mesmo tempo pelos metadados de um link magnet.
+const METADATA_PEERS: usize = 8;
+
+/// `left` anunciado enquanto o tamanho do torrent é desconhecido (link magnet);
+/// qualquer valor diferente de zero evita que sejamos contados como seeder.
+const UNKNOWN_LEFT: u64 = 16 * 1024;
 
 #[derive(Clone)]
 pub struct Peer {
     pub ip: String,
     pub port: u16,
-    pub shared_files: Vec<String>,
     pub name: String,
+    pub peer_id: PeerId,
+    /// Torrents que este peer baixa ou semeia, indexados pelo info-hash.
+    pub torrents: Arc<Mutex<HashMap<InfoHash, Arc<Torrent>>>>,
+    /// Tarefa do choker de cada torrent registrado, abortada quando ele sai.
+    chokers: Arc<Mutex<HashMap<InfoHash, AbortHandle>>>,
+    /// Nó DHT usado para achar peers sem tracker, se habilitado.
+    pub dht: Option<Dht>,
+    /// Descoberta de peers na rede local (BEP 14), se habilitada.
+    pub lsd: Option<Lsd>,
+    /// Peers unchoked ao mesmo tempo em cada torrent, incluindo o otimista.
+    unchoke_slots: Arc<AtomicUsize>,
+    /// Limites de banda somando todos os torrents.
+    pub global_limits: Arc<RateLimits>,
+    /// Limites aplicados a cada conexão com um peer.
+    pub peer_limits: Arc<RateLimits>,
 }
 
 impl Peer {
-    pub fn new(ip: String, port: u16, shared_files: Vec<String>, name: String) -> Self {
+    pub fn new(ip: String, port: u16, name: String) -> Self {
         Self {
             ip,
             port,
-            shared_files,
             name,
+            peer_id: wire::generate_peer_id(),
+            torrents: Arc::new(Mutex::new(HashMap::new())),
+            chokers: Arc::new(Mutex::new(HashMap::new())),
+            dht: None,
+            lsd: None,
+            unchoke_slots: Arc::new(AtomicUsize::new(choker::DEFAULT_UNCHOKE_SLOTS)),
+            global_limits: Arc::new(RateLimits::default()),
+            peer_limits: Arc::new(RateLimits::default()),
         }
     }
 
-    fn calculate_checksum(data: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let result = hasher.finalize();
-        hex::encode(result)
-    }
-
-    pub async fn send_file_in_blocks(&self, file_path: &str, socket: &mut TcpStream) -> std::io::Result<()> {
-        let mut file = File::open(file_path).await?;
-        let mut buffer = vec![0; 1024 * 1024]; // 1MB por bloco
-        let mut block_id = 0;
-
-        while let Ok(n) = file.read(&mut buffer).await {
-            if n == 0 { break; }
-            println!("Lido {} bytes do arquivo original.", n);
-            let block = &buffer[..n];
-            let checksum = Self::calculate_checksum(block);
-            let block_header = format!("BLOCK {} {}", block_id, checksum);
-            socket.write_all(block_header.as_bytes()).await?;
-            socket.write_all(block).await?;
-            println!("Enviado bloco {} com {} bytes.", block_id, n);
-            block_id += 1;
+    fn connection_settings(&self) -> Settings {
+        Settings {
+            listen_port: self.port,
+            global_limits: Arc::clone(&self.global_limits),
+            peer_limits: Arc::clone(&self.peer_limits),
         }
+    }
+
+    pub fn with_unchoke_slots(self, slots: usize) -> Self {
+        self.set_unchoke_slots(slots);
+        self
+    }
 
-        Ok(())
+    /// Altera os slots de unchoke; vale a partir da próxima rodada do choker.
+    pub fn set_unchoke_slots(&self, slots: usize) {
+        self.unchoke_slots.store(slots, Ordering::Relaxed);
     }
 
-    pub async fn receive_file_in_blocks(&self, file_path: &str, socket: &mut TcpStream) -> std::io::Result<()> {
-        let mut file = File::create(file_path).await?;
-        let mut buffer = vec![0; 1024 * 1024 + 64]; // Buffer maior para incluir o cabeçalho do bloco
+    pub fn unchoke_slots(&self) -> usize {
+        self.unchoke_slots.load(Ordering::Relaxed)
+    }
 
-        loop {
-            let n = socket.read(&mut buffer).await?;
-            if n == 0 { break; }
-            println!("Recebido {} bytes do socket.", n);
-            let header_data = String::from_utf8_lossy(&buffer[..64]).to_string();
-            let header_parts: Vec<&str> = header_data.split_whitespace().collect();
-            let block_id = header_parts[1];
-            let expected_checksum = header_parts[2];
-            let block_data = &buffer[64..n];
-
-            let calculated_checksum = Self::calculate_checksum(block_data);
-            if calculated_checksum == expected_checksum {
-                println!("Bloco {} válido", block_id);
-                file.write_all(block_data).await?;
-                println!("Escrito {} bytes no arquivo.", n - 64);
-            } else {
-                println!("Bloco {} inválido", block_id);
+    /// Usa o nó DHT informado, além dos trackers, para encontrar peers.
+    pub fn with_dht(mut self, dht: Dht) -> Self {
+        self.dht = Some(dht);
+        self
+    }
+
+    /// Anuncia os torrents na rede local e conecta aos peers locais que os anunciarem.
+    pub fn with_lsd(mut self, lsd: Lsd) -> Self {
+        self.lsd = Some(lsd);
+        self
+    }
+
+    /// Registra um torrent e inicia o choker dele.
+    pub async fn add_torrent(&self, torrent: Arc<Torrent>) {
+        let previous = self.torrents.lock().await.insert(torrent.info_hash(), Arc::clone(&torrent));
+        if previous.is_none() {
+            let choker = self.spawn_choker(&torrent);
+            if let Some(old) = self.chokers.lock().await.insert(torrent.info_hash(), choker) {
+                old.abort();
             }
         }
+    }
+
+    /// Remove um torrent e encerra seu choker; conexões de entrada para ele
+    /// passam a ser recusadas.
+    pub async fn remove_torrent(&self, info_hash: &InfoHash) -> Option<Arc<Torrent>> {
+        let removed = self.torrents.lock().await.remove(info_hash);
+        if let Some(choker) = self.chokers.lock().await.remove(info_hash) {
+            choker.abort();
+        }
+        removed
+    }
 
-        Ok(())
-    }
-
-    pub async fn list_peer_files(&self, peer_addr: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect(peer_addr).await?;
-        stream.write_all(b"LIST_FILES").await?;
-        
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer).await?;
-        let files = String::from_utf8_lossy(&buffer[..n])
-            .split(',')
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect();
-            
-        Ok(files)
-    }
-
-    pub async fn list_network_files(&self, peers: Vec<String>) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
-        let mut network_files = Vec::new();
-        
-        for peer in peers {
-            if peer != format!("{}:{}", self.ip, self.port) {
-                match self.list_peer_files(&peer).await {
-                    Ok(files) => {
-                        for file in files {
-                            network_files.push((file, peer.clone()));
-                        }
-                    }
-                    Err(e) => println!("Erro ao listar arquivos do peer {}: {}", peer, e)
+    /// Rodadas do choker a cada `RECHOKE_INTERVAL`, ou antes quando uma conexão
+    /// pede; termina quando o torrent é parado, removido ou deixa de existir.
+    fn spawn_choker(&self, torrent: &Arc<Torrent>) -> AbortHandle {
+        let torrent = Arc::downgrade(torrent);
+        let slots = Arc::clone(&self.unchoke_slots);
+        let task = tokio::spawn(async move {
+            let mut choker = Choker::new();
+            let mut ticker = tokio::time::interval(choker::RECHOKE_INTERVAL);
+            while let Some(torrent) = torrent.upgrade() {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = torrent.rechoke_requested() => {}
+                    _ = torrent.stopped() => break,
                 }
+                torrent.rechoke(&mut choker, slots.load(Ordering::Relaxed)).await;
             }
+        });
+        task.abort_handle()
+    }
+
+    pub async fn torrent(&self, info_hash: &InfoHash) -> Option<Arc<Torrent>> {
+        self.torrents.lock().await.get(info_hash).cloned()
+    }
+
+    /// Abre uma conexão com outro peer para o torrent informado.
+    pub async fn connect(&self, torrent: Arc<Torrent>, peer_addr: SocketAddr) -> io::Result<()> {
+        let (socket, remote) = self.open_connection(peer_addr, torrent.info_hash()).await?;
+        info!(parent: torrent.span(), peer = %peer_addr, "Conectado ao peer");
+        connection::run(socket, torrent, &remote, Some(peer_addr), &self.connection_settings()).await
+    }
+
+    /// Conecta e troca handshakes, conferindo o info-hash da resposta.
+    async fn open_connection(&self, peer_addr: SocketAddr, info_hash: InfoHash) -> io::Result<(TcpStream, Handshake)> {
+        let mut socket = timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(peer_addr)).await??;
+        let handshake = Handshake::new(info_hash, self.peer_id);
+        wire::write_handshake(&mut socket, &handshake).await?;
+        let remote = timeout(HANDSHAKE_TIMEOUT, wire::read_handshake(&mut socket)).await??;
+
+        if remote.info_hash != info_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "peer respondeu com outro info-hash"));
         }
-        
-        Ok(network_files)
-    }
-
-    pub async fn download_blocks_from_peers(&self, peers: Vec<String>, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut tasks: Vec<tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + 'static>>>> = Vec::new();
-        
-        // Cria um diretório de downloads se não existir
-        let download_dir = dirs::download_dir()
-            .unwrap_or_else(|| std::path::PathBuf::from("downloads"));
-        tokio::fs::create_dir_all(&download_dir).await?;
-        
-        // Extrai apenas o nome do arquivo do caminho completo
-        let file_name_only = std::path::Path::new(file_name)
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        
-        // Cria o caminho completo para o arquivo de download
-        let download_path = download_dir.join(&file_name_only);
-        println!("Arquivo será salvo em: {}", download_path.display());
-    
-        for peer in peers {
-            if peer != format!("{}:{}", self.ip, self.port) {
-                let peer_clone = peer.clone();
-                let file_name_clone = file_name.to_string();
+        if remote.peer_id == self.peer_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "conexão consigo mesmo"));
+        }
+        Ok((socket, remote))
+    }
+
+    /// Obtém o metainfo de um link magnet: procura peers nos trackers do link,
+    /// na DHT e em `x.pe`, e baixa o dicionário `info` do primeiro que o fornecer.
+    pub async fn fetch_metadata(&self, magnet: &Magnet) -> Result<Metainfo, Box<dyn std::error::Error>> {
+        let mut peers = magnet.peers.clone();
+        if !magnet.trackers.is_empty() {
+            let request = AnnounceRequest {
+                info_hash: magnet.info_hash,
+                peer_id: self.peer_id,
+                port: self.port,
+                uploaded: 0,
+                downloaded: 0,
+                left: UNKNOWN_LEFT,
+                event: Event::None,
+                numwant: Some(50),
+            };
+            if let Ok(response) = self.announce_to(&magnet.trackers, &request).await {
+                peers.extend(response.peers);
+            }
+        }
+        if let Some(dht) = &self.dht {
+            peers.extend(dht.get_peers(magnet.info_hash).await);
+        }
+        peers.sort_unstable();
+        peers.dedup();
+        let own = self.own_addrs();
+        peers.retain(|addr| !own.contains(addr));
+        if peers.is_empty() {
+            return Err("nenhum peer encontrado para o link magnet".into());
+        }
+        info!(peers = peers.len(), "Buscando metadados");
+
+        let mut peers = peers.into_iter();
+        let mut tasks = JoinSet::new();
+        loop {
+            while tasks.len() < METADATA_PEERS {
+                let Some(peer_addr) = peers.next() else { break };
                 let peer_self = self.clone();
-                let download_path = download_path.clone();
-                let file_name_only_clone = file_name_only.clone(); // Clonando a String aqui
-
-                tasks.push(tokio::spawn(async move {
-                    println!("Tentando conectar ao peer: {}", peer_clone);
-                    match TcpStream::connect(&peer_clone).await {
-                        Ok(mut socket) => {
-                            let request = format!("REQUEST_FILE {}", file_name_clone);
-                            match socket.write_all(request.as_bytes()).await {
-                                Ok(_) => {
-                                    match peer_self.receive_file_in_blocks(download_path.to_str().unwrap(), &mut socket).await {
-                                        Ok(_) => {
-                                            println!("Download do arquivo {} concluído", file_name_only_clone);
-                                            Ok(())
-                                        },
-                                        Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + 'static>)
-                                    }
-                                },
-                                Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + 'static>)
-                            }
-                        }
-                        Err(e) => {
-                            println!("Erro ao conectar ao peer {}: {}", peer_clone, e);
-                            Err(Box::new(e) as Box<dyn std::error::Error + Send + 'static>)
+                let info_hash = magnet.info_hash;
+                tasks.spawn(async move {
+                    let result = async {
+                        let (socket, remote) = peer_self.open_connection(peer_addr, info_hash).await?;
+                        if !remote.supports_extensions() {
+                            return Err(io::Error::new(io::ErrorKind::Unsupported, "peer sem protocolo de extensões"));
                         }
+                        metadata::fetch(socket, info_hash, peer_self.port).await
+                    };
+                    (peer_addr, result.await)
+                });
+            }
+            let Some(joined) = tasks.join_next().await else {
+                return Err("nenhum peer forneceu os metadados".into());
+            };
+            match joined {
+                Ok((peer_addr, Ok(info_bytes))) => {
+                    tasks.abort_all();
+                    let mut metainfo = Metainfo::from_info_bytes(info_bytes)?;
+                    if metainfo.info_hash != magnet.info_hash {
+                        return Err("metadados não conferem com o info-hash".into());
                     }
-                }));
+                    metainfo.announce = magnet.trackers.first().cloned();
+                    if !magnet.trackers.is_empty() {
+                        metainfo.announce_list = vec![magnet.trackers.clone()];
+                    }
+                    info!(peer = %peer_addr, torrent = %metainfo.info.name, "Metadados recebidos");
+                    return Ok(metainfo);
+                }
+                Ok((peer_addr, Err(e))) => debug!(peer = %peer_addr, error = %e, "Metadados indisponíveis"),
+                Err(_) => {}
             }
         }
-    
-        for task in tasks {
-            match task.await {
-                Ok(result) => {
-                    if let Err(e) = result {
-                        println!("Erro durante o download: {}", e);
+    }
+
+    /// Envia um announce aos trackers do torrent, tentando cada um na ordem
+    /// das camadas (BEP 12) até o primeiro responder.
+    pub async fn announce(&self, torrent: &Torrent, event: Event) -> Result<AnnounceResponse, AnnounceError> {
+        let request = AnnounceRequest {
+            info_hash: torrent.info_hash(),
+            peer_id: self.peer_id,
+            port: self.port,
+            uploaded: torrent.uploaded.load(Ordering::Relaxed),
+            downloaded: torrent.downloaded.load(Ordering::Relaxed),
+            left: torrent.bytes_left().await,
+            event,
+            numwant: Some(50),
+        };
+        self.announce_to(&torrent.metainfo.trackers(), &request).await
+    }
+
+    async fn announce_to(&self, trackers: &[String], request: &AnnounceRequest) -> Result<AnnounceResponse, AnnounceError> {
+        let mut last_error = AnnounceError::Invalid("torrent sem trackers");
+        for url in trackers {
+            match announce::announce(url, request).await {
+                Ok(response) => {
+                    if let Some(warning) = &response.warning {
+                        warn!(tracker = %url, warning = %warning, "Aviso do tracker");
                     }
+                    return Ok(response);
+                }
+                Err(e) => {
+                    warn!(tracker = %url, event = ?request.event, error = %e, "Erro no announce");
+                    last_error = e;
                 }
-                Err(e) => println!("Erro na task: {}", e),
             }
         }
-    
-        // Verifica se o arquivo existe após o download
-        match tokio::fs::metadata(&download_path).await {
-            Ok(metadata) => {
-                println!("\nDownload concluído com sucesso!");
-                println!("Arquivo salvo em: {}", download_path.display());
-                println!("Tamanho do arquivo: {} bytes", metadata.len());
+        Err(last_error)
+    }
+
+    /// Procura peers nos trackers e, se habilitada, na DHT, anunciando-se em
+    /// ambos e também na rede local. Só falha se o tracker falhar e não houver
+    /// DHT nem LSD; sem tracker, o próximo announce acontece em `RETRY_INTERVAL` segundos.
+    async fn discover(&self, torrent: &Torrent, event: Event) -> Result<(Vec<SocketAddr>, u32), AnnounceError> {
+        let tracker = self.announce(torrent, event).await;
+        let dht = match &self.dht {
+            // Torrents privados (BEP 27) só usam o tracker
+            Some(dht) if !torrent.is_private() && event != Event::Stopped => {
+                let peers = dht.announce(torrent.info_hash(), self.port).await;
+                debug!(peers = peers.len(), "DHT retornou peers");
+                Some(peers)
             }
-            Err(_) => {
-                println!("Erro: Arquivo não foi encontrado após o download!");
+            _ => None,
+        };
+        if let Some(lsd) = &self.lsd {
+            if !torrent.is_private() && event != Event::Stopped {
+                if let Err(e) = lsd.announce(&[torrent.info_hash()]).await {
+                    warn!(error = %e, "Erro no anúncio LSD");
+                }
             }
         }
-    
-        Ok(())
-    }
 
-    pub async fn unify_blocks(&self, file_path: &str, blocks: Vec<String>) -> std::io::Result<()> {
-        let mut file = File::create(file_path).await?;
+        let (mut peers, interval) = match (tracker, dht) {
+            (Ok(response), dht) => {
+                debug!(peers = response.peers.len(), interval = response.interval, "Tracker retornou peers");
+                let mut peers = response.peers;
+                peers.extend(dht.unwrap_or_default());
+                (peers, response.interval)
+            }
+            (Err(_), Some(dht)) => (dht, RETRY_INTERVAL),
+            // Sem tracker nem DHT, os peers ainda podem chegar pelo LSD
+            (Err(_), None) if self.lsd.is_some() && !torrent.is_private() => (Vec::new(), RETRY_INTERVAL),
+            (Err(e), None) => return Err(e),
+        };
+        peers.sort_unstable();
+        peers.dedup();
+        Ok((peers, interval))
+    }
 
-        for block in blocks {
-            let mut block_file = File::open(&block).await?;
-            let mut buffer = vec![0; 1024 * 1024];
-            while let Ok(n) = block_file.read(&mut buffer).await {
-                if n == 0 { break; }
-                file.write_all(&buffer[..n]).await?;
+    /// Abre conexões com os peers ainda não conectados.
+    fn connect_peers(&self, torrent: &Arc<Torrent>, peers: Vec<SocketAddr>, connected: Vec<SocketAddr>) -> JoinSet<()> {
+        let mut tasks = JoinSet::new();
+        let own = self.own_addrs();
+        for peer_addr in peers {
+            if own.contains(&peer_addr) || connected.contains(&peer_addr) {
+                continue;
             }
+            let peer_self = self.clone();
+            let torrent = Arc::clone(torrent);
+            let span = torrent.span().clone();
+            tasks.spawn(
+                async move {
+                    if let Err(e) = peer_self.connect(torrent, peer_addr).await {
+                        info!(peer = %peer_addr, error = %e, "Conexão com o peer encerrada");
+                    }
+                }
+                .instrument(span),
+            );
         }
+        tasks
+    }
 
-        Ok(())
+    /// Endereços pelos quais os outros nos alcançam, para não conectarmos a
+    /// nós mesmos. Escutando em `0.0.0.0` ou `::`, são todos os endereços das
+    /// interfaces locais na nossa porta.
+    fn own_addrs(&self) -> Vec<SocketAddr> {
+        let mut ips = vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)];
+        match self.ip.parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => ips.extend(interface_ips()),
+            Ok(ip) => ips.push(ip),
+            Err(_) => {}
+        }
+        ips.into_iter().map(|ip| SocketAddr::new(ip, self.port)).collect()
     }
 
-    pub async fn validate_final_file(&self, file_path: &str, expected_checksum: &str) -> std::io::Result<()> {
-        let mut file = File::open(file_path).await?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).await?;
-        let calculated_checksum = Self::calculate_checksum(&buffer);
+    /// Registra um torrent completo, anuncia `started` e o mantém anunciado.
+    pub async fn start_seeding(&self, torrent: Arc<Torrent>) {
+        self.add_torrent(Arc::clone(&torrent)).await;
+        let interval = match self.discover(&torrent, Event::Started).await {
+            Ok((_, interval)) => interval,
+            Err(_) => RETRY_INTERVAL,
+        };
+        self.spawn_announcer(torrent, interval);
+    }
 
-        if calculated_checksum == expected_checksum {
-            println!("Arquivo completo válido");
-        } else {
-            println!("Arquivo completo inválido");
-        }
+    /// Registra o torrent e, em segundo plano, anuncia `started`, conecta aos
+    /// peers encontrados (se faltarem peças) e mantém os announces periódicos.
+    /// Ao contrário de [`Peer::download`], retorna sem esperar o download.
+    pub async fn start_torrent(&self, torrent: Arc<Torrent>, known_peers: Vec<SocketAddr>) {
+        self.add_torrent(Arc::clone(&torrent)).await;
+        let peer_self = self.clone();
+        let span = torrent.span().clone();
+        let start = async move {
+            let (mut peers, interval) = match peer_self.discover(&torrent, Event::Started).await {
+                Ok(found) => found,
+                Err(_) => (Vec::new(), RETRY_INTERVAL),
+            };
+            if !torrent.is_active() {
+                return;
+            }
+            if !torrent.is_complete().await {
+                peers.extend(known_peers);
+                peers.sort_unstable();
+                peers.dedup();
+                peer_self.connect_peers(&torrent, peers, Vec::new()).detach_all();
+            }
+            peer_self.spawn_announcer(torrent, interval);
+        };
+        tokio::spawn(start.instrument(span));
+    }
+
+    /// Announces periódicos: conecta a peers novos enquanto faltarem peças e
+    /// envia `completed` quando o download termina. Peers recebidos via ut_pex
+    /// são conectados assim que chegam. Termina quando o torrent é parado.
+    fn spawn_announcer(&self, torrent: Arc<Torrent>, first_interval: u32) {
+        let peer_self = self.clone();
+        let span = torrent.span().clone();
+        let announcer = async move {
+            let mut interval = first_interval;
+            let mut was_complete = torrent.is_complete().await;
+            let mut pex_events = torrent.subscribe_pex();
+            let next_announce = tokio::time::sleep(Duration::from_secs(interval as u64));
+            tokio::pin!(next_announce);
+            loop {
+                let mut event = Event::None;
+                tokio::select! {
+                    _ = &mut next_announce => {}
+                    _ = torrent.wait_complete(), if !was_complete => {
+                        was_complete = true;
+                        event = Event::Completed;
+                    }
+                    Ok(peers) = pex_events.recv() => {
+                        if !torrent.is_complete().await {
+                            debug!(peers = peers.len(), "ut_pex trouxe peers novos");
+                            let connected = torrent.peer_addrs().await;
+                            peer_self.connect_peers(&torrent, peers, connected).detach_all();
+                        }
+                        continue;
+                    }
+                    _ = torrent.stopped() => break,
+                }
 
-        Ok(())
+                interval = match peer_self.discover(&torrent, event).await {
+                    Ok((peers, interval)) => {
+                        if !torrent.is_complete().await {
+                            let connected = torrent.peer_addrs().await;
+                            peer_self.connect_peers(&torrent, peers, connected).detach_all();
+                        }
+                        interval
+                    }
+                    Err(_) => RETRY_INTERVAL,
+                };
+                next_announce.as_mut().reset(tokio::time::Instant::now() + Duration::from_secs(interval as u64));
+            }
+        };
+        tokio::spawn(announcer.instrument(span));
+    }
+
+    /// Grava o arquivo de retomada de todos os torrents.
+    pub async fn save_resume_data(&self) {
+        let torrents: Vec<Arc<Torrent>> = self.torrents.lock().await.values().cloned().collect();
+        for torrent in torrents {
+            if let Err(e) = torrent.save_resume().await {
+                warn!(parent: torrent.span(), error = %e, "Erro ao gravar a retomada");
+            }
+        }
     }
 
-    pub async fn register_with_tracker(&self, tracker_ip: &str, tracker_port: u16) -> Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect(format!("{}:{}", tracker_ip, tracker_port)).await?;
-        let message = format!("REGISTER {}:{}:{}", self.name, self.ip, self.port);
-        stream.write_all(message.as_bytes()).await?;
-        println!("Registrado no tracker {}:{}", tracker_ip, tracker_port);
-        Ok(())
+    /// Envia `stopped` para os trackers de todos os torrents.
+    pub async fn stop_announcing(&self) {
+        let torrents: Vec<Arc<Torrent>> = self.torrents.lock().await.values().cloned().collect();
+        for torrent in torrents {
+            let _ = self.announce(&torrent, Event::Stopped).instrument(torrent.span().clone()).await;
+        }
     }
 
-    pub async fn unregister_from_tracker(&self, tracker_ip: &str, tracker_port: u16) -> Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect(format!("{}:{}", tracker_ip, tracker_port)).await?;
-        let message = format!("UNREGISTER {}:{}", self.name, self.port);
-        stream.write_all(message.as_bytes()).await?;
-        println!("Desregistrado do tracker {}:{}", tracker_ip, tracker_port);
-        Ok(())
+    /// Baixa o torrent dos peers informados pelo tracker, retornando quando todas
+    /// as peças estiverem no disco. As conexões continuam abertas para semear.
+    pub async fn download(&self, torrent: Arc<Torrent>) -> Result<(), Box<dyn std::error::Error>> {
+        self.download_from(torrent, Vec::new()).await
     }
 
-    pub async fn get_peers_from_tracker(&self, tracker_ip: &str, tracker_port: u16) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect(format!("{}:{}", tracker_ip, tracker_port)).await?;
-        stream.write_all(b"GET_PEERS").await?;
+    /// Como [`Peer::download`], conectando também aos peers informados (os
+    /// `x.pe` de um link magnet, por exemplo); com eles, o download começa
+    /// mesmo que nenhum tracker responda.
+    pub async fn download_from(
+        &self,
+        torrent: Arc<Torrent>,
+        known_peers: Vec<SocketAddr>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.add_torrent(Arc::clone(&torrent)).await;
+        if torrent.is_complete().await {
+            info!(parent: torrent.span(), "Todas as peças já estão no disco");
+            self.start_seeding(torrent).await;
+            return Ok(());
+        }
+
+        let (mut peers, interval) = match self.discover(&torrent, Event::Started).await {
+            Ok(found) => found,
+            Err(_) if !known_peers.is_empty() => (Vec::new(), RETRY_INTERVAL),
+            Err(e) => return Err(e.into()),
+        };
+        peers.extend(known_peers);
+        peers.sort_unstable();
+        peers.dedup();
+        let mut tasks = self.connect_peers(&torrent, peers, Vec::new());
+        self.spawn_announcer(Arc::clone(&torrent), interval);
+
+        loop {
+            tokio::select! {
+                _ = torrent.wait_complete() => break,
+                next = tasks.join_next() => if next.is_none() { break },
+            }
+        }
+        tasks.detach_all();
 
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer).await?;
-        let peer_list = String::from_utf8_lossy(&buffer[..n]).to_string();
-        let peers = peer_list.split(',').map(|s| s.to_string()).collect();
-        Ok(peers)
+        if torrent.is_complete().await {
+            let path = torrent.save_dir.join(&torrent.metainfo.info.name);
+            info!(
+                parent: torrent.span(),
+                path = %path.display(),
+                size = torrent.metainfo.info.total_length(),
+                "Download concluído"
+            );
+            Ok(())
+        } else {
+            let have = torrent.bitfield().await;
+            Err(format!(
+                "download incompleto: {} de {} peças; novos peers serão procurados no próximo announce",
+                have.count(),
+                have.len()
+            )
+            .into())
+        }
     }
 
     pub async fn start_server(&self) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(format!("{}:{}", self.ip, self.port)).await?;
-        println!("Peer rodando em {}:{}", self.ip, self.port);
+        info!(ip = %self.ip, port = self.port, "Peer rodando");
+        if let Some(lsd) = self.lsd.clone() {
+            let peer_self = self.clone();
+            tokio::spawn(async move { peer_self.lsd_loop(lsd).await });
+        }
 
         loop {
-            let (mut socket, _) = listener.accept().await?;
-            let shared_files = self.shared_files.clone();
-
+            let (socket, addr) = listener.accept().await?;
             let peer_self = self.clone();
+
             tokio::spawn(async move {
-                let mut buffer = [0; 1024];
-                if let Ok(n) = socket.read(&mut buffer).await {
-                    let request = String::from_utf8_lossy(&buffer[..n]).to_string();
+                if let Err(e) = peer_self.handle_incoming(socket).await {
+                    info!(peer = %addr, error = %e, "Conexão de entrada encerrada");
+                }
+            });
+        }
+    }
 
-                    if request.starts_with("LIST_FILES") {
-                        let file_list = shared_files.join(",");
-                        socket.write_all(file_list.as_bytes()).await.unwrap();
-                    }
-                    
-                    if request.starts_with("LIST_FILES") {
-                        println!("Recebida solicitação de listagem de arquivos");
-                        let file_list = shared_files.join(",");
-                        println!("Enviando lista de arquivos: {}", file_list);
-                        socket.write_all(file_list.as_bytes()).await.unwrap();
+    /// Reanuncia os torrents na rede local periodicamente e conecta aos peers
+    /// locais que anunciarem um dos nossos torrents.
+    async fn lsd_loop(&self, lsd: Lsd) {
+        let mut local_peers = lsd.subscribe();
+        let mut ticker = tokio::time::interval(lsd::ANNOUNCE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let info_hashes: Vec<InfoHash> = self
+                        .torrents
+                        .lock()
+                        .await
+                        .values()
+                        .filter(|torrent| !torrent.is_private())
+                        .map(|torrent| torrent.info_hash())
+                        .collect();
+                    if let Err(e) = lsd.announce(&info_hashes).await {
+                        warn!(error = %e, "Erro no anúncio LSD");
                     }
-
-                    if !request.is_empty() {
-                        println!("Mensagem recebida: {}", request);
+                }
+                Ok(local) = local_peers.recv() => {
+                    let Some(torrent) = self.torrent(&local.info_hash).await else {
+                        continue;
+                    };
+                    if torrent.is_private() {
+                        continue;
                     }
+                    // Mesmo com o torrent completo: quem acabou de anunciar pode estar
+                    // começando o download e não deve esperar o nosso próximo anúncio
+                    info!(parent: torrent.span(), peer = %local.addr, "Peer local anunciou o torrent");
+                    let connected = torrent.peer_addrs().await;
+                    self.connect_peers(&torrent, vec![local.addr], connected).detach_all();
                 }
-            });
+            }
+        }
+    }
+
+    /// Recebe o handshake de uma conexão de entrada e a direciona ao torrent pelo info-hash.
+    async fn handle_incoming(&self, mut socket: TcpStream) -> io::Result<()> {
+        let remote = timeout(HANDSHAKE_TIMEOUT, wire::read_handshake(&mut socket)).await??;
+        let Some(torrent) = self.torrent(&remote.info_hash).await else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "info-hash desconhecido"));
+        };
+        if remote.peer_id == self.peer_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "conexão consigo mesmo"));
         }
+
+        let handshake = Handshake::new(torrent.info_hash(), self.peer_id);
+        wire::write_handshake(&mut socket, &handshake).await?;
+        connection::run(socket, torrent, &remote, None, &self.connection_settings()).await
     }
 }
 
-pub fn list_local_files(directory: Option<&str>) -> Vec<(String, PathBuf)> {
+/// Arquivos (não recursivo) dos diretórios informados; diretórios inexistentes são ignorados.
+pub fn list_local_files(directories: &[PathBuf]) -> Vec<(String, PathBuf)> {
     let mut files = Vec::new();
-    
-    let directories = match directory {
-        Some(dir) => vec![PathBuf::from(dir)],
-        None => vec![
-            dirs::home_dir().unwrap_or_default().join("Documents"),
-            dirs::home_dir().unwrap_or_default().join("Downloads"),
-        ]
-    };
 
     for dir in directories {
-        if let Ok(entries) = read_dir(&dir) {
+        if let Ok(entries) = read_dir(dir) {
             for entry in entries.flatten() {
                 if let Ok(file_type) = entry.file_type() {
                     if file_type.is_file() {
@@ -312,4 +612,69 @@ pub fn list_local_files(directory: Option<&str>) -> Vec<(String, PathBuf)> {
     }
     
     files
-} 
\ No newline at end of file
+}
+
+/// Endereços IP das interfaces de rede da máquina, incluindo o loopback.
+#[cfg(unix)]
+fn interface_ips() -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: a lista de getifaddrs só é lida até o fim e liberada uma vez; cada
+    // endereço é convertido de acordo com a família indicada em sa_family
+    unsafe {
+        if libc::getifaddrs(&mut list) != 0 {
+            return vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)];
+        }
+        let mut cursor = list;
+        while let Some(interface) = cursor.as_ref() {
+            if let Some(addr) = interface.ifa_addr.as_ref() {
+                match addr.sa_family as libc::c_int {
+                    libc::AF_INET => {
+                        let addr = &*(interface.ifa_addr as *const libc::sockaddr_in);
+                        ips.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))));
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*(interface.ifa_addr as *const libc::sockaddr_in6);
+                        ips.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                    }
+                    _ => {}
+                }
+            }
+            cursor = interface.ifa_next;
+        }
+        libc::freeifaddrs(list);
+    }
+    ips
+}
+
+#[cfg(not(unix))]
+fn interface_ips() -> Vec<IpAddr> {
+    vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_addrs_match_parsed_ip_and_port() {
+        let own = Peer::new("127.0.0.1".to_string(), 6881, "a".to_string()).own_addrs();
+        assert!(own.contains(&SocketAddr::from(([127, 0, 0, 1], 6881))));
+        assert!(own.contains(&SocketAddr::from(([0, 0, 0, 0], 6881))));
+        assert!(!own.contains(&SocketAddr::from(([127, 0, 0, 1], 6882))));
+        assert!(!own.contains(&SocketAddr::from(([127, 0, 0, 2], 6881))));
+
+        let own = Peer::new("::1".to_string(), 6881, "a".to_string()).own_addrs();
+        assert!(own.contains(&"[::1]:6881".parse().unwrap()));
+    }
+
+    #[test]
+    fn own_addrs_cover_local_interfaces_when_unspecified() {
+        let own = Peer::new("0.0.0.0".to_string(), 6881, "a".to_string()).own_addrs();
+        assert!(own.contains(&SocketAddr::from(([127, 0, 0, 1], 6881))));
+        for ip in interface_ips() {
+            assert!(own.contains(&SocketAddr::new(ip, 6881)));
+        }
+        assert!(!own.contains(&SocketAddr::from(([192, 0, 2, 1], 6881))));
+    }
+}
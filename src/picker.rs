@@ -0,0 +1,417 @@
+﻿//! Escolha de peças e blocos: aleatória nas primeiras peças, depois a mais
+//! rara entre os peers conectados, e modo endgame no final do download.
+
+use crate::bitfield::Bitfield;
+use crate::metainfo::Info;
+use crate::wire::BLOCK_SIZE;
+use rand::seq::{IteratorRandom, SliceRandom};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Quantas peças são escolhidas ao acaso antes de passar para rarest-first,
+/// para ter logo algo a oferecer aos outros peers.
+const RANDOM_FIRST_PIECES: usize = 4;
+
+/// Um bloco de até 16 KiB dentro de uma peça.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub index: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BlockState {
+    Missing,
+    /// Peers a quem o bloco foi pedido; mais de um apenas no endgame.
+    Requested(Vec<SocketAddr>),
+    Received,
+}
+
+/// Peça com ao menos um bloco pedido, montada em memória até ser verificada.
+///
+/// Com todos os blocos recebidos, os dados seguem para verificação mas a peça
+/// continua aqui, sem nada a pedir, até [`PiecePicker::finish`]: assim nenhuma
+/// outra conexão começa a baixá-la de novo nesse meio tempo.
+struct PartialPiece {
+    data: Vec<u8>,
+    blocks: Vec<BlockState>,
+}
+
+impl PartialPiece {
+    fn new(size: usize) -> Self {
+        let blocks = size.div_ceil(BLOCK_SIZE as usize);
+        Self { data: vec![0; size], blocks: vec![BlockState::Missing; blocks] }
+    }
+
+    fn block(&self, index: usize, block: usize) -> Block {
+        let begin = block as u32 * BLOCK_SIZE;
+        let length = (self.data.len() as u32 - begin).min(BLOCK_SIZE);
+        Block { index: index as u32, begin, length }
+    }
+
+    fn is_done(&self) -> bool {
+        self.blocks.iter().all(|state| *state == BlockState::Received)
+    }
+}
+
+/// Resultado de entregar um bloco ao picker.
+pub enum Received {
+    /// Bloco que não estava pendente (duplicado do endgame ou já descartado).
+    Ignored,
+    /// Bloco guardado; a peça ainda não está completa.
+    Stored,
+    /// Último bloco da peça; os dados seguem para verificação e a peça fica
+    /// reservada até [`PiecePicker::finish`].
+    PieceDone(Vec<u8>),
+}
+
+pub struct PiecePicker {
+    piece_length: u64,
+    total_length: u64,
+    /// Em quantos peers conectados cada peça está disponível.
+    availability: Vec<u32>,
+    partial: HashMap<usize, PartialPiece>,
+}
+
+impl PiecePicker {
+    pub fn new(info: &Info) -> Self {
+        Self {
+            piece_length: info.piece_length,
+            total_length: info.total_length(),
+            availability: vec![0; info.piece_count()],
+            partial: HashMap::new(),
+        }
+    }
+
+    pub fn add_bitfield(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.iter() {
+            self.availability[index] += 1;
+        }
+    }
+
+    pub fn remove_bitfield(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.iter() {
+            self.availability[index] = self.availability[index].saturating_sub(1);
+        }
+    }
+
+    pub fn add_have(&mut self, index: usize) {
+        if let Some(count) = self.availability.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    pub fn availability(&self, index: usize) -> u32 {
+        self.availability.get(index).copied().unwrap_or(0)
+    }
+
+    /// Endgame: todos os blocos que faltam já foram pedidos a alguém.
+    pub fn in_endgame(&self, have: &Bitfield) -> bool {
+        let started = |index| self.partial.contains_key(&index);
+        (0..have.len()).all(|index| have.has(index) || started(index))
+            && self.partial.values().all(|piece| !piece.blocks.contains(&BlockState::Missing))
+    }
+
+    /// Escolhe até `max` blocos para pedir a `peer`, que tem as peças em `remote`.
+    ///
+    /// Primeiro completa peças já iniciadas, depois inicia uma nova (aleatória
+    /// nas primeiras, a mais rara em seguida). No endgame, repete pedidos que
+    /// estão com outros peers.
+    pub fn pick(&mut self, peer: SocketAddr, remote: &Bitfield, have: &Bitfield, max: usize) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        if max == 0 {
+            return blocks;
+        }
+
+        let mut started: Vec<usize> = self.partial.keys().copied().filter(|&i| remote.has(i)).collect();
+        started.sort_unstable();
+        for index in started {
+            self.request_missing(index, peer, max, &mut blocks);
+            if blocks.len() == max {
+                return blocks;
+            }
+        }
+
+        while blocks.len() < max {
+            let Some(index) = self.choose_new_piece(remote, have) else {
+                break;
+            };
+            let size = self.piece_size(index) as usize;
+            self.partial.insert(index, PartialPiece::new(size));
+            self.request_missing(index, peer, max, &mut blocks);
+        }
+
+        if blocks.is_empty() && self.in_endgame(have) {
+            self.request_duplicates(peer, remote, max, &mut blocks);
+        }
+        blocks
+    }
+
+    fn request_missing(&mut self, index: usize, peer: SocketAddr, max: usize, blocks: &mut Vec<Block>) {
+        let piece = self.partial.get_mut(&index).unwrap();
+        for block in 0..piece.blocks.len() {
+            if blocks.len() == max {
+                return;
+            }
+            if piece.blocks[block] == BlockState::Missing {
+                piece.blocks[block] = BlockState::Requested(vec![peer]);
+                blocks.push(piece.block(index, block));
+            }
+        }
+    }
+
+    fn request_duplicates(&mut self, peer: SocketAddr, remote: &Bitfield, max: usize, blocks: &mut Vec<Block>) {
+        let mut candidates = Vec::new();
+        for (&index, piece) in &self.partial {
+            if !remote.has(index) {
+                continue;
+            }
+            for (block, state) in piece.blocks.iter().enumerate() {
+                if let BlockState::Requested(peers) = state {
+                    if !peers.contains(&peer) {
+                        candidates.push((index, block));
+                    }
+                }
+            }
+        }
+        // Embaralha para que peers diferentes não dupliquem todos os mesmos blocos
+        candidates.shuffle(&mut rand::thread_rng());
+        for (index, block) in candidates.into_iter().take(max) {
+            let piece = self.partial.get_mut(&index).unwrap();
+            if let BlockState::Requested(peers) = &mut piece.blocks[block] {
+                peers.push(peer);
+            }
+            blocks.push(piece.block(index, block));
+        }
+    }
+
+    fn choose_new_piece(&self, remote: &Bitfield, have: &Bitfield) -> Option<usize> {
+        let candidates = remote.iter().filter(|&i| !have.has(i) && !self.partial.contains_key(&i));
+        let mut rng = rand::thread_rng();
+        if have.count() < RANDOM_FIRST_PIECES {
+            return candidates.choose(&mut rng);
+        }
+        let candidates: Vec<usize> = candidates.collect();
+        let rarest = candidates.iter().map(|&i| self.availability[i]).min()?;
+        candidates
+            .into_iter()
+            .filter(|&i| self.availability[i] == rarest)
+            .choose(&mut rng)
+    }
+
+    /// Guarda um bloco recebido de `peer`.
+    ///
+    /// Retorna também os outros peers a quem o mesmo bloco foi pedido, que
+    /// devem receber `cancel`.
+    pub fn receive(&mut self, peer: SocketAddr, block: Block, data: &[u8]) -> (Received, Vec<SocketAddr>) {
+        let index = block.index as usize;
+        let Some(piece) = self.partial.get_mut(&index) else {
+            return (Received::Ignored, Vec::new());
+        };
+        let number = (block.begin / BLOCK_SIZE) as usize;
+        if !block.begin.is_multiple_of(BLOCK_SIZE)
+            || number >= piece.blocks.len()
+            || piece.block(index, number) != block
+            || data.len() != block.length as usize
+        {
+            return (Received::Ignored, Vec::new());
+        }
+        let others = match &piece.blocks[number] {
+            BlockState::Requested(peers) => peers.iter().copied().filter(|p| *p != peer).collect(),
+            _ => return (Received::Ignored, Vec::new()),
+        };
+
+        let begin = block.begin as usize;
+        piece.data[begin..begin + data.len()].copy_from_slice(data);
+        piece.blocks[number] = BlockState::Received;
+        if !piece.is_done() {
+            return (Received::Stored, others);
+        }
+        (Received::PieceDone(std::mem::take(&mut piece.data)), others)
+    }
+
+    /// Libera uma peça entregue em [`Received::PieceDone`], depois de marcada
+    /// como nossa ou, se o hash não conferiu, para que seja baixada de novo.
+    pub fn finish(&mut self, index: usize) {
+        self.partial.remove(&index);
+    }
+
+    /// Esquece os pedidos feitos a um peer (choke ou desconexão).
+    pub fn cancel_peer(&mut self, peer: SocketAddr) {
+        for piece in self.partial.values_mut() {
+            for state in &mut piece.blocks {
+                if let BlockState::Requested(peers) = state {
+                    peers.retain(|p| *p != peer);
+                    if peers.is_empty() {
+                        *state = BlockState::Missing;
+                    }
+                }
+            }
+        }
+        self.partial
+            .retain(|_, piece| piece.blocks.iter().any(|state| *state != BlockState::Missing));
+    }
+
+    fn piece_size(&self, index: usize) -> u64 {
+        let start = index as u64 * self.piece_length;
+        (self.total_length - start).min(self.piece_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Peças de dois blocos; a última tem só um.
+    fn info(pieces: usize) -> Info {
+        let piece_length = 2 * BLOCK_SIZE as u64;
+        Info {
+            name: "teste".to_string(),
+            piece_length,
+            pieces: vec![0; pieces * 20],
+            length: Some(piece_length * pieces as u64 - BLOCK_SIZE as u64),
+            files: None,
+            private: None,
+        }
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn bitfield(len: usize, pieces: impl IntoIterator<Item = usize>) -> Bitfield {
+        let mut bitfield = Bitfield::new(len);
+        for index in pieces {
+            bitfield.set(index);
+        }
+        bitfield
+    }
+
+    fn data(block: &Block) -> Vec<u8> {
+        vec![block.index as u8; block.length as usize]
+    }
+
+    #[test]
+    fn first_pieces_are_random_then_rarest() {
+        let remote = Bitfield::full(10);
+        let chosen: HashSet<u32> = (0..50)
+            .map(|_| {
+                let mut picker = PiecePicker::new(&info(10));
+                picker.add_bitfield(&remote);
+                picker.add_have(7);
+                picker.pick(peer(1), &remote, &Bitfield::new(10), 1)[0].index
+            })
+            .collect();
+        // Sem nenhuma peça, a rara 7 não tem preferência
+        assert!(chosen.len() > 1, "{:?}", chosen);
+
+        let have = bitfield(10, 0..RANDOM_FIRST_PIECES);
+        for _ in 0..20 {
+            let mut picker = PiecePicker::new(&info(10));
+            picker.add_bitfield(&remote);
+            picker.add_bitfield(&bitfield(10, (0..10).filter(|&i| i != 8)));
+            assert_eq!(picker.pick(peer(1), &remote, &have, 1)[0].index, 8);
+        }
+    }
+
+    #[test]
+    fn rarest_first_follows_bitfields_and_haves() {
+        let mut picker = PiecePicker::new(&info(10));
+        let remote = Bitfield::full(10);
+        let have = bitfield(10, 0..RANDOM_FIRST_PIECES);
+        picker.add_bitfield(&remote);
+        picker.add_bitfield(&bitfield(10, [5, 6, 7]));
+        picker.add_bitfield(&bitfield(10, [6, 7]));
+        picker.add_have(7);
+        for index in [8, 8, 8, 9, 9, 9, 9] {
+            picker.add_have(index);
+        }
+        assert_eq!(picker.availability(4), 1);
+        assert_eq!(picker.availability(7), 4);
+
+        // Disponibilidade: 4 → 1, 5 → 2, 6 → 3, 7 e 8 → 4, 9 → 5
+        let order: Vec<u32> = (0..5)
+            .map(|_| {
+                let blocks = picker.pick(peer(1), &remote, &have, 2);
+                assert_eq!(blocks.len(), 2);
+                assert_eq!(blocks[0].index, blocks[1].index);
+                blocks[0].index
+            })
+            .collect();
+        assert_eq!(order[..3], [4, 5, 6]);
+        assert_eq!(order[3..].iter().copied().collect::<HashSet<_>>(), HashSet::from([7, 8]));
+        assert_eq!(picker.pick(peer(1), &remote, &have, 2)[0].index, 9);
+
+        picker.remove_bitfield(&remote);
+        assert_eq!(picker.availability(4), 0);
+    }
+
+    #[test]
+    fn endgame_duplicates_outstanding_requests() {
+        let mut picker = PiecePicker::new(&info(3));
+        let remote = Bitfield::full(3);
+        let have = bitfield(3, [0, 2]);
+
+        let first = picker.pick(peer(1), &remote, &have, 10);
+        assert_eq!(first.len(), 2);
+        assert!(picker.in_endgame(&have));
+        // O mesmo peer não recebe seus próprios pedidos de novo
+        assert!(picker.pick(peer(1), &remote, &have, 10).is_empty());
+
+        let mut second = picker.pick(peer(2), &remote, &have, 10);
+        second.sort_by_key(|block| block.begin);
+        assert_eq!(second, first);
+
+        let (received, others) = picker.receive(peer(2), first[0], &data(&first[0]));
+        assert!(matches!(received, Received::Stored));
+        assert_eq!(others, vec![peer(1)]);
+        // A cópia que chega depois é descartada
+        assert!(matches!(picker.receive(peer(1), first[0], &data(&first[0])).0, Received::Ignored));
+        let (received, others) = picker.receive(peer(1), first[1], &data(&first[1]));
+        assert!(matches!(received, Received::PieceDone(piece) if piece.len() == 2 * BLOCK_SIZE as usize));
+        assert_eq!(others, vec![peer(2)]);
+    }
+
+    #[test]
+    fn cancel_peer_releases_its_blocks() {
+        let mut picker = PiecePicker::new(&info(2));
+        let remote = Bitfield::full(2);
+        let have = bitfield(2, [1]);
+
+        let blocks = picker.pick(peer(1), &remote, &have, 1);
+        assert_eq!(blocks.len(), 1);
+        picker.cancel_peer(peer(1));
+        assert!(!picker.in_endgame(&have));
+        assert!(matches!(picker.receive(peer(1), blocks[0], &data(&blocks[0])).0, Received::Ignored));
+        assert_eq!(picker.pick(peer(2), &remote, &have, 1), blocks);
+
+        // Com um bloco já recebido, a peça continua iniciada
+        picker.receive(peer(2), blocks[0], &data(&blocks[0]));
+        let rest = picker.pick(peer(2), &remote, &have, 1);
+        picker.cancel_peer(peer(2));
+        assert_eq!(picker.pick(peer(3), &remote, &have, 10), rest);
+    }
+
+    #[test]
+    fn finished_piece_stays_reserved_until_finish() {
+        let mut picker = PiecePicker::new(&info(1));
+        let remote = Bitfield::full(1);
+        let have = Bitfield::new(1);
+
+        let blocks = picker.pick(peer(1), &remote, &have, 10);
+        assert_eq!(blocks.len(), 1);
+        assert!(matches!(picker.receive(peer(1), blocks[0], &data(&blocks[0])).0, Received::PieceDone(_)));
+
+        // Enquanto é verificada, ninguém mais baixa a peça, nem no endgame
+        assert!(picker.pick(peer(2), &remote, &have, 10).is_empty());
+        picker.cancel_peer(peer(1));
+        assert!(picker.pick(peer(2), &remote, &have, 10).is_empty());
+        assert!(matches!(picker.receive(peer(1), blocks[0], &data(&blocks[0])).0, Received::Ignored));
+
+        // Hash errado: a peça volta a faltar
+        picker.finish(0);
+        assert_eq!(picker.pick(peer(2), &remote, &have, 10), blocks);
+    }
+}
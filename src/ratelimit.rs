@@ -0,0 +1,187 @@
+﻿//! Limite de banda com token bucket, aplicado em três níveis: global, por
+//! torrent e por peer. Uma transferência passa por todos os níveis e espera
+//! pelo mais restritivo.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Taxa que significa "sem limite".
+pub const UNLIMITED: u64 = 0;
+
+/// Limite em KiB/s que não cabe em bytes/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateTooLarge(pub u64);
+
+impl fmt::Display for RateTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "limite grande demais: {} KiB/s", self.0)
+    }
+}
+
+impl std::error::Error for RateTooLarge {}
+
+/// Converte um limite em KiB/s, como os usuários o informam, para bytes/s.
+pub fn kib_to_bytes(kib: u64) -> Result<u64, RateTooLarge> {
+    kib.checked_mul(1024).ok_or(RateTooLarge(kib))
+}
+
+/// Token bucket com capacidade de um segundo de tráfego.
+///
+/// Uma reserva maior que o saldo deixa o balde negativo; quem reservou espera
+/// até o saldo voltar a zero. Assim blocos maiores que a taxa por segundo
+/// ainda passam, só que mais devagar.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Bytes por segundo; compartilhável entre baldes com a mesma configuração.
+    rate: Arc<AtomicU64>,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(UNLIMITED)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self::with_rate(Arc::new(AtomicU64::new(bytes_per_second)))
+    }
+
+    fn with_rate(rate: Arc<AtomicU64>) -> Self {
+        let tokens = rate.load(Ordering::Relaxed) as f64;
+        Self { rate, bucket: Mutex::new(Bucket { tokens, last_refill: Instant::now() }) }
+    }
+
+    /// Balde novo, vazio de histórico, que segue a taxa deste.
+    pub fn sharing_rate(&self) -> Self {
+        Self::with_rate(Arc::clone(&self.rate))
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Altera a taxa em bytes por segundo; [`UNLIMITED`] remove o limite.
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        self.rate.store(bytes_per_second, Ordering::Relaxed);
+    }
+
+    /// Altera a taxa em KiB/s; um valor que não cabe em bytes/s é recusado
+    /// e a taxa atual fica como está.
+    pub fn set_rate_kib(&self, kib: u64) -> Result<(), RateTooLarge> {
+        self.set_rate(kib_to_bytes(kib)?);
+        Ok(())
+    }
+
+    /// Desconta `bytes` do balde e retorna quanto esperar antes de transferi-los.
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.rate();
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        if rate == UNLIMITED {
+            bucket.tokens = 0.0;
+            bucket.last_refill = now;
+            return Duration::ZERO;
+        }
+        let rate = rate as f64;
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last_refill = now;
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// Limites de envio e recebimento de um nível.
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    pub upload: RateLimiter,
+    pub download: RateLimiter,
+}
+
+impl RateLimits {
+    /// Baldes novos com as mesmas taxas; usado para dar a cada conexão o
+    /// seu próprio limite por peer, todos controlados pela mesma configuração.
+    pub fn sharing_rates(&self) -> Self {
+        Self { upload: self.upload.sharing_rate(), download: self.download.sharing_rate() }
+    }
+}
+
+/// Reserva `bytes` em todos os limitadores e espera pelo mais lento.
+pub async fn throttle(limiters: &[&RateLimiter], bytes: usize) {
+    let wait = limiters.iter().map(|limiter| limiter.reserve(bytes)).max().unwrap_or_default();
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compara esperas com folga para o pouco tempo que passa entre as chamadas.
+    fn assert_wait(wait: Duration, seconds: f64) {
+        assert!((wait.as_secs_f64() - seconds).abs() < 0.05, "espera {:?}, esperado {}s", wait, seconds);
+    }
+
+    #[test]
+    fn bucket_starts_with_one_second_of_burst() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.reserve(600), Duration::ZERO);
+        assert_eq!(limiter.reserve(400), Duration::ZERO);
+        assert_wait(limiter.reserve(500), 0.5);
+    }
+
+    #[test]
+    fn negative_balance_waits_until_repaid() {
+        let limiter = RateLimiter::new(1000);
+        // Bloco maior que a taxa: passa, deixando o saldo em -2000
+        assert_wait(limiter.reserve(3000), 2.0);
+        assert_wait(limiter.reserve(1000), 3.0);
+
+        // Depois de muito tempo parado, o saldo volta só até a capacidade
+        limiter.bucket.lock().unwrap().last_refill -= Duration::from_secs(60);
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert_wait(limiter.reserve(250), 0.25);
+    }
+
+    #[test]
+    fn unlimited_can_be_switched_on_and_off() {
+        let limiter = RateLimiter::new(1000);
+        let shared = limiter.sharing_rate();
+        limiter.set_rate(UNLIMITED);
+        assert_eq!(limiter.reserve(1 << 30), Duration::ZERO);
+        assert_eq!(shared.reserve(1 << 30), Duration::ZERO);
+
+        // Ao voltar a limitar, o balde começa vazio: nada do tempo sem limite acumula
+        limiter.set_rate(1000);
+        assert_eq!(shared.rate(), 1000);
+        assert_wait(limiter.reserve(500), 0.5);
+
+        limiter.set_rate(UNLIMITED);
+        assert_eq!(limiter.reserve(10_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn set_rate_kib_rejects_overflow() {
+        let limiter = RateLimiter::new(UNLIMITED);
+        limiter.set_rate_kib(100).unwrap();
+        assert_eq!(limiter.rate(), 100 * 1024);
+        assert_eq!(limiter.set_rate_kib(u64::MAX / 1000), Err(RateTooLarge(u64::MAX / 1000)));
+        assert_eq!(limiter.rate(), 100 * 1024);
+        assert_eq!(kib_to_bytes(u64::MAX / 1024), Ok(u64::MAX / 1024 * 1024));
+    }
+}
@@ -0,0 +1,132 @@
+﻿//! Arquivo de retomada: quais peças já estavam no disco e o estado dos
+//! arquivos de dados quando isso foi registrado.
+//!
+//! Na retomada, peças cujos arquivos não mudaram desde o último registro são
+//! aceitas sem reler o disco; as demais precisam ser verificadas de novo.
+
+use crate::bencode;
+use crate::bitfield::Bitfield;
+use crate::metainfo::{InfoHash, Metainfo};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Tamanho e data de modificação de um arquivo de dados. Um arquivo ausente
+/// é registrado com ambos zerados.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    pub length: u64,
+    /// Nanossegundos desde a época Unix.
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeData {
+    #[serde(rename = "info hash", with = "serde_bytes")]
+    pub info_hash: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub bitfield: Vec<u8>,
+    /// Na mesma ordem de `Info::file_list`.
+    pub files: Vec<FileState>,
+}
+
+/// Peças aceitas diretamente do arquivo de retomada e peças a verificar.
+#[derive(Debug)]
+pub struct ResumePlan {
+    pub trusted: Bitfield,
+    pub uncertain: Vec<usize>,
+}
+
+impl ResumeData {
+    pub fn new(info_hash: InfoHash, bitfield: &Bitfield, files: Vec<FileState>) -> Self {
+        Self { info_hash: info_hash.as_bytes().to_vec(), bitfield: bitfield.as_bytes().to_vec(), files }
+    }
+
+    /// Lê o arquivo de retomada; retorna `None` se ele não existe.
+    pub async fn load(path: &Path) -> io::Result<Option<Self>> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        bencode::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Grava num arquivo temporário e renomeia, para nunca deixar um registro pela metade.
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = bencode::to_bytes(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp = path.with_extension("resume.tmp");
+        tokio::fs::write(&temp, bytes).await?;
+        tokio::fs::rename(&temp, path).await
+    }
+}
+
+/// Caminho do arquivo de retomada, ao lado do conteúdo baixado.
+pub fn resume_path(metainfo: &Metainfo, save_dir: &Path) -> PathBuf {
+    save_dir.join(format!("{}.resume", metainfo.info.name))
+}
+
+/// Estado atual de cada arquivo de dados do torrent.
+pub async fn file_states(metainfo: &Metainfo, save_dir: &Path) -> Vec<FileState> {
+    let mut states = Vec::new();
+    for (path, _) in metainfo.info.file_list() {
+        let state = match tokio::fs::metadata(save_dir.join(path)).await {
+            Ok(metadata) => FileState {
+                length: metadata.len(),
+                mtime: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or(0),
+            },
+            Err(_) => FileState::default(),
+        };
+        states.push(state);
+    }
+    states
+}
+
+/// Decide quais peças aceitar e quais reverificar.
+///
+/// Uma peça só é aceita se todos os arquivos que ela toca estão como no
+/// registro. Peças que tocam arquivos alterados (escritas após o último
+/// registro, por exemplo num crash) são incertas. Sem registro válido, toda
+/// peça que toca um arquivo existente é incerta.
+pub fn plan(metainfo: &Metainfo, resume: Option<&ResumeData>, current: &[FileState]) -> ResumePlan {
+    let info = &metainfo.info;
+    let piece_count = info.piece_count();
+    let resume = resume.filter(|resume| {
+        resume.info_hash == metainfo.info_hash.as_bytes() && resume.files.len() == current.len()
+    });
+    let saved_have = resume.and_then(|resume| Bitfield::from_bytes(&resume.bitfield, piece_count));
+    let (saved_have, saved_files) = match (saved_have, resume) {
+        (Some(have), Some(resume)) => (have, resume.files.clone()),
+        _ => (Bitfield::new(piece_count), vec![FileState::default(); current.len()]),
+    };
+
+    let mut uncertain = vec![false; piece_count];
+    let mut offset = 0;
+    for ((_, length), (now, saved)) in info.file_list().into_iter().zip(current.iter().zip(&saved_files)) {
+        if length > 0 && now != saved {
+            let first = (offset / info.piece_length) as usize;
+            let last = ((offset + length - 1) / info.piece_length) as usize;
+            for flag in &mut uncertain[first..=last.min(piece_count - 1)] {
+                *flag = true;
+            }
+        }
+        offset += length;
+    }
+
+    let mut trusted = Bitfield::new(piece_count);
+    for index in saved_have.iter().filter(|&index| !uncertain[index]) {
+        trusted.set(index);
+    }
+    ResumePlan {
+        trusted,
+        uncertain: (0..piece_count).filter(|&index| uncertain[index]).collect(),
+    }
+}
@@ -0,0 +1,603 @@
+﻿//! Sessão: vários torrents ao mesmo tempo, cada um com o seu estado, todos
+//! atendidos pela mesma porta de escuta do [`Peer`].
+//!
+//! Um torrent passa por `Metadata` (só para links magnet), `Checking`,
+//! `Downloading` e `Seeding`. Pode ser pausado em qualquer estado e, se não
+//! puder ser aberto, fica em `Error` até ser retomado.
+//!
+//! Com um diretório de estado ([`Session::restore`]), a lista de torrents é
+//! gravada a cada mudança e restaurada na próxima execução.
+
+use crate::announce::Event;
+use crate::magnet::Magnet;
+use crate::metainfo::{InfoHash, Metainfo};
+use crate::peer::Peer;
+use crate::state::{self, SavedTorrent, SessionState};
+use crate::storage::Allocation;
+use crate::torrent::Torrent;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tracing::{error, info, warn, Instrument};
+
+/// Intervalo entre gravações periódicas do estado, para não perder os
+/// totais transferidos se o processo for encerrado sem `shutdown`.
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorrentState {
+    /// Link magnet esperando os metadados de algum peer.
+    Metadata,
+    /// Conferindo no disco as peças já baixadas.
+    Checking,
+    Downloading,
+    Seeding,
+    Paused,
+    /// O torrent não pôde ser aberto; `resume` tenta de novo.
+    Error(String),
+}
+
+impl fmt::Display for TorrentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TorrentState::Metadata => write!(f, "buscando metadados"),
+            TorrentState::Checking => write!(f, "verificando"),
+            TorrentState::Downloading => write!(f, "baixando"),
+            TorrentState::Seeding => write!(f, "semeando"),
+            TorrentState::Paused => write!(f, "pausado"),
+            TorrentState::Error(e) => write!(f, "erro: {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    AlreadyAdded(InfoHash),
+    NotFound(InfoHash),
+    Io(io::Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::AlreadyAdded(info_hash) => write!(f, "torrent {} já está na sessão", info_hash),
+            SessionError::NotFound(info_hash) => write!(f, "torrent {} não está na sessão", info_hash),
+            SessionError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+/// Retrato de um torrent da sessão num dado momento.
+#[derive(Debug, Clone)]
+pub struct TorrentStatus {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub state: TorrentState,
+    pub save_dir: PathBuf,
+    /// Peças que temos e total de peças; zero enquanto os metadados não chegam.
+    pub pieces: (usize, usize),
+    /// Tamanho do conteúdo e quanto falta baixar, em bytes; zero até o torrent ser aberto.
+    pub size: u64,
+    pub left: u64,
+    /// Transferido nesta execução.
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Transferido desde que o torrent foi adicionado, somando execuções anteriores.
+    pub total_uploaded: u64,
+    pub total_downloaded: u64,
+    /// Taxas de recebimento e envio em bytes/s; zero se o torrent está parado.
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub peers: usize,
+}
+
+/// Torrent da sessão, aberto ou não.
+struct Entry {
+    info_hash: InfoHash,
+    /// Nome exibido até o metainfo estar disponível.
+    name: String,
+    save_dir: PathBuf,
+    magnet: Option<Magnet>,
+    metainfo: Mutex<Option<Arc<Metainfo>>>,
+    /// Torrent aberto. O lock também serializa pausa, retomada e remoção.
+    torrent: Mutex<Option<Arc<Torrent>>>,
+    state: watch::Sender<TorrentState>,
+    /// Há uma tarefa buscando os metadados ou verificando o torrent.
+    starting: AtomicBool,
+    /// Enviados e recebidos em execuções anteriores.
+    previous_totals: (u64, u64),
+}
+
+impl Entry {
+    fn new(info_hash: InfoHash, name: String, save_dir: PathBuf, state: TorrentState) -> Self {
+        Self {
+            info_hash,
+            name,
+            save_dir,
+            magnet: None,
+            metainfo: Mutex::new(None),
+            torrent: Mutex::new(None),
+            state: watch::channel(state).0,
+            starting: AtomicBool::new(false),
+            previous_totals: (0, 0),
+        }
+    }
+
+    fn state(&self) -> TorrentState {
+        self.state.borrow().clone()
+    }
+
+    /// Totais de envio e recebimento, incluindo execuções anteriores.
+    async fn totals(&self) -> (u64, u64) {
+        let (uploaded, downloaded) = self.previous_totals;
+        match self.torrent.lock().await.as_ref() {
+            Some(torrent) => (
+                uploaded + torrent.uploaded.load(Ordering::Relaxed),
+                downloaded + torrent.downloaded.load(Ordering::Relaxed),
+            ),
+            None => (uploaded, downloaded),
+        }
+    }
+
+    /// Muda o estado, a menos que o torrent tenha sido pausado nesse meio tempo.
+    fn advance(&self, state: TorrentState) {
+        self.state.send_if_modified(|current| {
+            if *current == TorrentState::Paused {
+                return false;
+            }
+            *current = state;
+            true
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    peer: Peer,
+    torrents: Mutex<HashMap<InfoHash, Arc<Entry>>>,
+    allocation: Allocation,
+    /// Onde o estado é gravado; sem ele, nada sobrevive ao processo.
+    state_dir: Option<PathBuf>,
+    /// Serializa as gravações do estado.
+    save_lock: Mutex<()>,
+}
+
+impl Session {
+    pub fn new(peer: Peer) -> Self {
+        Self::with_allocation(peer, Allocation::default())
+    }
+
+    pub fn with_allocation(peer: Peer, allocation: Allocation) -> Self {
+        Self::build(peer, allocation, None)
+    }
+
+    fn build(peer: Peer, allocation: Allocation, state_dir: Option<PathBuf>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                peer,
+                torrents: Mutex::new(HashMap::new()),
+                allocation,
+                state_dir,
+                save_lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Sessão que grava o estado em `state_dir`, começando pelos torrents de
+    /// `saved` (lido com [`SessionState::load`]). Os que estavam pausados
+    /// continuam pausados; os demais são verificados e reiniciados.
+    pub async fn restore(peer: Peer, allocation: Allocation, state_dir: PathBuf, saved: SessionState) -> Self {
+        let session = Self::build(peer, allocation, Some(state_dir.clone()));
+        for saved in saved.torrents {
+            let Some(info_hash) = saved.info_hash() else {
+                continue;
+            };
+            let metainfo = Metainfo::load(&state::metainfo_path(&state_dir, &info_hash))
+                .ok()
+                .filter(|metainfo| metainfo.info_hash == info_hash);
+            let magnet = saved.magnet.as_deref().and_then(|uri| Magnet::parse(uri).ok());
+            if metainfo.is_none() && magnet.is_none() {
+                warn!(torrent = %saved.name, "Metainfo não encontrado no estado, torrent ignorado");
+                continue;
+            }
+
+            let state = match (saved.paused, &metainfo) {
+                (true, _) => TorrentState::Paused,
+                (false, Some(_)) => TorrentState::Checking,
+                (false, None) => TorrentState::Metadata,
+            };
+            let entry = Entry {
+                magnet,
+                metainfo: Mutex::new(metainfo.map(Arc::new)),
+                previous_totals: (saved.uploaded, saved.downloaded),
+                ..Entry::new(info_hash, saved.name, saved.save_dir, state)
+            };
+            let entry = Arc::new(entry);
+            if session.insert(Arc::clone(&entry)).await.is_ok() && !saved.paused {
+                session.spawn_start(entry);
+            }
+        }
+
+        let inner = Arc::downgrade(&session.inner);
+        tokio::spawn(async move { Self::save_periodically(inner).await });
+        session
+    }
+
+    async fn save_periodically(inner: Weak<Inner>) {
+        let mut ticker = tokio::time::interval(STATE_SAVE_INTERVAL);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(inner) = inner.upgrade() else { break };
+            Self { inner }.persist().await;
+        }
+    }
+
+    /// Grava o estado, se a sessão tiver um diretório de estado; erros são só informados.
+    async fn persist(&self) {
+        if let Err(e) = self.save_state().await {
+            error!(error = %e, "Erro ao gravar o estado da sessão");
+        }
+    }
+
+    pub async fn save_state(&self) -> io::Result<()> {
+        let Some(state_dir) = &self.inner.state_dir else {
+            return Ok(());
+        };
+        let _guard = self.inner.save_lock.lock().await;
+        let entries: Vec<Arc<Entry>> = self.inner.torrents.lock().await.values().cloned().collect();
+        let mut state = SessionState { peer_name: Some(self.inner.peer.name.clone()), torrents: Vec::new() };
+        for entry in entries {
+            let metainfo = entry.metainfo.lock().await.clone();
+            if let Some(metainfo) = metainfo {
+                state::save_metainfo(state_dir, &metainfo).await?;
+            }
+            let (uploaded, downloaded) = entry.totals().await;
+            state.torrents.push(SavedTorrent {
+                info_hash: entry.info_hash.as_bytes().to_vec(),
+                name: entry.name.clone(),
+                save_dir: entry.save_dir.clone(),
+                magnet: entry.magnet.as_ref().map(|magnet| magnet.to_string()),
+                paused: entry.state() == TorrentState::Paused,
+                uploaded,
+                downloaded,
+            });
+        }
+        state.torrents.sort_by(|a, b| a.name.cmp(&b.name));
+        state.save(state_dir).await
+    }
+
+    pub fn peer(&self) -> &Peer {
+        &self.inner.peer
+    }
+
+    /// Aceita conexões na porta do peer, encaminhando cada uma ao torrent
+    /// ativo do info-hash do handshake.
+    pub async fn start_server(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.peer.start_server().await
+    }
+
+    /// Adiciona um torrent a baixar em `save_dir`. A verificação do que já
+    /// está no disco e o download seguem em segundo plano.
+    pub async fn add(&self, metainfo: Metainfo, save_dir: PathBuf) -> Result<InfoHash, SessionError> {
+        let (info_hash, name) = (metainfo.info_hash, metainfo.info.name.clone());
+        let entry = Entry {
+            metainfo: Mutex::new(Some(Arc::new(metainfo))),
+            ..Entry::new(info_hash, name, save_dir, TorrentState::Checking)
+        };
+        self.insert_and_start(entry).await
+    }
+
+    /// Adiciona um link magnet; os metadados são buscados em segundo plano e
+    /// gravados como `<nome>.torrent` em `save_dir`.
+    pub async fn add_magnet(&self, magnet: Magnet, save_dir: PathBuf) -> Result<InfoHash, SessionError> {
+        let (info_hash, name) = (magnet.info_hash, magnet.name.clone().unwrap_or_else(|| magnet.info_hash.to_hex()));
+        let entry = Entry {
+            magnet: Some(magnet),
+            ..Entry::new(info_hash, name, save_dir, TorrentState::Metadata)
+        };
+        self.insert_and_start(entry).await
+    }
+
+    /// Adiciona um torrent já aberto, como o de um arquivo local que acabamos
+    /// de gerar, e o inicia sem verificar o disco de novo.
+    pub async fn add_torrent(&self, torrent: Arc<Torrent>) -> Result<InfoHash, SessionError> {
+        let info_hash = torrent.info_hash();
+        let name = torrent.metainfo.info.name.clone();
+        let entry = Arc::new(Entry {
+            metainfo: Mutex::new(Some(Arc::clone(&torrent.metainfo))),
+            torrent: Mutex::new(Some(Arc::clone(&torrent))),
+            ..Entry::new(info_hash, name, torrent.save_dir.clone(), TorrentState::Checking)
+        });
+        self.insert(Arc::clone(&entry)).await?;
+        {
+            let _slot = entry.torrent.lock().await;
+            self.activate(&entry, torrent).await;
+        }
+        self.persist().await;
+        Ok(info_hash)
+    }
+
+    async fn insert(&self, entry: Arc<Entry>) -> Result<(), SessionError> {
+        let mut torrents = self.inner.torrents.lock().await;
+        if torrents.contains_key(&entry.info_hash) {
+            return Err(SessionError::AlreadyAdded(entry.info_hash));
+        }
+        torrents.insert(entry.info_hash, entry);
+        Ok(())
+    }
+
+    async fn insert_and_start(&self, entry: Entry) -> Result<InfoHash, SessionError> {
+        let entry = Arc::new(entry);
+        self.insert(Arc::clone(&entry)).await?;
+        let info_hash = entry.info_hash;
+        self.spawn_start(entry);
+        self.persist().await;
+        Ok(info_hash)
+    }
+
+    /// Busca os metadados se preciso, abre o torrent e o inicia, a menos que
+    /// tenha sido pausado ou removido enquanto isso.
+    fn spawn_start(&self, entry: Arc<Entry>) {
+        if entry.starting.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let session = self.clone();
+        // Antes de abrir o torrent não há o span dele; este tem os mesmos campos
+        let span = tracing::info_span!(parent: None, "torrent", torrent = %entry.name, info_hash = %entry.info_hash);
+        let start = async move {
+            let opened = session.open(&entry).await;
+            let mut slot = entry.torrent.lock().await;
+            entry.starting.store(false, Ordering::Release);
+            if !session.contains(&entry).await {
+                return;
+            }
+            match opened {
+                Ok(torrent) => {
+                    *slot = Some(Arc::clone(&torrent));
+                    if entry.state() != TorrentState::Paused {
+                        session.activate(&entry, torrent).await;
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, "Erro ao abrir o torrent");
+                    entry.advance(TorrentState::Error(e));
+                }
+            }
+        };
+        tokio::spawn(start.instrument(span));
+    }
+
+    async fn open(&self, entry: &Entry) -> Result<Arc<Torrent>, String> {
+        let metainfo = match entry.metainfo.lock().await.clone() {
+            Some(metainfo) => metainfo,
+            None => {
+                entry.advance(TorrentState::Metadata);
+                let magnet = entry.magnet.as_ref().ok_or("torrent sem metainfo")?;
+                let metainfo = self.inner.peer.fetch_metadata(magnet).await.map_err(|e| e.to_string())?;
+                // Guarda o .torrent para retomar o download sem buscar os metadados de novo
+                let torrent_path = entry.save_dir.join(format!("{}.torrent", metainfo.info.name));
+                if let Err(e) = std::fs::create_dir_all(&entry.save_dir).and_then(|_| metainfo.save(&torrent_path)) {
+                    warn!(path = %torrent_path.display(), error = %e, "Erro ao salvar o .torrent");
+                }
+                let metainfo = Arc::new(metainfo);
+                *entry.metainfo.lock().await = Some(Arc::clone(&metainfo));
+                self.persist().await;
+                metainfo
+            }
+        };
+        entry.advance(TorrentState::Checking);
+        let torrent = Torrent::open(metainfo, entry.save_dir.clone(), self.inner.allocation)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Arc::new(torrent))
+    }
+
+    /// Registra o torrent no peer e acompanha a passagem de baixando a semeando.
+    /// Deve ser chamado com o lock de `entry.torrent`.
+    async fn activate(&self, entry: &Arc<Entry>, torrent: Arc<Torrent>) {
+        torrent.restart();
+        let complete = torrent.is_complete().await;
+        entry.state.send_replace(if complete { TorrentState::Seeding } else { TorrentState::Downloading });
+        let known_peers: Vec<SocketAddr> = entry.magnet.as_ref().map(|magnet| magnet.peers.clone()).unwrap_or_default();
+        self.inner.peer.start_torrent(Arc::clone(&torrent), known_peers).await;
+
+        if !complete {
+            let entry = Arc::clone(entry);
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = torrent.wait_complete() => {
+                        info!(parent: torrent.span(), "Download concluído, semeando");
+                        entry.state.send_if_modified(|state| {
+                            if *state != TorrentState::Downloading {
+                                return false;
+                            }
+                            *state = TorrentState::Seeding;
+                            true
+                        });
+                    }
+                    _ = torrent.stopped() => {}
+                }
+            });
+        }
+    }
+
+    /// Tira o torrent do peer: fecha as conexões, anuncia `stopped` e grava a retomada.
+    async fn deactivate(&self, torrent: &Torrent) {
+        let was_active = torrent.is_active();
+        torrent.stop();
+        if self.inner.peer.remove_torrent(&torrent.info_hash()).await.is_some() && was_active {
+            let _ = self.inner.peer.announce(torrent, Event::Stopped).instrument(torrent.span().clone()).await;
+        }
+        if let Err(e) = torrent.save_resume().await {
+            warn!(parent: torrent.span(), error = %e, "Erro ao gravar a retomada");
+        }
+    }
+
+    async fn entry(&self, info_hash: &InfoHash) -> Result<Arc<Entry>, SessionError> {
+        self.inner.torrents.lock().await.get(info_hash).cloned().ok_or(SessionError::NotFound(*info_hash))
+    }
+
+    async fn contains(&self, entry: &Arc<Entry>) -> bool {
+        self.inner
+            .torrents
+            .lock()
+            .await
+            .get(&entry.info_hash)
+            .is_some_and(|current| Arc::ptr_eq(current, entry))
+    }
+
+    /// Para o torrent, mantendo-o na sessão.
+    pub async fn pause(&self, info_hash: &InfoHash) -> Result<(), SessionError> {
+        let entry = self.entry(info_hash).await?;
+        let slot = entry.torrent.lock().await;
+        if entry.state() == TorrentState::Paused {
+            return Ok(());
+        }
+        entry.state.send_replace(TorrentState::Paused);
+        if let Some(torrent) = slot.as_ref() {
+            self.deactivate(torrent).await;
+        }
+        drop(slot);
+        self.persist().await;
+        Ok(())
+    }
+
+    /// Retoma um torrent pausado ou que falhou ao abrir.
+    pub async fn resume(&self, info_hash: &InfoHash) -> Result<(), SessionError> {
+        let entry = self.entry(info_hash).await?;
+        let slot = entry.torrent.lock().await;
+        match (entry.state(), slot.as_ref()) {
+            (TorrentState::Paused, Some(torrent)) => {
+                self.activate(&entry, Arc::clone(torrent)).await;
+                drop(slot);
+            }
+            (TorrentState::Paused | TorrentState::Error(_), None) => {
+                // A tarefa que abre o torrent ainda pode estar rodando; ela segue do ponto em que está
+                let state = if entry.metainfo.lock().await.is_some() { TorrentState::Checking } else { TorrentState::Metadata };
+                entry.state.send_replace(state);
+                drop(slot);
+                self.spawn_start(entry);
+            }
+            _ => return Ok(()),
+        }
+        self.persist().await;
+        Ok(())
+    }
+
+    /// Tira o torrent da sessão; com `delete_files`, apaga também o que foi baixado.
+    pub async fn remove(&self, info_hash: &InfoHash, delete_files: bool) -> Result<(), SessionError> {
+        let entry = self.inner.torrents.lock().await.remove(info_hash).ok_or(SessionError::NotFound(*info_hash))?;
+        let slot = entry.torrent.lock().await;
+        if let Some(torrent) = slot.as_ref() {
+            self.deactivate(torrent).await;
+            if delete_files {
+                torrent.delete_files().await?;
+            }
+        }
+        drop(slot);
+        if let Some(state_dir) = &self.inner.state_dir {
+            let path = state::metainfo_path(state_dir, info_hash);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(path = %path.display(), error = %e, "Erro ao apagar o metainfo do estado");
+                }
+            }
+        }
+        self.persist().await;
+        Ok(())
+    }
+
+    /// Torrent aberto, se já passou pela verificação.
+    pub async fn torrent(&self, info_hash: &InfoHash) -> Option<Arc<Torrent>> {
+        let entry = self.entry(info_hash).await.ok()?;
+        let torrent = entry.torrent.lock().await.clone();
+        torrent
+    }
+
+    pub async fn state(&self, info_hash: &InfoHash) -> Option<TorrentState> {
+        self.entry(info_hash).await.ok().map(|entry| entry.state())
+    }
+
+    /// Recebe as mudanças de estado de um torrent.
+    pub async fn subscribe(&self, info_hash: &InfoHash) -> Option<watch::Receiver<TorrentState>> {
+        self.entry(info_hash).await.ok().map(|entry| entry.state.subscribe())
+    }
+
+    /// Estado de todos os torrents, ordenados pelo nome.
+    pub async fn list(&self) -> Vec<TorrentStatus> {
+        let entries: Vec<Arc<Entry>> = self.inner.torrents.lock().await.values().cloned().collect();
+        let mut statuses = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let torrent = entry.torrent.lock().await.clone();
+            let mut status = TorrentStatus {
+                info_hash: entry.info_hash,
+                name: entry.name.clone(),
+                state: entry.state(),
+                save_dir: entry.save_dir.clone(),
+                pieces: (0, 0),
+                size: 0,
+                left: 0,
+                uploaded: 0,
+                downloaded: 0,
+                total_uploaded: entry.previous_totals.0,
+                total_downloaded: entry.previous_totals.1,
+                download_rate: 0,
+                upload_rate: 0,
+                peers: 0,
+            };
+            if let Some(torrent) = torrent {
+                let have = torrent.bitfield().await;
+                status.name = torrent.metainfo.info.name.clone();
+                status.pieces = (have.count(), have.len());
+                status.size = torrent.metainfo.info.total_length();
+                status.left = torrent.bytes_left().await;
+                status.uploaded = torrent.uploaded.load(Ordering::Relaxed);
+                status.downloaded = torrent.downloaded.load(Ordering::Relaxed);
+                status.total_uploaded += status.uploaded;
+                status.total_downloaded += status.downloaded;
+                // A amostra é tirada mesmo pausado, para não contar a pausa na próxima taxa
+                let (download_rate, upload_rate) = torrent.rates();
+                if status.state != TorrentState::Paused {
+                    status.download_rate = download_rate;
+                    status.upload_rate = upload_rate;
+                }
+                status.peers = torrent.peer_count().await;
+            }
+            statuses.push(status);
+        }
+        statuses.sort_by(|a, b| a.name.cmp(&b.name).then(a.info_hash.cmp(&b.info_hash)));
+        statuses
+    }
+
+    /// Para todos os torrents ativos, anunciando `stopped` e gravando a retomada
+    /// e o estado da sessão. O estado de cada torrent é mantido, para que a
+    /// próxima execução o restaure como estava.
+    pub async fn shutdown(&self) {
+        let entries: Vec<Arc<Entry>> = self.inner.torrents.lock().await.values().cloned().collect();
+        for entry in entries {
+            if let Some(torrent) = entry.torrent.lock().await.as_ref() {
+                self.deactivate(torrent).await;
+            }
+        }
+        self.persist().await;
+    }
+}
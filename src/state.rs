@@ -0,0 +1,99 @@
+﻿//! Estado da sessão gravado entre execuções: nome do peer, torrents com o
+//! diretório de destino, se estavam pausados e os totais transferidos.
+//!
+//! Fica em `<diretório de dados>/bittorrent-client/`:
+//!
+//! ```text
+//! session.state            dicionário bencode com o estado
+//! torrents/<hash>.torrent  metainfo de cada torrent
+//! ```
+//!
+//! O progresso de cada download continua no arquivo de retomada ao lado do
+//! conteúdo (ver [`crate::resume`]).
+
+use crate::bencode;
+use crate::metainfo::{InfoHash, Metainfo};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const STATE_FILE: &str = "session.state";
+const TORRENTS_DIR: &str = "torrents";
+
+/// Diretório de estado padrão, dentro do diretório de dados do usuário.
+pub fn default_dir() -> PathBuf {
+    dirs::data_dir().unwrap_or_else(|| PathBuf::from(".")).join("bittorrent-client")
+}
+
+/// Onde o metainfo de um torrent da sessão é guardado.
+pub fn metainfo_path(state_dir: &Path, info_hash: &InfoHash) -> PathBuf {
+    state_dir.join(TORRENTS_DIR).join(format!("{}.torrent", info_hash.to_hex()))
+}
+
+/// Guarda o metainfo do torrent no diretório de estado, se ainda não estiver lá.
+pub async fn save_metainfo(state_dir: &Path, metainfo: &Metainfo) -> io::Result<()> {
+    let path = metainfo_path(state_dir, &metainfo.info_hash);
+    if tokio::fs::try_exists(&path).await? {
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(state_dir.join(TORRENTS_DIR)).await?;
+    tokio::fs::write(&path, metainfo.to_bytes()).await
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(rename = "peer name", default)]
+    pub peer_name: Option<String>,
+    #[serde(default)]
+    pub torrents: Vec<SavedTorrent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedTorrent {
+    #[serde(rename = "info hash", with = "serde_bytes")]
+    pub info_hash: Vec<u8>,
+    pub name: String,
+    #[serde(rename = "save dir")]
+    pub save_dir: PathBuf,
+    /// Link magnet de origem, para buscar os metadados de novo se eles
+    /// ainda não tinham chegado.
+    #[serde(default)]
+    pub magnet: Option<String>,
+    #[serde(default)]
+    pub paused: bool,
+    /// Totais desde que o torrent foi adicionado, somando todas as execuções.
+    #[serde(default)]
+    pub uploaded: u64,
+    #[serde(default)]
+    pub downloaded: u64,
+}
+
+impl SavedTorrent {
+    pub fn info_hash(&self) -> Option<InfoHash> {
+        InfoHash::from_bytes(&self.info_hash)
+    }
+}
+
+impl SessionState {
+    /// Lê o estado gravado em `state_dir`; retorna `None` se não há nenhum.
+    pub async fn load(state_dir: &Path) -> io::Result<Option<Self>> {
+        let bytes = match tokio::fs::read(state_dir.join(STATE_FILE)).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        bencode::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Grava num arquivo temporário e renomeia, como o arquivo de retomada.
+    pub async fn save(&self, state_dir: &Path) -> io::Result<()> {
+        let bytes = bencode::to_bytes(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        tokio::fs::create_dir_all(state_dir).await?;
+        let path = state_dir.join(STATE_FILE);
+        let temp = path.with_extension("state.tmp");
+        tokio::fs::write(&temp, bytes).await?;
+        tokio::fs::rename(&temp, &path).await
+    }
+}
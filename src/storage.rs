@@ -0,0 +1,334 @@
+﻿//! Armazenamento do conteúdo de um torrent: converte posições de peça em
+//! trechos de um ou mais arquivos e faz leituras e escritas posicionais, de
+//! modo que as peças possam chegar em qualquer ordem.
+
+use crate::metainfo::Info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Como os arquivos são criados antes do download.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Allocation {
+    /// Apenas define o tamanho; o sistema de arquivos não reserva os blocos.
+    #[default]
+    Sparse,
+    /// Preenche com zeros, reservando todo o espaço de uma vez.
+    Full,
+}
+
+impl std::str::FromStr for Allocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sparse" => Ok(Allocation::Sparse),
+            "full" => Ok(Allocation::Full),
+            _ => Err(format!("modo de alocação desconhecido: {} (use sparse ou full)", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StorageFile {
+    path: PathBuf,
+    /// Posição do início do arquivo no conteúdo concatenado do torrent.
+    offset: u64,
+    length: u64,
+}
+
+/// Trecho de um arquivo que corresponde a parte de um intervalo do torrent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    file: usize,
+    file_offset: u64,
+    /// Posição do trecho dentro do buffer lido ou escrito.
+    buffer_offset: usize,
+    length: usize,
+}
+
+/// Arquivos abertos, com a indicação de se foram abertos para escrita.
+type Handles = HashMap<usize, (Arc<File>, bool)>;
+
+#[derive(Clone)]
+pub struct Storage {
+    files: Arc<Vec<StorageFile>>,
+    piece_length: u64,
+    total_length: u64,
+    handles: Arc<Mutex<Handles>>,
+}
+
+impl Storage {
+    pub fn new(info: &Info, save_dir: &Path) -> Self {
+        let mut offset = 0;
+        let files = info
+            .file_list()
+            .into_iter()
+            .map(|(path, length)| {
+                let file = StorageFile { path: save_dir.join(path), offset, length };
+                offset += length;
+                file
+            })
+            .collect();
+        Self {
+            files: Arc::new(files),
+            piece_length: info.piece_length,
+            total_length: offset,
+            handles: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Caminhos e tamanhos dos arquivos, na ordem do torrent.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, u64)> + '_ {
+        self.files.iter().map(|file| (file.path.as_path(), file.length))
+    }
+
+    /// Cria todos os arquivos com o tamanho final. Dados já existentes são preservados.
+    pub async fn allocate(&self, mode: Allocation) -> io::Result<()> {
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for index in 0..storage.files.len() {
+                let length = storage.files[index].length;
+                let file = storage.handle(index, true)?;
+                let current = file.metadata()?.len();
+                if current >= length {
+                    continue;
+                }
+                match mode {
+                    Allocation::Sparse => file.set_len(length)?,
+                    Allocation::Full => {
+                        let zeros = vec![0u8; 1024 * 1024];
+                        let mut position = current;
+                        while position < length {
+                            let n = (length - position).min(zeros.len() as u64) as usize;
+                            write_all_at(&file, &zeros[..n], position)?;
+                            position += n as u64;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Lê `length` bytes a partir de `begin` dentro da peça `index`.
+    pub async fn read(&self, index: usize, begin: u64, length: usize) -> io::Result<Vec<u8>> {
+        let offset = self.piece_offset(index, begin, length)?;
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = vec![0; length];
+            for span in storage.spans(offset, length) {
+                let file = storage.handle(span.file, false)?;
+                let target = &mut buffer[span.buffer_offset..span.buffer_offset + span.length];
+                read_exact_at(&file, target, span.file_offset)?;
+            }
+            Ok(buffer)
+        })
+        .await?
+    }
+
+    /// Grava `data` a partir de `begin` dentro da peça `index`, criando os arquivos se preciso.
+    pub async fn write(&self, index: usize, begin: u64, data: Vec<u8>) -> io::Result<()> {
+        let offset = self.piece_offset(index, begin, data.len())?;
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for span in storage.spans(offset, data.len()) {
+                let file = storage.handle(span.file, true)?;
+                write_all_at(&file, &data[span.buffer_offset..span.buffer_offset + span.length], span.file_offset)?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Fecha os arquivos abertos; a próxima operação os reabre.
+    pub fn close(&self) {
+        self.handles.lock().unwrap().clear();
+    }
+
+    fn piece_offset(&self, index: usize, begin: u64, length: usize) -> io::Result<u64> {
+        let within_piece = begin.checked_add(length as u64).is_some_and(|end| end <= self.piece_length);
+        let start = (index as u64).checked_mul(self.piece_length).and_then(|start| start.checked_add(begin));
+        let end = start.and_then(|start| start.checked_add(length as u64));
+        match (start, end) {
+            (Some(start), Some(end)) if within_piece && end <= self.total_length => Ok(start),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "trecho fora dos limites do torrent")),
+        }
+    }
+
+    /// Divide o intervalo `[offset, offset + length)` entre os arquivos que ele cobre.
+    fn spans(&self, offset: u64, length: usize) -> Vec<Span> {
+        let end = offset + length as u64;
+        // Primeiro arquivo cujo fim passa do início do intervalo
+        let first = self.files.partition_point(|file| file.offset + file.length <= offset);
+        self.files[first..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, file)| file.offset < end)
+            .filter(|(_, file)| file.length > 0)
+            .map(|(i, file)| {
+                let start = offset.max(file.offset);
+                let stop = end.min(file.offset + file.length);
+                Span {
+                    file: first + i,
+                    file_offset: start - file.offset,
+                    buffer_offset: (start - offset) as usize,
+                    length: (stop - start) as usize,
+                }
+            })
+            .collect()
+    }
+
+    fn handle(&self, index: usize, write: bool) -> io::Result<Arc<File>> {
+        let mut handles = self.handles.lock().unwrap();
+        if let Some((file, writable)) = handles.get(&index) {
+            if *writable || !write {
+                return Ok(Arc::clone(file));
+            }
+        }
+        let path = &self.files[index].path;
+        let file = if write {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Sem truncar: o arquivo vai sendo preenchido peça por peça
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?
+        } else {
+            File::open(path)?
+        };
+        let file = Arc::new(file);
+        handles.insert(index, (Arc::clone(&file), write));
+        Ok(file)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "arquivo menor que o esperado")),
+            n => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, offset)? {
+            0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "falha ao gravar no arquivo")),
+            n => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::FileEntry;
+
+    /// Peças de 8 bytes sobre arquivos de 3, 0, 2, 4 e 5 bytes: a peça 0
+    /// cruza três arquivos (pulando o vazio) e a última, de 6 bytes, dois.
+    fn storage(save_dir: &Path) -> Storage {
+        let files = [("a", 3), ("vazio", 0), ("b", 2), ("c", 4), ("d", 5)]
+            .into_iter()
+            .map(|(name, length)| FileEntry { length, path: vec![name.to_string()] })
+            .collect();
+        let info = Info {
+            name: "teste".to_string(),
+            piece_length: 8,
+            pieces: vec![0; 2 * 20],
+            length: None,
+            files: Some(files),
+            private: None,
+        };
+        Storage::new(&info, save_dir)
+    }
+
+    fn span(file: usize, file_offset: u64, buffer_offset: usize, length: usize) -> Span {
+        Span { file, file_offset, buffer_offset, length }
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("bt-storage-{}", hex::encode(rand::random::<[u8; 8]>())))
+    }
+
+    #[test]
+    fn spans_cross_file_boundaries() {
+        let storage = storage(Path::new("/nenhum"));
+        assert_eq!(storage.total_length, 14);
+        assert_eq!(storage.spans(0, 8), vec![span(0, 0, 0, 3), span(2, 0, 3, 2), span(3, 0, 5, 3)]);
+        // Última peça, mais curta
+        let offset = storage.piece_offset(1, 0, 6).unwrap();
+        assert_eq!(storage.spans(offset, 6), vec![span(3, 3, 0, 1), span(4, 0, 1, 5)]);
+        // Trechos dentro de um só arquivo, inclusive logo após o arquivo vazio
+        assert_eq!(storage.spans(3, 2), vec![span(2, 0, 0, 2)]);
+        assert_eq!(storage.spans(10, 3), vec![span(4, 1, 0, 3)]);
+        assert_eq!(storage.spans(2, 2), vec![span(0, 2, 0, 1), span(2, 0, 1, 1)]);
+    }
+
+    #[test]
+    fn piece_offset_rejects_out_of_range() {
+        let storage = storage(Path::new("/nenhum"));
+        assert_eq!(storage.piece_offset(0, 0, 8).unwrap(), 0);
+        assert_eq!(storage.piece_offset(1, 2, 4).unwrap(), 10);
+        for (index, begin, length) in [(1, 0, 7), (0, 4, 5), (0, 9, 0), (2, 0, 1), (usize::MAX, 0, 1), (0, u64::MAX, 1), ((1 << 61) - 1, 0, 8)] {
+            let error = storage.piece_offset(index, begin, length).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{} {} {}", index, begin, length);
+        }
+    }
+
+    #[tokio::test]
+    async fn write_read_round_trip_across_files() {
+        let dir = temp_dir();
+        let storage = storage(&dir);
+        let content: Vec<u8> = (1..=14).collect();
+
+        // Fora de ordem e em blocos que não coincidem com os arquivos
+        storage.write(1, 0, content[8..].to_vec()).await.unwrap();
+        storage.write(0, 4, content[4..8].to_vec()).await.unwrap();
+        storage.write(0, 0, content[..4].to_vec()).await.unwrap();
+        assert!(storage.write(1, 4, vec![0; 4]).await.is_err());
+
+        assert_eq!(storage.read(0, 0, 8).await.unwrap(), content[..8]);
+        assert_eq!(storage.read(1, 0, 6).await.unwrap(), content[8..]);
+        assert_eq!(storage.read(0, 2, 5).await.unwrap(), content[2..7]);
+
+        let on_disk: Vec<Vec<u8>> = storage.files().map(|(path, _)| std::fs::read(path).unwrap_or_default()).collect();
+        assert_eq!(on_disk, vec![vec![1, 2, 3], vec![], vec![4, 5], vec![6, 7, 8, 9], vec![10, 11, 12, 13, 14]]);
+
+        storage.allocate(Allocation::Sparse).await.unwrap();
+        for (path, length) in storage.files() {
+            assert_eq!(std::fs::metadata(path).unwrap().len(), length);
+        }
+        assert_eq!(storage.read(0, 0, 8).await.unwrap(), content[..8]);
+
+        storage.close();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,649 @@
+﻿use crate::bitfield::Bitfield;
+use crate::choker::{Choker, PeerSample};
+use crate::metainfo::{InfoHash, Metainfo};
+use crate::picker::{Block, PiecePicker, Received};
+use crate::ratelimit::RateLimits;
+use crate::resume::{self, ResumeData};
+use crate::storage::{Allocation, Storage};
+use crate::wire::{PeerId, BLOCK_SIZE};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch, Mutex, Notify};
+use tracing::{info, warn, Span};
+
+/// A cada quantas peças concluídas o arquivo de retomada é regravado.
+const RESUME_SAVE_INTERVAL: usize = 16;
+
+/// Intervalo mínimo entre duas amostras de taxa; leituras mais próximas
+/// devolvem as taxas da amostra anterior.
+pub const RATE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resultado de um bloco recebido, do ponto de vista da conexão.
+pub enum BlockResult {
+    /// Bloco descartado (não pedido ou já recebido de outro peer).
+    Ignored,
+    Stored,
+    /// A peça foi completada, verificada e gravada.
+    PieceVerified(usize),
+    /// A peça foi completada mas o hash não confere; volta a faltar.
+    PieceFailed(usize),
+}
+
+/// Resultado de uma reverificação de todas as peças.
+#[derive(Debug, Clone, Default)]
+pub struct RecheckResult {
+    pub checked: usize,
+    pub valid: usize,
+    /// Peças que constavam como baixadas mas não conferem; voltaram a faltar.
+    pub bad: Vec<usize>,
+    /// Peças que não constavam como baixadas mas estão corretas no disco.
+    pub recovered: Vec<usize>,
+}
+
+/// Contadores de uma conexão, atualizados por ela e lidos pelo choker.
+#[derive(Debug, Default)]
+pub struct PeerCounters {
+    pub downloaded: AtomicU64,
+    pub uploaded: AtomicU64,
+    /// O peer está interessado nas nossas peças.
+    pub interested: AtomicBool,
+}
+
+/// Taxas de recebimento e envio em bytes/s, pela diferença dos contadores
+/// entre amostras. Compartilhado por todas as telas, para que exibam os mesmos valores.
+#[derive(Debug)]
+pub struct RateMeter(std::sync::Mutex<RateSample>);
+
+#[derive(Debug)]
+struct RateSample {
+    at: Instant,
+    downloaded: u64,
+    uploaded: u64,
+    download_rate: u64,
+    upload_rate: u64,
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateMeter {
+    /// Medidor de contadores que começam em zero agora.
+    pub fn new() -> Self {
+        Self(std::sync::Mutex::new(RateSample { at: Instant::now(), downloaded: 0, uploaded: 0, download_rate: 0, upload_rate: 0 }))
+    }
+
+    /// Registra os contadores atuais e devolve as taxas de recebimento e envio.
+    pub fn sample(&self, downloaded: u64, uploaded: u64) -> (u64, u64) {
+        self.sample_at(Instant::now(), downloaded, uploaded)
+    }
+
+    fn sample_at(&self, now: Instant, downloaded: u64, uploaded: u64) -> (u64, u64) {
+        let mut sample = self.0.lock().unwrap();
+        let elapsed = now.saturating_duration_since(sample.at);
+        if elapsed >= RATE_SAMPLE_INTERVAL {
+            let per_second = |current: u64, previous: u64| (current.saturating_sub(previous) as f64 / elapsed.as_secs_f64()) as u64;
+            sample.download_rate = per_second(downloaded, sample.downloaded);
+            sample.upload_rate = per_second(uploaded, sample.uploaded);
+            sample.at = now;
+            sample.downloaded = downloaded;
+            sample.uploaded = uploaded;
+        }
+        (sample.download_rate, sample.upload_rate)
+    }
+}
+
+/// O que a conexão recebe ao ser registrada: seus contadores e a decisão
+/// do choker (`true` quando o peer deve ficar unchoked).
+pub struct PeerSlot {
+    pub counters: Arc<PeerCounters>,
+    pub unchoked: watch::Receiver<bool>,
+}
+
+/// Retrato de uma conexão ativa, para exibição.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    pub peer_id: PeerId,
+    pub listen: Option<SocketAddr>,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    /// Taxas de recebimento e envio em bytes/s.
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub interested: bool,
+    pub unchoked: bool,
+}
+
+/// Conexão ativa com um peer.
+struct PeerEntry {
+    peer_id: PeerId,
+    /// Endereço em que o peer aceita conexões, quando conhecido; é o que
+    /// repassamos aos outros peers via ut_pex.
+    listen: Option<SocketAddr>,
+    counters: Arc<PeerCounters>,
+    rates: RateMeter,
+    unchoked: watch::Sender<bool>,
+}
+
+/// Estado de um torrent em andamento: quais peças já temos, quais estão
+/// sendo baixadas e os contadores de transferência.
+pub struct Torrent {
+    pub metainfo: Arc<Metainfo>,
+    /// Diretório de destino; o conteúdo fica em `save_dir/<nome do torrent>`.
+    pub save_dir: PathBuf,
+    storage: Storage,
+    have: Mutex<Bitfield>,
+    picker: Mutex<PiecePicker>,
+    /// Conexões ativas, indexadas pelo endereço remoto.
+    peers: Mutex<HashMap<SocketAddr, PeerEntry>>,
+    /// Peers já recebidos via ut_pex, para não repassar o mesmo endereço duas vezes.
+    pex_known: Mutex<HashSet<SocketAddr>>,
+    pex_events: broadcast::Sender<Vec<SocketAddr>>,
+    /// Acorda o choker antes da próxima rodada (peer novo interessado, por exemplo).
+    rechoke_requested: Notify,
+    pub uploaded: AtomicU64,
+    pub downloaded: AtomicU64,
+    rates: RateMeter,
+    /// Limites de banda deste torrent, somando todas as suas conexões.
+    pub limits: RateLimits,
+    complete: watch::Sender<bool>,
+    /// `false` enquanto o torrent está parado: conexões, announces e choker encerram.
+    active: watch::Sender<bool>,
+    have_events: broadcast::Sender<u32>,
+    /// Peças que deixamos de ter (falharam na reverificação).
+    lost_events: broadcast::Sender<u32>,
+    /// Blocos recebidos, para que as outras conexões cancelem pedidos duplicados.
+    block_events: broadcast::Sender<Block>,
+    /// Serializa as gravações do arquivo de retomada.
+    resume_lock: Mutex<()>,
+    /// Span de log das tarefas e conexões do torrent.
+    span: Span,
+}
+
+impl Torrent {
+    pub fn new(metainfo: Arc<Metainfo>, save_dir: PathBuf, have: Bitfield) -> Self {
+        let (complete, _) = watch::channel(have.is_complete());
+        let (active, _) = watch::channel(true);
+        let (have_events, _) = broadcast::channel(256);
+        let (lost_events, _) = broadcast::channel(256);
+        let (block_events, _) = broadcast::channel(256);
+        let (pex_events, _) = broadcast::channel(64);
+        let picker = PiecePicker::new(&metainfo.info);
+        let storage = Storage::new(&metainfo.info, &save_dir);
+        let span =
+            tracing::info_span!(parent: None, "torrent", torrent = %metainfo.info.name, info_hash = %metainfo.info_hash);
+        Self {
+            metainfo,
+            save_dir,
+            storage,
+            have: Mutex::new(have),
+            picker: Mutex::new(picker),
+            peers: Mutex::new(HashMap::new()),
+            pex_known: Mutex::new(HashSet::new()),
+            pex_events,
+            rechoke_requested: Notify::new(),
+            uploaded: AtomicU64::new(0),
+            downloaded: AtomicU64::new(0),
+            rates: RateMeter::new(),
+            limits: RateLimits::default(),
+            complete,
+            active,
+            have_events,
+            lost_events,
+            block_events,
+            resume_lock: Mutex::new(()),
+            span,
+        }
+    }
+
+    /// Abre um torrent para download, retomando o que já estiver no disco.
+    ///
+    /// Peças registradas no arquivo de retomada cujos arquivos não mudaram são
+    /// aceitas direto; as incertas são verificadas pelo hash. Em seguida os
+    /// arquivos são pré-alocados conforme `allocation`.
+    pub async fn open(metainfo: Arc<Metainfo>, save_dir: PathBuf, allocation: Allocation) -> io::Result<Self> {
+        let mut torrent = Self::new(Arc::clone(&metainfo), save_dir, Bitfield::new(metainfo.info.piece_count()));
+        let resume = match ResumeData::load(&torrent.resume_path()).await {
+            Ok(resume) => resume,
+            Err(e) => {
+                warn!(parent: &torrent.span, error = %e, "Arquivo de retomada ignorado");
+                None
+            }
+        };
+        let current = resume::file_states(&metainfo, &torrent.save_dir).await;
+        let plan = resume::plan(&metainfo, resume.as_ref(), &current);
+
+        let mut have = plan.trusted;
+        let trusted = have.count();
+        for &index in &plan.uncertain {
+            if torrent.verify_piece(index).await {
+                have.set(index);
+            }
+        }
+        if have.count() > 0 || !plan.uncertain.is_empty() {
+            info!(
+                parent: &torrent.span,
+                trusted,
+                rechecked = plan.uncertain.len(),
+                confirmed = have.count() - trusted,
+                "Retomando: peças aceitas do registro e confirmadas na reverificação"
+            );
+        }
+
+        torrent.complete.send_replace(have.is_complete());
+        *torrent.have.get_mut() = have;
+        torrent.storage.allocate(allocation).await?;
+        torrent.save_resume().await?;
+        Ok(torrent)
+    }
+
+    /// Registra as peças que temos e o estado atual dos arquivos.
+    pub async fn save_resume(&self) -> io::Result<()> {
+        let _guard = self.resume_lock.lock().await;
+        // O bitfield é lido antes dos arquivos: uma peça gravada entre as duas
+        // leituras altera o mtime e só faz a peça ser reverificada depois
+        let have = self.bitfield().await;
+        let files = resume::file_states(&self.metainfo, &self.save_dir).await;
+        ResumeData::new(self.info_hash(), &have, files).save(&self.resume_path()).await
+    }
+
+    fn resume_path(&self) -> PathBuf {
+        resume::resume_path(&self.metainfo, &self.save_dir)
+    }
+
+    /// Apaga do disco os arquivos do torrent e o arquivo de retomada. Diretórios
+    /// criados para o torrent só são removidos se ficarem vazios.
+    pub async fn delete_files(&self) -> io::Result<()> {
+        let _guard = self.resume_lock.lock().await;
+        self.storage.close();
+        let mut directories = Vec::new();
+        for (path, _) in self.storage.files() {
+            remove_if_exists(tokio::fs::remove_file(path).await)?;
+            let parents = path.ancestors().skip(1).take_while(|dir| dir.starts_with(&self.save_dir) && *dir != self.save_dir);
+            directories.extend(parents.map(PathBuf::from));
+        }
+        remove_if_exists(tokio::fs::remove_file(self.resume_path()).await)?;
+        // Os mais profundos primeiro, para que os pais já estejam vazios
+        directories.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        directories.dedup();
+        for dir in directories {
+            let _ = tokio::fs::remove_dir(&dir).await;
+        }
+        Ok(())
+    }
+
+    /// Relê todas as peças do disco, uma de cada vez, e acerta o bitfield:
+    /// peças inválidas passam a faltar e serão baixadas de novo.
+    pub async fn recheck(&self) -> io::Result<RecheckResult> {
+        let mut result = RecheckResult::default();
+        for index in 0..self.piece_count() {
+            let valid = self.verify_piece(index).await;
+            result.checked += 1;
+            if valid {
+                result.valid += 1;
+            }
+
+            let mut have = self.have.lock().await;
+            match (valid, have.has(index)) {
+                (true, false) => {
+                    have.set(index);
+                    result.recovered.push(index);
+                    let _ = self.have_events.send(index as u32);
+                }
+                (false, true) => {
+                    have.clear(index);
+                    result.bad.push(index);
+                    let _ = self.lost_events.send(index as u32);
+                }
+                _ => {}
+            }
+        }
+
+        let complete = self.is_complete().await;
+        self.complete.send_replace(complete);
+        self.save_resume().await?;
+        Ok(result)
+    }
+
+    /// Relê uma peça do disco, um bloco de cada vez, e confere o hash; erros
+    /// de leitura contam como peça ausente.
+    pub async fn verify_piece(&self, index: usize) -> bool {
+        let size = self.metainfo.info.piece_size(index) as u32;
+        let mut hasher = Sha1::new();
+        let mut begin = 0;
+        while begin < size {
+            let length = BLOCK_SIZE.min(size - begin);
+            match self.read_block(index, begin, length).await {
+                Ok(data) => hasher.update(&data),
+                Err(_) => return false,
+            }
+            begin += length;
+        }
+        self.metainfo.info.piece_hash(index) == Some(hasher.finalize().as_slice())
+    }
+
+    pub fn info_hash(&self) -> InfoHash {
+        self.metainfo.info_hash
+    }
+
+    /// Span com o nome e o info-hash, pai dos eventos de log deste torrent.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.metainfo.info.piece_count()
+    }
+
+    pub async fn bitfield(&self) -> Bitfield {
+        self.have.lock().await.clone()
+    }
+
+    pub async fn has_piece(&self, index: usize) -> bool {
+        self.have.lock().await.has(index)
+    }
+
+    pub async fn is_complete(&self) -> bool {
+        self.have.lock().await.is_complete()
+    }
+
+    /// Taxas de recebimento e envio do torrent, somando todas as conexões.
+    pub fn rates(&self) -> (u64, u64) {
+        self.rates.sample(self.downloaded.load(Ordering::Relaxed), self.uploaded.load(Ordering::Relaxed))
+    }
+
+    /// Bytes que ainda faltam baixar (o `left` do announce).
+    pub async fn bytes_left(&self) -> u64 {
+        let have = self.have.lock().await;
+        (0..self.piece_count())
+            .filter(|&index| !have.has(index))
+            .map(|index| self.metainfo.info.piece_size(index))
+            .sum()
+    }
+
+    /// Registra uma conexão; retorna `None` se já há conexão com o mesmo peer id.
+    ///
+    /// `listen` é o endereço em que o peer aceita conexões, se já conhecido.
+    /// A conexão começa choked até a próxima rodada do choker.
+    pub async fn add_peer(&self, addr: SocketAddr, peer_id: PeerId, listen: Option<SocketAddr>) -> Option<PeerSlot> {
+        let mut peers = self.peers.lock().await;
+        if peers.values().any(|entry| entry.peer_id == peer_id) {
+            return None;
+        }
+        let counters = Arc::new(PeerCounters::default());
+        let (unchoked, receiver) = watch::channel(false);
+        peers.insert(addr, PeerEntry { peer_id, listen, counters: Arc::clone(&counters), rates: RateMeter::new(), unchoked });
+        Some(PeerSlot { counters, unchoked: receiver })
+    }
+
+    /// Pede uma rodada do choker sem esperar o intervalo.
+    pub fn request_rechoke(&self) {
+        self.rechoke_requested.notify_one();
+    }
+
+    /// Espera um pedido de [`Torrent::request_rechoke`].
+    pub async fn rechoke_requested(&self) {
+        self.rechoke_requested.notified().await
+    }
+
+    /// Executa uma rodada do choker com `slots` unchokes e avisa as conexões.
+    pub async fn rechoke(&self, choker: &mut Choker, slots: usize) {
+        let seeding = self.is_complete().await;
+        let peers = self.peers.lock().await;
+        let samples: Vec<PeerSample> = peers
+            .iter()
+            .map(|(addr, entry)| PeerSample {
+                addr: *addr,
+                interested: entry.counters.interested.load(Ordering::Relaxed),
+                downloaded: entry.counters.downloaded.load(Ordering::Relaxed),
+                uploaded: entry.counters.uploaded.load(Ordering::Relaxed),
+            })
+            .collect();
+        let unchoked = choker.rechoke(&samples, slots, seeding);
+        for (addr, entry) in peers.iter() {
+            entry.unchoked.send_if_modified(|current| {
+                let next = unchoked.contains(addr);
+                std::mem::replace(current, next) != next
+            });
+        }
+    }
+
+    /// Registra a porta informada pelo peer no handshake estendido.
+    pub async fn set_listen_addr(&self, addr: &SocketAddr, listen: SocketAddr) {
+        if let Some(entry) = self.peers.lock().await.get_mut(addr) {
+            entry.listen = Some(listen);
+        }
+    }
+
+    /// Endereços em que os peers conectados aceitam conexões.
+    pub async fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.peers.lock().await.values().filter_map(|entry| entry.listen).collect()
+    }
+
+    pub async fn remove_peer(&self, addr: &SocketAddr) {
+        self.peers.lock().await.remove(addr);
+    }
+
+    pub async fn peer_count(&self) -> usize {
+        self.peers.lock().await.len()
+    }
+
+    /// Conexões ativas com os seus contadores, ordenadas pelo endereço.
+    pub async fn peers(&self) -> Vec<PeerInfo> {
+        let peers = self.peers.lock().await;
+        let mut infos: Vec<PeerInfo> = peers
+            .iter()
+            .map(|(addr, entry)| {
+                let downloaded = entry.counters.downloaded.load(Ordering::Relaxed);
+                let uploaded = entry.counters.uploaded.load(Ordering::Relaxed);
+                let (download_rate, upload_rate) = entry.rates.sample(downloaded, uploaded);
+                PeerInfo {
+                    addr: *addr,
+                    peer_id: entry.peer_id,
+                    listen: entry.listen,
+                    downloaded,
+                    uploaded,
+                    download_rate,
+                    upload_rate,
+                    interested: entry.counters.interested.load(Ordering::Relaxed),
+                    unchoked: *entry.unchoked.borrow(),
+                }
+            })
+            .collect();
+        infos.sort_unstable_by_key(|info| info.addr);
+        infos
+    }
+
+    /// Endereços das conexões ativas, incluindo as portas de escuta conhecidas.
+    pub async fn peer_addrs(&self) -> Vec<SocketAddr> {
+        let peers = self.peers.lock().await;
+        let mut addrs: Vec<SocketAddr> = peers.keys().copied().chain(peers.values().filter_map(|entry| entry.listen)).collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Repassa os peers recebidos via ut_pex que ainda não conhecíamos.
+    pub async fn add_pex_peers(&self, peers: Vec<SocketAddr>) {
+        let mut known = self.pex_known.lock().await;
+        let new: Vec<SocketAddr> = peers.into_iter().filter(|addr| known.insert(*addr)).collect();
+        if !new.is_empty() {
+            let _ = self.pex_events.send(new);
+        }
+    }
+
+    /// Recebe os peers novos descobertos via ut_pex.
+    pub fn subscribe_pex(&self) -> broadcast::Receiver<Vec<SocketAddr>> {
+        self.pex_events.subscribe()
+    }
+
+    /// Torrents privados (BEP 27) só obtêm peers do tracker.
+    pub fn is_private(&self) -> bool {
+        self.metainfo.info.private == Some(1)
+    }
+
+    /// Para o torrent: as conexões são fechadas e as tarefas dele encerram.
+    pub fn stop(&self) {
+        self.active.send_replace(false);
+    }
+
+    /// Permite que o torrent volte a conectar e anunciar depois de [`Torrent::stop`].
+    pub fn restart(&self) {
+        self.active.send_replace(true);
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self.active.borrow()
+    }
+
+    /// Espera até o torrent ser parado.
+    pub async fn stopped(&self) {
+        let mut receiver = self.active.subscribe();
+        let _ = receiver.wait_for(|active| !*active).await;
+    }
+
+    /// Espera até que todas as peças tenham sido baixadas.
+    pub async fn wait_complete(&self) {
+        let mut receiver = self.complete.subscribe();
+        let _ = receiver.wait_for(|complete| *complete).await;
+    }
+
+    /// Recebe o índice de cada peça concluída, para repassar como `have`.
+    pub fn subscribe_have(&self) -> broadcast::Receiver<u32> {
+        self.have_events.subscribe()
+    }
+
+    /// Recebe o índice de cada peça que deixamos de ter, para reavaliar o interesse.
+    pub fn subscribe_lost(&self) -> broadcast::Receiver<u32> {
+        self.lost_events.subscribe()
+    }
+
+    /// Recebe os blocos concluídos por qualquer conexão deste torrent.
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<Block> {
+        self.block_events.subscribe()
+    }
+
+    /// Atualiza a disponibilidade das peças quando um peer envia seu bitfield.
+    pub async fn peer_bitfield(&self, old: &Bitfield, new: &Bitfield) {
+        let mut picker = self.picker.lock().await;
+        picker.remove_bitfield(old);
+        picker.add_bitfield(new);
+    }
+
+    pub async fn peer_have(&self, index: usize) {
+        self.picker.lock().await.add_have(index);
+    }
+
+    /// Remove um peer desconectado da contagem de disponibilidade e libera seus pedidos.
+    pub async fn peer_gone(&self, addr: SocketAddr, remote: &Bitfield) {
+        let mut picker = self.picker.lock().await;
+        picker.remove_bitfield(remote);
+        picker.cancel_peer(addr);
+    }
+
+    /// Libera os pedidos pendentes de um peer que nos deu choke.
+    pub async fn cancel_requests(&self, addr: SocketAddr) {
+        self.picker.lock().await.cancel_peer(addr);
+    }
+
+    /// Escolhe até `max` blocos para pedir ao peer em `addr`.
+    pub async fn pick_blocks(&self, addr: SocketAddr, remote: &Bitfield, max: usize) -> Vec<Block> {
+        let have = self.have.lock().await;
+        self.picker.lock().await.pick(addr, remote, &have, max)
+    }
+
+    /// Entrega um bloco recebido; ao completar a peça, verifica e grava.
+    pub async fn receive_block(&self, addr: SocketAddr, block: Block, data: &[u8]) -> io::Result<BlockResult> {
+        let (received, others) = self.picker.lock().await.receive(addr, block, data);
+        if !others.is_empty() {
+            let _ = self.block_events.send(block);
+        }
+        match received {
+            Received::Ignored => Ok(BlockResult::Ignored),
+            Received::Stored => Ok(BlockResult::Stored),
+            Received::PieceDone(piece) => {
+                let index = block.index as usize;
+                if self.complete_piece(index, piece).await? {
+                    Ok(BlockResult::PieceVerified(index))
+                } else {
+                    Ok(BlockResult::PieceFailed(index))
+                }
+            }
+        }
+    }
+
+    /// Lê um bloco de uma peça que já temos.
+    pub async fn read_block(&self, index: usize, begin: u32, length: u32) -> io::Result<Vec<u8>> {
+        self.storage.read(index, begin as u64, length as usize).await
+    }
+
+    /// Confere o hash de uma peça recebida e a grava no disco.
+    ///
+    /// Retorna `false` se o hash não confere; nesse caso a peça volta a faltar.
+    /// Até o fim, a peça continua reservada no picker.
+    async fn complete_piece(&self, index: usize, data: Vec<u8>) -> io::Result<bool> {
+        let expected = self.metainfo.info.piece_hash(index);
+        let length = data.len() as u64;
+        let stored = if expected == Some(Sha1::digest(&data).as_slice()) {
+            self.storage.write(index, 0, data).await.map(|()| true)
+        } else {
+            Ok(false)
+        };
+        let counts = match stored {
+            Ok(true) => {
+                let mut have = self.have.lock().await;
+                have.set(index);
+                Some((have.count(), have.is_complete()))
+            }
+            _ => None,
+        };
+        self.picker.lock().await.finish(index);
+        let Some((count, complete)) = counts else {
+            return stored;
+        };
+
+        self.downloaded.fetch_add(length, Ordering::Relaxed);
+        let _ = self.have_events.send(index as u32);
+        if complete || count.is_multiple_of(RESUME_SAVE_INTERVAL) {
+            if let Err(e) = self.save_resume().await {
+                warn!(error = %e, "Erro ao gravar o arquivo de retomada");
+            }
+        }
+        if complete {
+            self.complete.send_replace(true);
+        }
+        Ok(true)
+    }
+}
+
+fn remove_if_exists(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_meter_keeps_rates_between_samples() {
+        let meter = RateMeter::new();
+        let start = meter.0.lock().unwrap().at;
+        assert_eq!(meter.sample_at(start + RATE_SAMPLE_INTERVAL / 2, 1000, 10), (0, 0));
+        assert_eq!(meter.sample_at(start + Duration::from_secs(2), 4000, 1000), (2000, 500));
+        // Leituras próximas repetem a amostra anterior, qualquer que seja a tela
+        assert_eq!(meter.sample_at(start + Duration::from_millis(2500), 9000, 9000), (2000, 500));
+        assert_eq!(meter.sample_at(start + Duration::from_secs(4), 4000, 3000), (0, 1000));
+    }
+}
@@ -1,60 +1,669 @@
-﻿use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+﻿use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::Mutex;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use rand::seq::SliceRandom;
+use sha1::{Digest, Sha1};
+use crate::announce::Event;
+use crate::bencode::{self, Dict, Value};
+use crate::codec::Framed;
+use crate::http::{HttpCodec, Request, Response};
+use futures_util::{SinkExt, StreamExt};
+use crate::metainfo::InfoHash;
+use crate::wire::PeerId;
+use tracing::{info, warn, Instrument};
+
+/// Intervalo sugerido aos peers entre announces, em segundos.
+pub const ANNOUNCE_INTERVAL: u32 = 120;
+
+/// Intervalo mínimo que os peers devem respeitar, em segundos.
+pub const MIN_ANNOUNCE_INTERVAL: u32 = 30;
+
+const DEFAULT_NUMWANT: usize = 50;
+const MAX_NUMWANT: usize = 200;
+
+/// Constante mágica do pedido `connect` do protocolo UDP (BEP 15).
+pub const UDP_PROTOCOL_ID: u64 = 0x41727101980;
+
+pub const UDP_ACTION_CONNECT: u32 = 0;
+pub const UDP_ACTION_ANNOUNCE: u32 = 1;
+pub const UDP_ACTION_SCRAPE: u32 = 2;
+pub const UDP_ACTION_ERROR: u32 = 3;
+
+/// Quantos info-hashes cabem num scrape UDP sem passar do tamanho de um pacote.
+const UDP_MAX_SCRAPE: usize = 74;
+
+struct SwarmPeer {
+    addr: SocketAddr,
+    left: u64,
+    last_seen: Instant,
+}
+
+/// Peers de um torrent e quantas vezes ele foi baixado por completo.
+#[derive(Default)]
+struct Swarm {
+    peers: HashMap<PeerId, SwarmPeer>,
+    downloaded: u32,
+}
+
+impl Swarm {
+    fn complete(&self) -> u32 {
+        self.peers.values().filter(|p| p.left == 0).count() as u32
+    }
+
+    fn incomplete(&self) -> u32 {
+        self.peers.len() as u32 - self.complete()
+    }
+}
+
+/// Dados de um announce, independente de ter chegado por HTTP ou UDP.
+#[derive(Debug, Clone)]
+pub struct AnnounceParams {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+    pub addr: SocketAddr,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub event: Event,
+    pub numwant: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnounceReply {
+    pub interval: u32,
+    pub complete: u32,
+    pub incomplete: u32,
+    pub peers: Vec<(PeerId, SocketAddr)>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScrapeStats {
+    pub complete: u32,
+    pub incomplete: u32,
+    pub downloaded: u32,
+}
 
 #[derive(Clone)]
 pub struct Tracker {
-    peers: Arc<Mutex<HashSet<String>>>,
+    swarms: Arc<Mutex<HashMap<InfoHash, Swarm>>>,
+    interval: u32,
+    /// Segredo usado para gerar connection ids UDP sem guardar estado.
+    secret: [u8; 16],
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Tracker {
     pub fn new() -> Self {
         Self {
-            peers: Arc::new(Mutex::new(HashSet::new())),
+            swarms: Arc::new(Mutex::new(HashMap::new())),
+            interval: ANNOUNCE_INTERVAL,
+            secret: rand::random(),
         }
     }
 
-    /// Inicia o servidor tracker
+    /// Inicia o servidor tracker HTTP (`/announce` e `/scrape`)
     pub async fn start(&self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-        println!("Tracker rodando na porta {}", port);
+        info!(port, "Tracker rodando");
+        self.serve(listener).await
+    }
 
+    /// Atende o tracker HTTP num socket já aberto (em loopback, nos testes).
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), Box<dyn std::error::Error>> {
         loop {
-            let (mut socket, _) = listener.accept().await?;
-            let peers = Arc::clone(&self.peers);
-
-            tokio::spawn(async move {
-                let mut buffer = [0; 1024];
-                if let Ok(n) = socket.read(&mut buffer).await {
-                    let request = String::from_utf8_lossy(&buffer[..n]).to_string();
-
-                    if request.starts_with("REGISTER") {
-                        let peer_info = request[9..].to_string();
-                        let parts: Vec<&str> = peer_info.split(':').collect();
-                        if parts.len() == 3 {
-                            let peer_addr = format!("{}:{}", parts[1], parts[2]);
-                            // Clone peer_addr antes de inserir
-                            peers.lock().await.insert(peer_addr.clone());
-                            println!("Peer registrado: {} em {}", parts[0], peer_addr);
-                        }
-                    }
+            let (socket, remote) = listener.accept().await?;
+            let tracker = self.clone();
 
-                    if request.starts_with("GET_PEERS") {
-                        let peer_list: Vec<String> = peers.lock().await.iter().cloned().collect();
-                        let response = peer_list.join(",");
-                        socket.write_all(response.as_bytes()).await.unwrap();
+            let span = tracing::debug_span!("tracker", %remote);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = tracker.handle_http(socket, remote).await {
+                        warn!(error = %e, "Erro na requisição");
                     }
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    async fn handle_http(&self, socket: TcpStream, remote: SocketAddr) -> std::io::Result<()> {
+        let mut framed = Framed::new(socket, HttpCodec);
+        let Some(request) = tokio::time::timeout(Duration::from_secs(10), framed.next()).await? else {
+            return Ok(());
+        };
+        let request = request?;
+
+        let body = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/announce") => match parse_announce(&request, remote.ip()) {
+                Ok(params) => {
+                    let compact = request.query_str("compact") != Some("0");
+                    let no_peer_id = request.query_str("no_peer_id") == Some("1");
+                    let reply = self.announce(&params).await;
+                    encode_announce(&reply, compact, no_peer_id)
+                }
+                Err(reason) => failure(reason),
+            },
+            ("GET", "/scrape") => {
+                let hashes: Vec<InfoHash> = request.query_all("info_hash").filter_map(InfoHash::from_bytes).collect();
+                encode_scrape(&self.scrape(&hashes).await)
+            }
+            _ => {
+                return framed.send(Response::text(404, "não encontrado")).await;
+            }
+        };
+
+        framed.send(Response::new(200, "text/plain", body)).await
+    }
 
-                    if request.starts_with("UNREGISTER") {
-                        let peer_info = request[11..].to_string();
-                        peers.lock().await.remove(&peer_info);
-                        println!("Peer removido: {}", peer_info);
+    /// Inicia o servidor tracker UDP (BEP 15)
+    pub async fn start_udp(&self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+        let socket = UdpSocket::bind(format!("0.0.0.0:{}", port)).await?;
+        info!(port, "Tracker UDP rodando");
+        self.serve_udp(socket).await
+    }
+
+    /// Atende o tracker UDP num socket já aberto.
+    pub async fn serve_udp(&self, socket: UdpSocket) -> Result<(), Box<dyn std::error::Error>> {
+        let mut buffer = [0u8; 2048];
+        loop {
+            let (n, remote) = socket.recv_from(&mut buffer).await?;
+            if let Some(reply) = self.handle_udp(&buffer[..n], remote).await {
+                if let Err(e) = socket.send_to(&reply, remote).await {
+                    warn!(%remote, error = %e, "Erro ao responder pelo UDP");
+                }
+            }
+        }
+    }
+
+    /// Processa um pacote UDP; pacotes inválidos sem transaction id são ignorados.
+    pub async fn handle_udp(&self, packet: &[u8], remote: SocketAddr) -> Option<Vec<u8>> {
+        if packet.len() < 16 {
+            return None;
+        }
+        let connection_id = u64::from_be_bytes(packet[0..8].try_into().unwrap());
+        let action = u32::from_be_bytes(packet[8..12].try_into().unwrap());
+        let transaction_id = u32::from_be_bytes(packet[12..16].try_into().unwrap());
+
+        if action == UDP_ACTION_CONNECT {
+            if connection_id != UDP_PROTOCOL_ID {
+                return None;
+            }
+            let mut reply = udp_header(UDP_ACTION_CONNECT, transaction_id);
+            reply.extend_from_slice(&self.connection_id(&remote, current_minute()).to_be_bytes());
+            return Some(reply);
+        }
+
+        if !self.is_valid_connection_id(connection_id, &remote) {
+            return Some(udp_error(transaction_id, "connection id inválido"));
+        }
+
+        match action {
+            UDP_ACTION_ANNOUNCE => {
+                let Some(params) = parse_udp_announce(packet, remote) else {
+                    return Some(udp_error(transaction_id, "announce inválido"));
+                };
+                let reply = self.announce(&params).await;
+                let mut out = udp_header(UDP_ACTION_ANNOUNCE, transaction_id);
+                out.extend_from_slice(&reply.interval.to_be_bytes());
+                out.extend_from_slice(&reply.incomplete.to_be_bytes());
+                out.extend_from_slice(&reply.complete.to_be_bytes());
+                // Só é possível devolver peers da mesma família do pacote recebido
+                for (_, addr) in &reply.peers {
+                    match (addr.ip(), remote.is_ipv4()) {
+                        (IpAddr::V4(ip), true) => out.extend_from_slice(&ip.octets()),
+                        (IpAddr::V6(ip), false) => out.extend_from_slice(&ip.octets()),
+                        _ => continue,
                     }
+                    out.extend_from_slice(&addr.port().to_be_bytes());
                 }
-            });
+                Some(out)
+            }
+            UDP_ACTION_SCRAPE => {
+                let hashes: Vec<InfoHash> = packet[16..]
+                    .chunks_exact(20)
+                    .take(UDP_MAX_SCRAPE)
+                    .filter_map(InfoHash::from_bytes)
+                    .collect();
+                if hashes.is_empty() {
+                    return Some(udp_error(transaction_id, "scrape sem info-hash"));
+                }
+                let mut out = udp_header(UDP_ACTION_SCRAPE, transaction_id);
+                for (_, stats) in self.scrape(&hashes).await {
+                    out.extend_from_slice(&stats.complete.to_be_bytes());
+                    out.extend_from_slice(&stats.downloaded.to_be_bytes());
+                    out.extend_from_slice(&stats.incomplete.to_be_bytes());
+                }
+                Some(out)
+            }
+            _ => Some(udp_error(transaction_id, "ação desconhecida")),
         }
     }
-} 
 
+    /// Connection id derivado do endereço e do minuto atual, sem estado no servidor.
+    fn connection_id(&self, remote: &SocketAddr, minute: u64) -> u64 {
+        let mut hasher = Sha1::new();
+        hasher.update(self.secret);
+        hasher.update(remote.to_string().as_bytes());
+        hasher.update(minute.to_be_bytes());
+        u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap())
+    }
+
+    /// Aceita ids gerados nos últimos dois minutos, como recomenda o BEP 15.
+    fn is_valid_connection_id(&self, connection_id: u64, remote: &SocketAddr) -> bool {
+        let now = current_minute();
+        (0..=2).any(|age| self.connection_id(remote, now.saturating_sub(age)) == connection_id)
+    }
+
+    /// Registra o announce de um peer e devolve outros peers do mesmo torrent.
+    pub async fn announce(&self, params: &AnnounceParams) -> AnnounceReply {
+        let mut swarms = self.swarms.lock().await;
+        let swarm = swarms.entry(params.info_hash).or_default();
+
+        // Descarta peers que pararam de anunciar
+        let expiry = Duration::from_secs(self.interval as u64 * 2);
+        swarm.peers.retain(|_, peer| peer.last_seen.elapsed() < expiry);
+
+        match params.event {
+            Event::Stopped => {
+                swarm.peers.remove(&params.peer_id);
+                info!(peer = %params.addr, info_hash = %params.info_hash, "Peer removido");
+            }
+            event => {
+                if event == Event::Completed {
+                    swarm.downloaded += 1;
+                }
+                let previous = swarm.peers.insert(
+                    params.peer_id,
+                    SwarmPeer { addr: params.addr, left: params.left, last_seen: Instant::now() },
+                );
+                if previous.is_none() {
+                    info!(peer = %params.addr, info_hash = %params.info_hash, left = params.left, "Peer registrado");
+                }
+            }
+        }
+
+        let numwant = params.numwant.unwrap_or(DEFAULT_NUMWANT).min(MAX_NUMWANT);
+        let mut peers: Vec<(PeerId, SocketAddr)> = swarm
+            .peers
+            .iter()
+            .filter(|(id, _)| **id != params.peer_id)
+            .map(|(id, peer)| (*id, peer.addr))
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(numwant);
+
+        AnnounceReply {
+            interval: self.interval,
+            complete: swarm.complete(),
+            incomplete: swarm.incomplete(),
+            peers,
+        }
+    }
+
+    /// Estatísticas dos torrents pedidos, ou de todos se a lista estiver vazia.
+    pub async fn scrape(&self, info_hashes: &[InfoHash]) -> Vec<(InfoHash, ScrapeStats)> {
+        let swarms = self.swarms.lock().await;
+        let stats = |swarm: &Swarm| ScrapeStats {
+            complete: swarm.complete(),
+            incomplete: swarm.incomplete(),
+            downloaded: swarm.downloaded,
+        };
+        if info_hashes.is_empty() {
+            swarms.iter().map(|(hash, swarm)| (*hash, stats(swarm))).collect()
+        } else {
+            info_hashes
+                .iter()
+                .map(|hash| (*hash, swarms.get(hash).map(stats).unwrap_or_default()))
+                .collect()
+        }
+    }
+}
+
+fn parse_announce(request: &Request, remote_ip: IpAddr) -> Result<AnnounceParams, &'static str> {
+    let info_hash = request
+        .query_param("info_hash")
+        .and_then(InfoHash::from_bytes)
+        .ok_or("info_hash inválido")?;
+    let peer_id: PeerId = request
+        .query_param("peer_id")
+        .and_then(|id| id.try_into().ok())
+        .ok_or("peer_id inválido")?;
+    let port: u16 = number(request, "port")?.ok_or("port ausente")?;
+    let ip = request
+        .query_str("ip")
+        .and_then(|ip| ip.parse().ok())
+        .unwrap_or(remote_ip);
+    let event = match request.query_str("event").unwrap_or("") {
+        "" | "empty" => Event::None,
+        "started" => Event::Started,
+        "completed" => Event::Completed,
+        "stopped" => Event::Stopped,
+        _ => return Err("event inválido"),
+    };
+
+    Ok(AnnounceParams {
+        info_hash,
+        peer_id,
+        addr: SocketAddr::new(ip, port),
+        uploaded: number(request, "uploaded")?.unwrap_or(0),
+        downloaded: number(request, "downloaded")?.unwrap_or(0),
+        left: number(request, "left")?.unwrap_or(0),
+        event,
+        numwant: number(request, "numwant")?,
+    })
+}
+
+fn parse_udp_announce(packet: &[u8], remote: SocketAddr) -> Option<AnnounceParams> {
+    if packet.len() < 98 {
+        return None;
+    }
+    let u32_at = |offset: usize| u32::from_be_bytes(packet[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_be_bytes(packet[offset..offset + 8].try_into().unwrap());
+
+    let event = match u32_at(80) {
+        0 => Event::None,
+        1 => Event::Completed,
+        2 => Event::Started,
+        3 => Event::Stopped,
+        _ => return None,
+    };
+    let ip = match u32_at(84) {
+        0 => remote.ip(),
+        ip => IpAddr::V4(ip.into()),
+    };
+    let numwant = match u32_at(92) as i32 {
+        n if n < 0 => None,
+        n => Some(n as usize),
+    };
+    let port = u16::from_be_bytes([packet[96], packet[97]]);
+
+    Some(AnnounceParams {
+        info_hash: InfoHash::from_bytes(&packet[16..36])?,
+        peer_id: packet[36..56].try_into().ok()?,
+        addr: SocketAddr::new(ip, port),
+        downloaded: u64_at(56),
+        left: u64_at(64),
+        uploaded: u64_at(72),
+        event,
+        numwant,
+    })
+}
+
+fn udp_header(action: u32, transaction_id: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16);
+    out.extend_from_slice(&action.to_be_bytes());
+    out.extend_from_slice(&transaction_id.to_be_bytes());
+    out
+}
+
+fn udp_error(transaction_id: u32, message: &str) -> Vec<u8> {
+    let mut out = udp_header(UDP_ACTION_ERROR, transaction_id);
+    out.extend_from_slice(message.as_bytes());
+    out
+}
+
+fn current_minute() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 60)
+        .unwrap_or(0)
+}
+
+fn number<T: std::str::FromStr>(request: &Request, name: &'static str) -> Result<Option<T>, &'static str> {
+    match request.query_str(name) {
+        Some(value) => value.parse().map(Some).map_err(|_| "parâmetro numérico inválido"),
+        None => Ok(None),
+    }
+}
+
+fn failure(reason: &str) -> Vec<u8> {
+    let mut dict = Dict::new();
+    dict.insert(b"failure reason".to_vec(), Value::from(reason));
+    bencode::encode(&Value::Dict(dict))
+}
+
+/// Resposta de announce; no modo compacto (BEP 23) cada peer ocupa 6 bytes (18 no IPv6).
+fn encode_announce(reply: &AnnounceReply, compact: bool, no_peer_id: bool) -> Vec<u8> {
+    let mut dict = Dict::new();
+    dict.insert(b"interval".to_vec(), Value::Integer(reply.interval as i64));
+    dict.insert(b"min interval".to_vec(), Value::Integer(MIN_ANNOUNCE_INTERVAL as i64));
+    dict.insert(b"complete".to_vec(), Value::Integer(reply.complete as i64));
+    dict.insert(b"incomplete".to_vec(), Value::Integer(reply.incomplete as i64));
+
+    if compact {
+        let mut peers = Vec::new();
+        let mut peers6 = Vec::new();
+        for (_, addr) in &reply.peers {
+            match addr.ip() {
+                IpAddr::V4(ip) => peers.extend_from_slice(&ip.octets()),
+                IpAddr::V6(ip) => peers6.extend_from_slice(&ip.octets()),
+            }
+            let target = if addr.is_ipv4() { &mut peers } else { &mut peers6 };
+            target.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        dict.insert(b"peers".to_vec(), Value::Bytes(peers));
+        if !peers6.is_empty() {
+            dict.insert(b"peers6".to_vec(), Value::Bytes(peers6));
+        }
+    } else {
+        let peers = reply
+            .peers
+            .iter()
+            .map(|(id, addr)| {
+                let mut peer = Dict::new();
+                if !no_peer_id {
+                    peer.insert(b"peer id".to_vec(), Value::from(id.as_slice()));
+                }
+                peer.insert(b"ip".to_vec(), Value::from(addr.ip().to_string()));
+                peer.insert(b"port".to_vec(), Value::Integer(addr.port() as i64));
+                Value::Dict(peer)
+            })
+            .collect();
+        dict.insert(b"peers".to_vec(), Value::List(peers));
+    }
+
+    bencode::encode(&Value::Dict(dict))
+}
+
+fn encode_scrape(stats: &[(InfoHash, ScrapeStats)]) -> Vec<u8> {
+    let mut files = Dict::new();
+    for (hash, entry) in stats {
+        let mut file = Dict::new();
+        file.insert(b"complete".to_vec(), Value::Integer(entry.complete as i64));
+        file.insert(b"incomplete".to_vec(), Value::Integer(entry.incomplete as i64));
+        file.insert(b"downloaded".to_vec(), Value::Integer(entry.downloaded as i64));
+        files.insert(hash.as_bytes().to_vec(), Value::Dict(file));
+    }
+    let mut dict = Dict::new();
+    dict.insert(b"files".to_vec(), Value::Dict(files));
+    bencode::encode(&Value::Dict(dict))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::announce::{self, AnnounceRequest, UdpTrackerClient};
+    use crate::http::percent_encode;
+
+    const INFO_HASH: [u8; 20] = [7; 20];
+
+    fn request(peer: u8, port: u16, left: u64) -> AnnounceRequest {
+        AnnounceRequest {
+            info_hash: InfoHash::from_bytes(&INFO_HASH).unwrap(),
+            peer_id: [peer; 20],
+            port,
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            event: Event::Started,
+            numwant: Some(10),
+        }
+    }
+
+    /// Cliente UDP com esperas curtas: em loopback a resposta chega em milissegundos.
+    fn udp_client() -> UdpTrackerClient {
+        UdpTrackerClient { base_timeout: Duration::from_millis(200), max_retries: 2 }
+    }
+
+    async fn start_udp(tracker: &Tracker) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let tracker = tracker.clone();
+        tokio::spawn(async move { tracker.serve_udp(socket).await.unwrap() });
+        addr
+    }
+
+    async fn start_http(tracker: &Tracker) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tracker = tracker.clone();
+        tokio::spawn(async move { tracker.serve(listener).await.unwrap() });
+        addr
+    }
+
+    /// Pacote UDP cru, para simular clientes que não seguem o protocolo.
+    async fn send_raw(tracker: SocketAddr, connection_id: u64, action: u32) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut packet = connection_id.to_be_bytes().to_vec();
+        packet.extend_from_slice(&action.to_be_bytes());
+        packet.extend_from_slice(&0xCAFEu32.to_be_bytes());
+        packet.extend_from_slice(&INFO_HASH);
+        socket.send_to(&packet, tracker).await.unwrap();
+        let mut buffer = [0u8; 1024];
+        let (n, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buffer)).await.unwrap().unwrap();
+        buffer[..n].to_vec()
+    }
+
+    #[tokio::test]
+    async fn udp_connect_announce_scrape() {
+        let tracker = Tracker::new();
+        let url = format!("udp://{}/announce", start_udp(&tracker).await);
+        let client = udp_client();
+
+        let seeder = client.announce(&url, &request(1, 7001, 0)).await.unwrap();
+        assert_eq!(seeder.interval, ANNOUNCE_INTERVAL);
+        assert_eq!((seeder.complete, seeder.incomplete), (Some(1), Some(0)));
+        assert!(seeder.peers.is_empty());
+
+        let leecher = client.announce(&url, &request(2, 7002, 100)).await.unwrap();
+        assert_eq!((leecher.complete, leecher.incomplete), (Some(1), Some(1)));
+        assert_eq!(leecher.peers, vec!["127.0.0.1:7001".parse().unwrap()]);
+
+        let info_hash = InfoHash::from_bytes(&INFO_HASH).unwrap();
+        let unknown = InfoHash::from_bytes(&[9; 20]).unwrap();
+        let stats = client.scrape(&url, &[info_hash, unknown]).await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].1.complete, stats[0].1.incomplete, stats[0].1.downloaded), (1, 1, 0));
+        assert_eq!((stats[1].1.complete, stats[1].1.incomplete), (0, 0));
+
+        let mut stopped = request(2, 7002, 100);
+        stopped.event = Event::Stopped;
+        client.announce(&url, &stopped).await.unwrap();
+        let stats = client.scrape(&url, &[info_hash]).await.unwrap();
+        assert_eq!((stats[0].1.complete, stats[0].1.incomplete), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn udp_rejects_forged_connection_id() {
+        let tracker = Tracker::new();
+        let addr = start_udp(&tracker).await;
+
+        let reply = send_raw(addr, 0x1234_5678, UDP_ACTION_SCRAPE).await;
+        assert_eq!(u32::from_be_bytes(reply[0..4].try_into().unwrap()), UDP_ACTION_ERROR);
+        assert_eq!(u32::from_be_bytes(reply[4..8].try_into().unwrap()), 0xCAFE);
+
+        // Com o id certo, o mesmo pacote é aceito
+        let connect = send_raw(addr, UDP_PROTOCOL_ID, UDP_ACTION_CONNECT).await;
+        assert_eq!(u32::from_be_bytes(connect[0..4].try_into().unwrap()), UDP_ACTION_CONNECT);
+    }
+
+    #[tokio::test]
+    async fn udp_rejects_stale_connection_id() {
+        let tracker = Tracker::new();
+        let remote: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let packet = |connection_id: u64| {
+            let mut packet = connection_id.to_be_bytes().to_vec();
+            packet.extend_from_slice(&UDP_ACTION_SCRAPE.to_be_bytes());
+            packet.extend_from_slice(&1u32.to_be_bytes());
+            packet.extend_from_slice(&INFO_HASH);
+            packet
+        };
+        let action = |reply: Vec<u8>| u32::from_be_bytes(reply[0..4].try_into().unwrap());
+        let now = current_minute();
+
+        // Um minuto atrás ainda vale mesmo que o minuto vire durante o teste
+        let fresh = tracker.connection_id(&remote, now - 1);
+        assert_eq!(action(tracker.handle_udp(&packet(fresh), remote).await.unwrap()), UDP_ACTION_SCRAPE);
+        let stale = tracker.connection_id(&remote, now - 3);
+        assert_eq!(action(tracker.handle_udp(&packet(stale), remote).await.unwrap()), UDP_ACTION_ERROR);
+        // O id vale só para o endereço que o pediu
+        let other: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        assert_eq!(action(tracker.handle_udp(&packet(fresh), other).await.unwrap()), UDP_ACTION_ERROR);
+        // Connect sem a constante do protocolo é ignorado
+        let mut connect = packet(0);
+        connect[8..12].copy_from_slice(&UDP_ACTION_CONNECT.to_be_bytes());
+        assert!(tracker.handle_udp(&connect, remote).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn http_compact_announce_and_scrape() {
+        let tracker = Tracker::new();
+        let url = format!("http://{}/announce", start_http(&tracker).await);
+
+        announce::announce(&url, &request(1, 7001, 0)).await.unwrap();
+        let reply = announce::announce(&url, &request(2, 7002, 50)).await.unwrap();
+        assert_eq!(reply.interval, ANNOUNCE_INTERVAL);
+        assert_eq!(reply.min_interval, Some(MIN_ANNOUNCE_INTERVAL));
+        assert_eq!((reply.complete, reply.incomplete), (Some(1), Some(1)));
+        assert_eq!(reply.peers, vec!["127.0.0.1:7001".parse().unwrap()]);
+
+        let info_hash = InfoHash::from_bytes(&INFO_HASH).unwrap();
+        let stats = announce::scrape(&url, &[info_hash]).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].0, info_hash);
+        assert_eq!((stats[0].1.complete, stats[0].1.incomplete), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn http_non_compact_announce() {
+        let tracker = Tracker::new();
+        let addr = start_http(&tracker).await;
+        announce::announce(&format!("http://{}/announce", addr), &request(1, 7001, 0)).await.unwrap();
+
+        let get = |extra: &str| {
+            let url = format!(
+                "http://{}/announce?info_hash={}&peer_id={}&port=7002&left=10&compact=0{}",
+                addr,
+                percent_encode(&INFO_HASH),
+                percent_encode(&[2; 20]),
+                extra
+            );
+            async move { bencode::decode(&reqwest::get(url).await.unwrap().bytes().await.unwrap()).unwrap() }
+        };
+
+        let response = get("").await;
+        let peers = response.get("peers").and_then(Value::as_list).unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].get("ip").and_then(Value::as_str), Some("127.0.0.1"));
+        assert_eq!(peers[0].get("port").and_then(Value::as_int), Some(7001));
+        assert_eq!(peers[0].get("peer id").and_then(Value::as_bytes), Some(&[1u8; 20][..]));
+
+        let response = get("&no_peer_id=1").await;
+        let peers = response.get("peers").and_then(Value::as_list).unwrap();
+        assert!(peers[0].get("peer id").is_none());
+
+        let response = bencode::decode(
+            &reqwest::get(format!("http://{}/announce?peer_id=x", addr)).await.unwrap().bytes().await.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response.get("failure reason").and_then(Value::as_str), Some("info_hash inválido"));
+    }
+}
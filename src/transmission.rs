@@ -0,0 +1,628 @@
+﻿//! Compatibilidade com a RPC do Transmission (`POST /transmission/rpc`), para
+//! que interfaces remotas e scripts feitos para ele controlem esta sessão.
+//!
+//! Métodos atendidos: `session-get`, `session-set`, `session-stats`,
+//! `torrent-get`, `torrent-add`, `torrent-start`, `torrent-start-now`,
+//! `torrent-stop`, `torrent-verify`, `torrent-remove` e `torrent-set` (só os
+//! limites de banda). Campos desconhecidos são ignorados, como no Transmission.
+//!
+//! A autenticação é a mesma da API: o token vai como senha no Basic auth,
+//! com qualquer usuário. Sem o cabeçalho `X-Transmission-Session-Id` correto,
+//! a resposta é 409 com o id, que o cliente repete nas requisições seguintes.
+
+use crate::http::{Request, Response};
+use crate::magnet::Magnet;
+use crate::metainfo::{InfoHash, Metainfo};
+use crate::ratelimit::{self, RateLimiter, RateTooLarge, UNLIMITED};
+use crate::session::{Session, SessionError, TorrentState, TorrentStatus};
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+
+pub const RPC_PATH: &str = "/transmission/rpc";
+
+pub const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+/// Versão da RPC do Transmission 4.0, cujos campos implementamos.
+const RPC_VERSION: u32 = 17;
+const RPC_VERSION_MINIMUM: u32 = 14;
+const RPC_VERSION_SEMVER: &str = "5.3.0";
+
+/// Os limites internos são em KiB/s; anunciamos a mesma unidade aos clientes.
+const SPEED_BYTES: u64 = 1024;
+
+/// Valores de `status` do Transmission.
+const STATUS_STOPPED: u8 = 0;
+const STATUS_CHECK: u8 = 2;
+const STATUS_DOWNLOAD: u8 = 4;
+const STATUS_SEED: u8 = 6;
+
+/// `error` do Transmission para erros locais (disco, arquivo ausente).
+const ERROR_LOCAL: u8 = 3;
+
+/// `eta` quando não há como estimar.
+const ETA_NOT_AVAILABLE: i64 = -1;
+
+pub struct Transmission {
+    session: Session,
+    download_dir: PathBuf,
+    session_id: String,
+    started: Instant,
+    /// Ids numéricos dos torrents, estáveis enquanto o processo roda.
+    ids: Mutex<Ids>,
+    /// Limites em KiB/s configurados enquanto desabilitados, pelo torrent
+    /// (`None` para os globais) e pelo nome do campo.
+    saved_limits: Mutex<HashMap<LimitKey, u64>>,
+}
+
+type LimitKey = (Option<InfoHash>, &'static str);
+
+struct Ids {
+    by_hash: HashMap<InfoHash, i64>,
+    next: i64,
+}
+
+/// Erro de um método, devolvido em `result` com status 200, como o Transmission faz.
+struct RpcError(String);
+
+impl From<RateTooLarge> for RpcError {
+    fn from(e: RateTooLarge) -> Self {
+        RpcError(e.to_string())
+    }
+}
+
+impl From<SessionError> for RpcError {
+    fn from(e: SessionError) -> Self {
+        RpcError(e.to_string())
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+impl Transmission {
+    pub fn new(session: Session, download_dir: PathBuf) -> Self {
+        Self {
+            session,
+            download_dir,
+            session_id: hex::encode(rand::random::<[u8; 24]>()),
+            started: Instant::now(),
+            ids: Mutex::new(Ids { by_hash: HashMap::new(), next: 1 }),
+            saved_limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Atende uma requisição em [`RPC_PATH`] já autenticada.
+    pub async fn handle(&self, request: &Request) -> Response {
+        if request.header(SESSION_ID_HEADER) != Some(self.session_id.as_str()) {
+            let body = format!("<h1>409: Conflict</h1><p>{}: {}</p>", SESSION_ID_HEADER, self.session_id);
+            return Response::new(409, "text/html; charset=utf-8", body).with_header(SESSION_ID_HEADER, &self.session_id);
+        }
+        if request.method != "POST" {
+            return Response::text(405, "use POST");
+        }
+        let Ok(call) = serde_json::from_slice::<Value>(&request.body) else {
+            return Response::text(400, "JSON inválido");
+        };
+        let method = call["method"].as_str().unwrap_or_default();
+        let arguments = call.get("arguments").cloned().unwrap_or_else(|| json!({}));
+        let (result, arguments) = match self.call(method, &arguments).await {
+            Ok(arguments) => ("success".to_string(), arguments),
+            Err(RpcError(message)) => (message, json!({})),
+        };
+        let mut reply = json!({ "result": result, "arguments": arguments });
+        if let Some(tag) = call.get("tag") {
+            reply["tag"] = tag.clone();
+        }
+        Response::new(200, "application/json", reply.to_string()).with_header(SESSION_ID_HEADER, &self.session_id)
+    }
+
+    async fn call(&self, method: &str, arguments: &Value) -> RpcResult {
+        match method {
+            "session-get" => Ok(self.session_get(arguments)),
+            "session-set" => {
+                let peer = self.session.peer();
+                self.set_limit(None, &peer.global_limits.upload, arguments, "speed-limit-up", "speed-limit-up-enabled")?;
+                self.set_limit(None, &peer.global_limits.download, arguments, "speed-limit-down", "speed-limit-down-enabled")?;
+                Ok(json!({}))
+            }
+            "session-stats" => Ok(self.session_stats().await),
+            "torrent-get" => self.torrent_get(arguments).await,
+            "torrent-add" => self.torrent_add(arguments).await,
+            "torrent-start" | "torrent-start-now" => {
+                for info_hash in self.resolve(arguments).await {
+                    self.session.resume(&info_hash).await?;
+                }
+                Ok(json!({}))
+            }
+            "torrent-stop" => {
+                for info_hash in self.resolve(arguments).await {
+                    self.session.pause(&info_hash).await?;
+                }
+                Ok(json!({}))
+            }
+            "torrent-verify" => {
+                for info_hash in self.resolve(arguments).await {
+                    let Some(torrent) = self.session.torrent(&info_hash).await else {
+                        continue;
+                    };
+                    // Como no Transmission, a verificação segue depois da resposta
+                    tokio::spawn(async move {
+                        if let Err(e) = torrent.recheck().await {
+                            warn!(parent: torrent.span(), error = %e, "Erro na reverificação");
+                        }
+                    });
+                }
+                Ok(json!({}))
+            }
+            "torrent-remove" => {
+                let delete_files = arguments["delete-local-data"].as_bool().unwrap_or(false);
+                for info_hash in self.resolve(arguments).await {
+                    self.session.remove(&info_hash, delete_files).await?;
+                    self.ids.lock().unwrap().by_hash.remove(&info_hash);
+                    self.saved_limits.lock().unwrap().retain(|(scope, _), _| *scope != Some(info_hash));
+                }
+                Ok(json!({}))
+            }
+            "torrent-set" => {
+                for info_hash in self.resolve(arguments).await {
+                    if let Some(torrent) = self.session.torrent(&info_hash).await {
+                        let scope = Some(info_hash);
+                        self.set_limit(scope, &torrent.limits.upload, arguments, "uploadLimit", "uploadLimited")?;
+                        self.set_limit(scope, &torrent.limits.download, arguments, "downloadLimit", "downloadLimited")?;
+                    }
+                }
+                Ok(json!({}))
+            }
+            _ => Err(RpcError(format!("método não suportado: {}", method))),
+        }
+    }
+
+    fn session_get(&self, arguments: &Value) -> Value {
+        let peer = self.session.peer();
+        let limits = &peer.global_limits;
+        let all = json!({
+            "version": format!("{} (bittorrent_client)", env!("CARGO_PKG_VERSION")),
+            "rpc-version": RPC_VERSION,
+            "rpc-version-minimum": RPC_VERSION_MINIMUM,
+            "rpc-version-semver": RPC_VERSION_SEMVER,
+            "session-id": self.session_id,
+            "download-dir": self.download_dir,
+            "peer-port": peer.port,
+            "dht-enabled": peer.dht.is_some(),
+            "lpd-enabled": peer.lsd.is_some(),
+            "pex-enabled": true,
+            "encryption": "tolerated",
+            "speed-limit-up": self.limit_kib(None, &limits.upload, "speed-limit-up"),
+            "speed-limit-up-enabled": limits.upload.rate() != UNLIMITED,
+            "speed-limit-down": self.limit_kib(None, &limits.download, "speed-limit-down"),
+            "speed-limit-down-enabled": limits.download.rate() != UNLIMITED,
+            "alt-speed-enabled": false,
+            "units": {
+                "speed-units": ["KiB/s", "MiB/s", "GiB/s", "TiB/s"],
+                "speed-bytes": SPEED_BYTES,
+                "size-units": ["KiB", "MiB", "GiB", "TiB"],
+                "size-bytes": 1024,
+                "memory-units": ["KiB", "MiB", "GiB", "TiB"],
+                "memory-bytes": 1024,
+            },
+        });
+        select_fields(all, arguments.get("fields"))
+    }
+
+    async fn session_stats(&self) -> Value {
+        let statuses = self.session.list().await;
+        let paused = statuses.iter().filter(|status| status.state == TorrentState::Paused).count();
+        let sum = |field: fn(&TorrentStatus) -> u64| statuses.iter().map(field).sum::<u64>();
+        let seconds_active = self.started.elapsed().as_secs();
+        json!({
+            "activeTorrentCount": statuses.len() - paused,
+            "pausedTorrentCount": paused,
+            "torrentCount": statuses.len(),
+            "uploadSpeed": sum(|status| status.upload_rate),
+            "downloadSpeed": sum(|status| status.download_rate),
+            "current-stats": {
+                "uploadedBytes": sum(|status| status.uploaded),
+                "downloadedBytes": sum(|status| status.downloaded),
+                "filesAdded": statuses.len(),
+                "sessionCount": 1,
+                "secondsActive": seconds_active,
+            },
+            "cumulative-stats": {
+                "uploadedBytes": sum(|status| status.total_uploaded),
+                "downloadedBytes": sum(|status| status.total_downloaded),
+                "filesAdded": statuses.len(),
+                "sessionCount": 1,
+                "secondsActive": seconds_active,
+            },
+        })
+    }
+
+    async fn torrent_get(&self, arguments: &Value) -> RpcResult {
+        let wanted = self.resolve(arguments).await;
+        let mut torrents = Vec::new();
+        for status in self.session.list().await.iter().filter(|status| wanted.contains(&status.info_hash)) {
+            torrents.push(select_fields(self.torrent_json(status).await, arguments.get("fields")));
+        }
+        Ok(json!({ "torrents": torrents }))
+    }
+
+    async fn torrent_json(&self, status: &TorrentStatus) -> Value {
+        let (status_code, error, error_string) = match &status.state {
+            TorrentState::Metadata | TorrentState::Downloading => (STATUS_DOWNLOAD, 0, String::new()),
+            TorrentState::Checking => (STATUS_CHECK, 0, String::new()),
+            TorrentState::Seeding => (STATUS_SEED, 0, String::new()),
+            TorrentState::Paused => (STATUS_STOPPED, 0, String::new()),
+            TorrentState::Error(e) => (STATUS_STOPPED, ERROR_LOCAL, e.clone()),
+        };
+        let (have, total) = status.pieces;
+        let percent_done = if total == 0 { 0.0 } else { have as f64 / total as f64 };
+        let eta = match status.state {
+            TorrentState::Downloading if status.download_rate > 0 => (status.left / status.download_rate) as i64,
+            _ => ETA_NOT_AVAILABLE,
+        };
+        let ratio = match status.total_downloaded {
+            0 => -1.0,
+            downloaded => status.total_uploaded as f64 / downloaded as f64,
+        };
+        // Sem contadores por direção, "enviando para nós" é quem já mandou algum dado
+        let (mut getting_from_us, mut sending_to_us) = (0, 0);
+        // Torrents ainda sem metainfo não têm limites próprios
+        let (mut upload_limit, mut download_limit) = ((0, false), (0, false));
+        if let Some(torrent) = self.session.torrent(&status.info_hash).await {
+            for peer in torrent.peers().await {
+                getting_from_us += usize::from(peer.unchoked && peer.interested);
+                sending_to_us += usize::from(peer.downloaded > 0);
+            }
+            let scope = Some(status.info_hash);
+            let limit = |limiter: &RateLimiter, field| (self.limit_kib(scope, limiter, field), limiter.rate() != UNLIMITED);
+            upload_limit = limit(&torrent.limits.upload, "uploadLimit");
+            download_limit = limit(&torrent.limits.download, "downloadLimit");
+        }
+        json!({
+            "id": self.id(&status.info_hash),
+            "hashString": status.info_hash.to_hex(),
+            "name": status.name,
+            "status": status_code,
+            "error": error,
+            "errorString": error_string,
+            "downloadDir": status.save_dir,
+            "totalSize": status.size,
+            "sizeWhenDone": status.size,
+            "leftUntilDone": status.left,
+            "haveValid": status.size.saturating_sub(status.left),
+            "percentDone": percent_done,
+            "metadataPercentComplete": if status.state == TorrentState::Metadata { 0.0 } else { 1.0 },
+            "isFinished": false,
+            "eta": eta,
+            "rateUpload": status.upload_rate,
+            "rateDownload": status.download_rate,
+            "uploadedEver": status.total_uploaded,
+            "downloadedEver": status.total_downloaded,
+            "uploadRatio": ratio,
+            "peersConnected": status.peers,
+            "peersGettingFromUs": getting_from_us,
+            "peersSendingToUs": sending_to_us,
+            "pieceCount": total,
+            "uploadLimit": upload_limit.0,
+            "uploadLimited": upload_limit.1,
+            "downloadLimit": download_limit.0,
+            "downloadLimited": download_limit.1,
+        })
+    }
+
+    async fn torrent_add(&self, arguments: &Value) -> RpcResult {
+        let save_dir = arguments["download-dir"].as_str().map(PathBuf::from).unwrap_or_else(|| self.download_dir.clone());
+        let added = if let Some(metainfo) = arguments["metainfo"].as_str() {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(metainfo.trim())
+                .map_err(|e| RpcError(format!("metainfo inválido: {}", e)))?;
+            let metainfo = Metainfo::from_bytes(&bytes).map_err(|e| RpcError(e.to_string()))?;
+            self.session.add(metainfo, save_dir).await
+        } else if let Some(filename) = arguments["filename"].as_str() {
+            if filename.starts_with("magnet:") {
+                let magnet = Magnet::parse(filename).map_err(|e| RpcError(e.to_string()))?;
+                self.session.add_magnet(magnet, save_dir).await
+            } else {
+                let metainfo = load_metainfo(filename).await?;
+                self.session.add(metainfo, save_dir).await
+            }
+        } else {
+            return Err(RpcError("torrent-add precisa de filename ou metainfo".to_string()));
+        };
+        let (key, info_hash) = match added {
+            Ok(info_hash) => ("torrent-added", info_hash),
+            Err(SessionError::AlreadyAdded(info_hash)) => ("torrent-duplicate", info_hash),
+            Err(e) => return Err(e.into()),
+        };
+        if key == "torrent-added" && arguments["paused"].as_bool() == Some(true) {
+            self.session.pause(&info_hash).await?;
+        }
+        let name = self
+            .session
+            .list()
+            .await
+            .into_iter()
+            .find(|status| status.info_hash == info_hash)
+            .map(|status| status.name)
+            .unwrap_or_default();
+        Ok(json!({ key: { "id": self.id(&info_hash), "name": name, "hashString": info_hash.to_hex() } }))
+    }
+
+    /// Torrents indicados por `ids`: um id, um hash, uma lista deles ou
+    /// `recently-active`; sem `ids`, todos.
+    async fn resolve(&self, arguments: &Value) -> Vec<InfoHash> {
+        let statuses = self.session.list().await;
+        for status in &statuses {
+            self.id(&status.info_hash);
+        }
+        let all = statuses.iter().map(|status| status.info_hash);
+        let ids = match arguments.get("ids") {
+            None | Some(Value::Null) => return all.collect(),
+            Some(Value::String(ids)) if ids == "recently-active" => return all.collect(),
+            Some(Value::Array(ids)) => ids.clone(),
+            Some(id) => vec![id.clone()],
+        };
+        let by_hash = &self.ids.lock().unwrap().by_hash;
+        ids.iter()
+            .filter_map(|id| match id {
+                Value::Number(id) => by_hash.iter().find(|(_, n)| Some(**n) == id.as_i64()).map(|(hash, _)| *hash),
+                Value::String(hash) => InfoHash::from_hex(hash).filter(|hash| by_hash.contains_key(hash)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn id(&self, info_hash: &InfoHash) -> i64 {
+        let mut ids = self.ids.lock().unwrap();
+        if let Some(id) = ids.by_hash.get(info_hash) {
+            return *id;
+        }
+        let id = ids.next;
+        ids.next += 1;
+        ids.by_hash.insert(*info_hash, id);
+        id
+    }
+
+    /// Aplica um par limite/habilitado do Transmission, em KiB/s. Como lá, o
+    /// valor e o flag são independentes: um valor dado com o limite
+    /// desabilitado fica guardado e passa a valer quando ele for habilitado.
+    fn set_limit(
+        &self,
+        scope: Option<InfoHash>,
+        limiter: &RateLimiter,
+        arguments: &Value,
+        limit: &'static str,
+        enabled: &str,
+    ) -> Result<(), RpcError> {
+        let kib = match arguments[limit].as_u64() {
+            Some(kib) => kib,
+            None => self.limit_kib(scope, limiter, limit),
+        };
+        let enabled = arguments[enabled].as_bool().unwrap_or(limiter.rate() != UNLIMITED);
+        let mut saved = self.saved_limits.lock().unwrap();
+        if enabled {
+            limiter.set_rate_kib(kib)?;
+            saved.remove(&(scope, limit));
+        } else {
+            ratelimit::kib_to_bytes(kib)?;
+            limiter.set_rate(UNLIMITED);
+            saved.insert((scope, limit), kib);
+        }
+        Ok(())
+    }
+
+    /// Valor configurado de um limite em KiB/s, esteja ele habilitado ou não.
+    fn limit_kib(&self, scope: Option<InfoHash>, limiter: &RateLimiter, limit: &'static str) -> u64 {
+        match limiter.rate() {
+            UNLIMITED => self.saved_limits.lock().unwrap().get(&(scope, limit)).copied().unwrap_or(0),
+            rate => rate / SPEED_BYTES,
+        }
+    }
+}
+
+/// `filename` do torrent-add: caminho local ou URL HTTP de um .torrent.
+async fn load_metainfo(filename: &str) -> Result<Metainfo, RpcError> {
+    if filename.starts_with("http://") || filename.starts_with("https://") {
+        let fetch = async { reqwest::get(filename).await?.error_for_status()?.bytes().await };
+        let bytes = fetch.await.map_err(|e| RpcError(format!("erro ao baixar {}: {}", filename, e)))?;
+        Metainfo::from_bytes(&bytes).map_err(|e| RpcError(e.to_string()))
+    } else {
+        Metainfo::load(filename.as_ref()).map_err(|e| RpcError(format!("erro ao abrir {}: {}", filename, e)))
+    }
+}
+
+
+/// Mantém só os campos pedidos em `fields`; sem a lista, todos.
+fn select_fields(value: Value, fields: Option<&Value>) -> Value {
+    match (value, fields) {
+        (Value::Object(mut object), Some(Value::Array(fields))) => {
+            let selected: Map<String, Value> = fields
+                .iter()
+                .filter_map(Value::as_str)
+                .filter_map(|field| object.remove(field).map(|value| (field.to_string(), value)))
+                .collect();
+            Value::Object(selected)
+        }
+        (value, _) => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitfield::Bitfield;
+    use crate::peer::Peer;
+    use crate::torrent::Torrent;
+    use std::sync::Arc;
+
+    struct Fixture {
+        rpc: Transmission,
+        dir: PathBuf,
+        hashes: Vec<InfoHash>,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Sessão com dois torrents completos, criados a partir de arquivos temporários.
+    async fn fixture() -> Fixture {
+        let dir = std::env::temp_dir().join(format!("bt-transmission-{}", hex::encode(rand::random::<[u8; 8]>())));
+        std::fs::create_dir_all(&dir).unwrap();
+        let session = Session::new(Peer::new("127.0.0.1".to_string(), 0, "teste".to_string()));
+        let mut hashes = Vec::new();
+        for name in ["um", "dois"] {
+            let path = dir.join(name);
+            std::fs::write(&path, name.repeat(1000)).unwrap();
+            let metainfo = Arc::new(Metainfo::create(&path, None, Vec::new()).unwrap());
+            let have = Bitfield::full(metainfo.info.piece_count());
+            let torrent = Arc::new(Torrent::new(metainfo, dir.clone(), have));
+            hashes.push(session.add_torrent(torrent).await.unwrap());
+        }
+        Fixture { rpc: Transmission::new(session, dir.clone()), dir, hashes }
+    }
+
+    fn request(session_id: Option<&str>, body: Value) -> Request {
+        let headers = session_id.map(|id| (SESSION_ID_HEADER.to_string(), id.to_string())).into_iter().collect();
+        Request { method: "POST".to_string(), path: RPC_PATH.to_string(), query: Vec::new(), headers, body: body.to_string().into_bytes() }
+    }
+
+    async fn call(rpc: &Transmission, body: Value) -> Value {
+        let response = rpc.handle(&request(Some(&rpc.session_id), body)).await;
+        assert_eq!(response.status, 200);
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    async fn torrent_ids(rpc: &Transmission, ids: Value) -> Vec<String> {
+        let reply = call(rpc, json!({ "method": "torrent-get", "arguments": { "ids": ids, "fields": ["hashString"] } })).await;
+        let mut hashes: Vec<String> = reply["arguments"]["torrents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|torrent| torrent["hashString"].as_str().unwrap().to_string())
+            .collect();
+        hashes.sort();
+        hashes
+    }
+
+    #[tokio::test]
+    async fn session_id_handshake_and_tag() {
+        let fixture = fixture().await;
+        let rpc = &fixture.rpc;
+        let body = json!({ "method": "session-get", "arguments": { "fields": ["rpc-version"] }, "tag": 7 });
+
+        let response = rpc.handle(&request(None, body.clone())).await;
+        assert_eq!(response.status, 409);
+        let id = response.headers.iter().find(|(name, _)| name == SESSION_ID_HEADER).map(|(_, id)| id.clone()).unwrap();
+        assert_eq!(rpc.handle(&request(Some("outro"), body.clone())).await.status, 409);
+
+        let response = rpc.handle(&request(Some(&id), body)).await;
+        assert_eq!(response.status, 200);
+        let reply: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(reply, json!({ "result": "success", "arguments": { "rpc-version": RPC_VERSION }, "tag": 7 }));
+
+        let reply = call(rpc, json!({ "method": "nada", "tag": "x" })).await;
+        assert_eq!(reply["result"], "método não suportado: nada");
+        assert_eq!(reply["tag"], "x");
+    }
+
+    #[tokio::test]
+    async fn torrent_get_selects_fields_and_resolves_ids() {
+        let fixture = fixture().await;
+        let rpc = &fixture.rpc;
+        let reply = call(rpc, json!({ "method": "torrent-get", "arguments": { "fields": ["id", "name", "percentDone"] } })).await;
+        let torrents = reply["arguments"]["torrents"].as_array().unwrap();
+        assert_eq!(torrents.len(), 2);
+        for torrent in torrents {
+            let keys: Vec<&String> = torrent.as_object().unwrap().keys().collect();
+            assert_eq!(keys, ["id", "name", "percentDone"]);
+            assert_eq!(torrent["percentDone"], 1.0);
+        }
+
+        let [first, second] = [0, 1].map(|i| fixture.hashes[i].to_hex());
+        let mut both = vec![first.clone(), second.clone()];
+        both.sort();
+        let first_id = rpc.id(&fixture.hashes[0]);
+        assert_eq!(torrent_ids(rpc, json!(first_id)).await, vec![first.clone()]);
+        assert_eq!(torrent_ids(rpc, json!([second.clone()])).await, vec![second.clone()]);
+        assert_eq!(torrent_ids(rpc, json!([first_id, second.to_uppercase()])).await, both);
+        assert_eq!(torrent_ids(rpc, json!("recently-active")).await, both);
+        assert_eq!(torrent_ids(rpc, Value::Null).await, both);
+        assert!(torrent_ids(rpc, json!([999, "00".repeat(20), "inválido"])).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn torrent_add_reports_duplicates() {
+        let fixture = fixture().await;
+        let rpc = &fixture.rpc;
+        let path = fixture.dir.join("um");
+        let metainfo = Metainfo::create(&path, None, Vec::new()).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(metainfo.to_bytes());
+
+        let reply = call(rpc, json!({ "method": "torrent-add", "arguments": { "metainfo": encoded } })).await;
+        assert_eq!(reply["result"], "success");
+        let duplicate = &reply["arguments"]["torrent-duplicate"];
+        assert_eq!(duplicate["hashString"], fixture.hashes[0].to_hex());
+        assert_eq!(duplicate["id"], rpc.id(&fixture.hashes[0]));
+        assert_eq!(duplicate["name"], "um");
+
+        let reply = call(rpc, json!({ "method": "torrent-add", "arguments": { "metainfo": "!!" } })).await;
+        assert_ne!(reply["result"], "success");
+    }
+
+    #[tokio::test]
+    async fn limit_value_and_flag_are_independent() {
+        let fixture = fixture().await;
+        let rpc = &fixture.rpc;
+        let limits = || rpc.session.peer().global_limits.upload.rate();
+        let session_set = |arguments: Value| call(rpc, json!({ "method": "session-set", "arguments": arguments }));
+        let session_get = || async {
+            let fields = json!(["speed-limit-up", "speed-limit-up-enabled"]);
+            call(rpc, json!({ "method": "session-get", "arguments": { "fields": fields } })).await["arguments"].clone()
+        };
+
+        // Valor com o limite desabilitado: guardado, mas sem efeito
+        session_set(json!({ "speed-limit-up": 50 })).await;
+        assert_eq!(limits(), UNLIMITED);
+        assert_eq!(session_get().await, json!({ "speed-limit-up": 50, "speed-limit-up-enabled": false }));
+
+        // Só o flag: passa a valer o valor guardado
+        session_set(json!({ "speed-limit-up-enabled": true })).await;
+        assert_eq!(limits(), 50 * 1024);
+        session_set(json!({ "speed-limit-up": 80 })).await;
+        assert_eq!(limits(), 80 * 1024);
+
+        session_set(json!({ "speed-limit-up-enabled": false })).await;
+        assert_eq!(limits(), UNLIMITED);
+        assert_eq!(session_get().await, json!({ "speed-limit-up": 80, "speed-limit-up-enabled": false }));
+
+        let reply = session_set(json!({ "speed-limit-up": u64::MAX, "speed-limit-up-enabled": true })).await;
+        assert_ne!(reply["result"], "success");
+        assert_eq!(limits(), UNLIMITED);
+
+        // Por torrent, com os campos do torrent-set
+        let id = rpc.id(&fixture.hashes[1]);
+        let torrent_set = |arguments: Value| call(rpc, json!({ "method": "torrent-set", "arguments": arguments }));
+        torrent_set(json!({ "ids": [id], "downloadLimit": 20 })).await;
+        torrent_set(json!({ "ids": [id], "downloadLimited": true })).await;
+        let torrent = rpc.session.torrent(&fixture.hashes[1]).await.unwrap();
+        assert_eq!(torrent.limits.download.rate(), 20 * 1024);
+        let reply = call(
+            rpc,
+            json!({ "method": "torrent-get", "arguments": { "ids": id, "fields": ["downloadLimit", "downloadLimited", "uploadLimited"] } }),
+        )
+        .await;
+        assert_eq!(
+            reply["arguments"]["torrents"][0],
+            json!({ "downloadLimit": 20, "downloadLimited": true, "uploadLimited": false })
+        );
+        let other = rpc.session.torrent(&fixture.hashes[0]).await.unwrap();
+        assert_eq!(other.limits.download.rate(), UNLIMITED);
+    }
+}
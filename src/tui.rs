@@ -0,0 +1,582 @@
+﻿//! Interface de terminal em tela cheia (subcomando `tui`): torrents com
+//! barras de progresso, peers do torrent selecionado com as taxas, log e chat,
+//! com comandos de uma tecla no lugar do laço de linhas do modo `peer`.
+//!
+//! No Unix, a saída padrão e a de erros do processo vão para o painel de log
+//! e a tela é desenhada direto em `/dev/tty`, para que as mensagens das
+//! conexões não atravessem a interface.
+
+use crate::{add_seed_path, shutdown_signal, TorrentSource};
+use bittorrent_client::chat;
+use bittorrent_client::config::Config;
+use bittorrent_client::metainfo::InfoHash;
+use bittorrent_client::session::{Session, TorrentState, TorrentStatus};
+use bittorrent_client::torrent::PeerInfo;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures_util::StreamExt;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Intervalo entre as atualizações das tabelas e das taxas.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Linhas guardadas nos painéis de log e de chat.
+const LOG_LINES: usize = 1000;
+const CHAT_LINES: usize = 200;
+
+const BAR_WIDTH: usize = 20;
+
+const HELP: &str =
+    "↑↓ seleciona  a adiciona  p pausa/retoma  v reverifica  x remove  c destino do chat  m mensagem  PgUp/PgDn log  q sai";
+
+type Backend = CrosstermBackend<Box<dyn Write + Send>>;
+
+/// Terminal em modo de tela cheia; volta ao normal ao ser descartado.
+pub struct Screen {
+    terminal: Terminal<Backend>,
+    log: mpsc::UnboundedReceiver<String>,
+    log_sender: mpsc::UnboundedSender<String>,
+    #[cfg(unix)]
+    _output: capture::Captured,
+}
+
+impl Screen {
+    /// Entra em tela cheia e, no Unix, passa a mostrar a saída do processo no painel de log.
+    pub fn open() -> io::Result<Self> {
+        let (log_sender, log) = mpsc::unbounded_channel();
+        #[cfg(unix)]
+        let (output, writer): (_, Box<dyn Write + Send>) = {
+            let tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+            (capture::start(log_sender.clone())?, Box::new(tty))
+        };
+        #[cfg(not(unix))]
+        let writer: Box<dyn Write + Send> = Box::new(io::stdout());
+
+        let mut terminal = Terminal::new(CrosstermBackend::new(writer))?;
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+        terminal.clear()?;
+        Ok(Self {
+            terminal,
+            log,
+            log_sender,
+            #[cfg(unix)]
+            _output: output,
+        })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = crossterm::execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// O que a linha de entrada está pedindo.
+enum Prompt {
+    Add,
+    ChatPort,
+    ChatMessage,
+    /// Confirmação de remoção, respondida com uma tecla.
+    Remove(InfoHash, String),
+}
+
+struct Input {
+    prompt: Prompt,
+    text: String,
+}
+
+struct App {
+    session: Session,
+    config: Config,
+    torrents: Vec<TorrentStatus>,
+    peers: Vec<PeerInfo>,
+    table: TableState,
+    log: VecDeque<String>,
+    log_sender: mpsc::UnboundedSender<String>,
+    /// Linhas roladas para trás a partir do fim do log.
+    log_scroll: usize,
+    chat: VecDeque<String>,
+    chat_port: Option<u16>,
+    input: Option<Input>,
+    quit: bool,
+}
+
+/// Roda a interface até `q`, Ctrl+C ou SIGTERM.
+pub async fn run(
+    mut screen: Screen,
+    session: Session,
+    config: Config,
+    mut chat_messages: mpsc::Receiver<(String, String)>,
+) -> io::Result<()> {
+    let mut app = App {
+        session,
+        config,
+        torrents: Vec::new(),
+        peers: Vec::new(),
+        table: TableState::default().with_selected(Some(0)),
+        log: VecDeque::new(),
+        log_sender: screen.log_sender.clone(),
+        log_scroll: 0,
+        chat: VecDeque::new(),
+        chat_port: None,
+        input: None,
+        quit: false,
+    };
+    let mut events = EventStream::new();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+
+    while !app.quit {
+        screen.terminal.draw(|frame| app.draw(frame))?;
+        tokio::select! {
+            _ = refresh.tick() => app.refresh().await,
+            Some(line) = screen.log.recv() => push_line(&mut app.log, line, LOG_LINES),
+            Some((_, message)) = chat_messages.recv() => push_line(&mut app.chat, format!("📩 {}", message), CHAT_LINES),
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => app.key(key).await,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+            _ = &mut signal => break,
+        }
+    }
+    Ok(())
+}
+
+impl App {
+    async fn refresh(&mut self) {
+        self.torrents = self.session.list().await;
+        if !self.torrents.is_empty() {
+            let selected = self.table.selected().unwrap_or(0).min(self.torrents.len() - 1);
+            self.table.select(Some(selected));
+        }
+
+        self.peers = match self.selected() {
+            Some(status) => match self.session.torrent(&status.info_hash).await {
+                Some(torrent) => torrent.peers().await,
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+    }
+
+    fn selected(&self) -> Option<&TorrentStatus> {
+        self.table.selected().and_then(|index| self.torrents.get(index))
+    }
+
+    fn log(&mut self, line: String) {
+        push_line(&mut self.log, line, LOG_LINES);
+    }
+
+    async fn key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        if let Some(input) = self.input.take() {
+            self.edit(input, key).await;
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1).await,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1).await,
+            KeyCode::PageUp => self.log_scroll = (self.log_scroll + 10).min(self.log.len()),
+            KeyCode::PageDown => self.log_scroll = self.log_scroll.saturating_sub(10),
+            KeyCode::End => self.log_scroll = 0,
+            KeyCode::Char('a') => self.prompt(Prompt::Add),
+            KeyCode::Char('c') => self.prompt(Prompt::ChatPort),
+            KeyCode::Char('m') if self.chat_port.is_some() => self.prompt(Prompt::ChatMessage),
+            KeyCode::Char('m') => self.prompt(Prompt::ChatPort),
+            KeyCode::Char('p') => self.toggle_pause().await,
+            KeyCode::Char('v') => self.recheck().await,
+            KeyCode::Char('x') => {
+                if let Some(status) = self.selected() {
+                    let prompt = Prompt::Remove(status.info_hash, status.name.clone());
+                    self.prompt(prompt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prompt(&mut self, prompt: Prompt) {
+        self.input = Some(Input { prompt, text: String::new() });
+    }
+
+    async fn move_selection(&mut self, delta: isize) {
+        if self.torrents.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, self.torrents.len() as isize - 1);
+        self.table.select(Some(next as usize));
+        self.refresh().await;
+    }
+
+    /// Tecla com a linha de entrada aberta; a entrada volta a `self.input` se continuar aberta.
+    async fn edit(&mut self, mut input: Input, key: KeyEvent) {
+        if let Prompt::Remove(info_hash, name) = &input.prompt {
+            let delete_files = match key.code {
+                KeyCode::Char('s') => false,
+                KeyCode::Char('a') => true,
+                _ => return,
+            };
+            match self.session.remove(info_hash, delete_files).await {
+                Ok(()) => self.log(format!("{} removido", name)),
+                Err(e) => self.log(format!("Erro ao remover {}: {}", name, e)),
+            }
+            self.refresh().await;
+            return;
+        }
+        match key.code {
+            KeyCode::Esc => return,
+            KeyCode::Enter => {
+                self.submit(input).await;
+                return;
+            }
+            KeyCode::Backspace => {
+                input.text.pop();
+            }
+            KeyCode::Char(c) => input.text.push(c),
+            _ => {}
+        }
+        self.input = Some(input);
+    }
+
+    async fn submit(&mut self, input: Input) {
+        let text = input.text.trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+        match input.prompt {
+            Prompt::Add => self.add(text),
+            Prompt::ChatPort => match text.parse() {
+                Ok(port) => {
+                    self.chat_port = Some(port);
+                    self.prompt(Prompt::ChatMessage);
+                }
+                Err(_) => self.log(format!("Porta inválida: {}", text)),
+            },
+            Prompt::ChatMessage => {
+                let Some(port) = self.chat_port else {
+                    return;
+                };
+                let message = format!("{}: {}", self.session.peer().name, text);
+                match chat::send_message(port, message).await {
+                    Ok(()) => push_line(&mut self.chat, format!("você → {}: {}", port, text), CHAT_LINES),
+                    Err(e) => push_line(&mut self.chat, format!("erro ao enviar para a porta {}: {}", port, e), CHAT_LINES),
+                }
+            }
+            Prompt::Remove(..) => {}
+        }
+    }
+
+    /// Link magnet, .torrent ou arquivo local a semear. Calcular os hashes
+    /// pode demorar, então a adição segue em segundo plano.
+    fn add(&mut self, source: String) {
+        let session = self.session.clone();
+        let config = self.config.clone();
+        let log = self.log_sender.clone();
+        tokio::spawn(async move {
+            let is_local_file = !source.starts_with("magnet:") && !source.ends_with(".torrent") && Path::new(&source).is_file();
+            let result = if is_local_file {
+                add_seed_path(&session, &config, source.clone().into()).await
+            } else {
+                match TorrentSource::parse(&source) {
+                    Ok(torrent) => torrent.add_to(&session, config.paths.download_dir.clone()).await.map(|_| ()),
+                    Err(e) => Err(e.into()),
+                }
+            };
+            let line = match result {
+                Ok(()) => format!("Adicionado: {}", source),
+                Err(e) => format!("Erro ao adicionar {}: {}", source, e),
+            };
+            let _ = log.send(line);
+        });
+    }
+
+    async fn toggle_pause(&mut self) {
+        let Some(status) = self.selected() else {
+            return;
+        };
+        let (info_hash, name) = (status.info_hash, status.name.clone());
+        let result = match status.state {
+            TorrentState::Paused | TorrentState::Error(_) => self.session.resume(&info_hash).await,
+            _ => self.session.pause(&info_hash).await,
+        };
+        if let Err(e) = result {
+            self.log(format!("Erro em {}: {}", name, e));
+        }
+        self.refresh().await;
+    }
+
+    async fn recheck(&mut self) {
+        let Some(status) = self.selected() else {
+            return;
+        };
+        let Some(torrent) = self.session.torrent(&status.info_hash).await else {
+            self.log(format!("{} ainda não foi aberto", status.name));
+            return;
+        };
+        let log = self.log_sender.clone();
+        tokio::spawn(async move {
+            let name = torrent.metainfo.info.name.clone();
+            let _ = log.send(format!("Reverificando {}...", name));
+            let line = match torrent.recheck().await {
+                Ok(result) => format!(
+                    "{}: {} de {} peças válidas, {} inválidas, {} encontradas no disco",
+                    name,
+                    result.valid,
+                    result.checked,
+                    result.bad.len(),
+                    result.recovered.len()
+                ),
+                Err(e) => format!("Erro na reverificação de {}: {}", name, e),
+            };
+            let _ = log.send(line);
+        });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [torrents, middle, log, footer] = Layout::vertical([
+            Constraint::Percentage(35),
+            Constraint::Percentage(35),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [peers, chat] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(middle);
+
+        self.draw_torrents(frame, torrents);
+        self.draw_peers(frame, peers);
+        self.draw_chat(frame, chat);
+        self.draw_log(frame, log);
+        self.draw_footer(frame, footer);
+    }
+
+    fn draw_torrents(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.torrents.iter().map(|status| {
+            let (have, total) = status.pieces;
+            let progress = if total == 0 { 0.0 } else { have as f64 / total as f64 };
+            let (down, up) = format_rates(status.download_rate, status.upload_rate);
+            let state = match &status.state {
+                TorrentState::Error(_) => Line::styled(status.state.to_string(), Style::new().fg(Color::Red)),
+                state => Line::from(state.to_string()),
+            };
+            Row::new(vec![
+                Line::from(status.name.clone()),
+                state,
+                Line::from(progress_bar(progress)),
+                Line::from(if status.size == 0 { "–".to_string() } else { format_bytes(status.size) }),
+                Line::from(down),
+                Line::from(up),
+                Line::from(status.peers.to_string()),
+            ])
+        });
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(20),
+            Constraint::Length(BAR_WIDTH as u16 + 7),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(5),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header(["Nome", "Estado", "Progresso", "Tamanho", "↓", "↑", "Peers"]))
+            .block(Block::bordered().title(format!(" Torrents ({}) ", self.torrents.len())))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_peers(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.peers.iter().map(|peer| {
+            let (down, up) = format_rates(peer.download_rate, peer.upload_rate);
+            let flags = format!(
+                "{}{}",
+                if peer.interested { "I" } else { "-" },
+                if peer.unchoked { "U" } else { "-" }
+            );
+            Row::new(vec![
+                peer.addr.to_string(),
+                String::from_utf8_lossy(&peer.peer_id[..8]).to_string(),
+                down,
+                up,
+                format_bytes(peer.downloaded),
+                format_bytes(peer.uploaded),
+                flags,
+            ])
+        });
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(9),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(4),
+        ];
+        let title = match self.selected() {
+            Some(status) => format!(" Peers de {} ", status.name),
+            None => " Peers ".to_string(),
+        };
+        let table = Table::new(rows, widths)
+            .header(header(["Endereço", "Cliente", "↓", "↑", "Recebido", "Enviado", "I/U"]))
+            .block(Block::bordered().title(title));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_chat(&self, frame: &mut Frame, area: Rect) {
+        let title = match self.chat_port {
+            Some(port) => format!(" Chat → porta {} ", port),
+            None => " Chat (c escolhe o destino) ".to_string(),
+        };
+        frame.render_widget(tail(&self.chat, area, 0).block(Block::bordered().title(title)), area);
+    }
+
+    fn draw_log(&self, frame: &mut Frame, area: Rect) {
+        let title = if self.log_scroll == 0 { " Log ".to_string() } else { format!(" Log (-{} linhas, End volta ao fim) ", self.log_scroll) };
+        frame.render_widget(tail(&self.log, area, self.log_scroll).block(Block::bordered().title(title)), area);
+    }
+
+    fn draw_footer(&self, frame: &mut Frame, area: Rect) {
+        let line = match &self.input {
+            Some(Input { prompt: Prompt::Remove(_, name), .. }) => {
+                format!("Remover {}? s = sim, a = sim e apagar os arquivos, outra tecla cancela", name)
+            }
+            Some(input) => {
+                let label = match input.prompt {
+                    Prompt::Add => "Magnet, .torrent ou arquivo a semear",
+                    Prompt::ChatPort => "Porta de chat do destino",
+                    Prompt::ChatMessage => "Mensagem",
+                    Prompt::Remove(..) => unreachable!(),
+                };
+                format!("{}: {}█  (Enter confirma, Esc cancela)", label, input.text)
+            }
+            None => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(line).style(Style::new().add_modifier(Modifier::REVERSED)), area);
+    }
+}
+
+/// Taxas formatadas de recebimento e envio.
+fn format_rates(download_rate: u64, upload_rate: u64) -> (String, String) {
+    (format!("{}/s", format_bytes(download_rate)), format!("{}/s", format_bytes(upload_rate)))
+}
+
+fn push_line(lines: &mut VecDeque<String>, line: String, max: usize) {
+    if lines.len() == max {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+/// As últimas linhas que cabem na área, `scroll` linhas antes do fim.
+fn tail(lines: &VecDeque<String>, area: Rect, scroll: usize) -> Paragraph<'static> {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+    Paragraph::new(lines.range(start..end).map(|line| Line::from(line.clone())).collect::<Vec<_>>())
+}
+
+fn header<const N: usize>(titles: [&'static str; N]) -> Row<'static> {
+    Row::new(titles).style(Style::new().add_modifier(Modifier::BOLD))
+}
+
+fn progress_bar(progress: f64) -> String {
+    let filled = ((progress * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    format!("{}{} {:5.1}%", "█".repeat(filled), "░".repeat(BAR_WIDTH - filled), progress * 100.0)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Desvio da saída padrão e da de erros para o painel de log.
+#[cfg(unix)]
+mod capture {
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use tokio::sync::mpsc;
+
+    /// Descritores originais, restaurados ao descartar.
+    pub struct Captured {
+        saved: [RawFd; 2],
+    }
+
+    pub fn start(log: mpsc::UnboundedSender<String>) -> io::Result<Captured> {
+        io::stdout().flush()?;
+        let mut fds = [0; 2];
+        // SAFETY: pipe, dup e dup2 só recebem descritores válidos; o lado de
+        // escrita fica só em 1 e 2, então a leitura termina quando eles são restaurados
+        let saved = unsafe {
+            if libc::pipe(fds.as_mut_ptr()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = [libc::dup(libc::STDOUT_FILENO), libc::dup(libc::STDERR_FILENO)];
+            if saved.contains(&-1)
+                || libc::dup2(fds[1], libc::STDOUT_FILENO) < 0
+                || libc::dup2(fds[1], libc::STDERR_FILENO) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            libc::close(fds[1]);
+            saved
+        };
+        let reader = BufReader::new(unsafe { File::from_raw_fd(fds[0]) });
+        std::thread::spawn(move || {
+            // Lê sempre, mesmo linhas que não são UTF-8: parar deixaria quem escreve bloqueado
+            // quando o pipe enchesse
+            for line in reader.split(b'\n') {
+                let Ok(line) = line else {
+                    break;
+                };
+                if log.send(String::from_utf8_lossy(&line).to_string()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Captured { saved })
+    }
+
+    impl Drop for Captured {
+        fn drop(&mut self) {
+            let _ = io::stdout().flush();
+            // SAFETY: os descritores salvos continuam abertos até aqui
+            unsafe {
+                libc::dup2(self.saved[0], libc::STDOUT_FILENO);
+                libc::dup2(self.saved[1], libc::STDERR_FILENO);
+                libc::close(self.saved[0]);
+                libc::close(self.saved[1]);
+            }
+        }
+    }
+}
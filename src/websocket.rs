@@ -0,0 +1,171 @@
+﻿//! WebSocket (RFC 6455) do lado do servidor, só o necessário para a interface
+//! web: handshake, mensagens de texto e binárias, ping/pong e close. Não há
+//! extensões, e as mensagens enviadas vão sempre num único frame.
+
+use crate::codec::{frame_too_large, Decoder, Encoder};
+use crate::http::Request;
+use base64::Engine;
+use bytes::{Buf, BufMut, BytesMut};
+use sha1::{Digest, Sha1};
+use std::io;
+
+/// Concatenado à chave do cliente para calcular `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Tamanho máximo de uma mensagem recebida, somando os fragmentos.
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// A requisição pede a troca para WebSocket.
+pub fn is_upgrade(request: &Request) -> bool {
+    request.method == "GET"
+        && request.header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+        && request.header("Sec-WebSocket-Key").is_some()
+}
+
+/// Resposta `101 Switching Protocols` a uma requisição de [`is_upgrade`].
+///
+/// Vai escrita direto no socket: o codec HTTP sempre fecha a conexão depois da resposta.
+pub fn handshake_response(request: &Request) -> Option<String> {
+    let key = request.header("Sec-WebSocket-Key")?;
+    let accept = base64::engine::general_purpose::STANDARD.encode(Sha1::digest(format!("{}{}", key.trim(), ACCEPT_GUID)));
+    Some(format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    ))
+}
+
+/// Codec de frames: decodifica o que o navegador envia (sempre mascarado) e
+/// codifica as respostas do servidor (nunca mascaradas).
+#[derive(Debug, Default)]
+pub struct WebSocketCodec {
+    /// Opcode e conteúdo de uma mensagem fragmentada ainda incompleta.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl Decoder for WebSocketCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Message>> {
+        loop {
+            let Some((fin, opcode, payload)) = decode_frame(src)? else {
+                return Ok(None);
+            };
+            match opcode {
+                OPCODE_PING => return Ok(Some(Message::Ping(payload))),
+                OPCODE_PONG => return Ok(Some(Message::Pong(payload))),
+                OPCODE_CLOSE => return Ok(Some(Message::Close)),
+                OPCODE_TEXT | OPCODE_BINARY if self.partial.is_none() => {
+                    if fin {
+                        return message(opcode, payload).map(Some);
+                    }
+                    self.partial = Some((opcode, payload));
+                }
+                OPCODE_CONTINUATION if self.partial.is_some() => {
+                    let (first, mut data) = self.partial.take().unwrap();
+                    data.extend_from_slice(&payload);
+                    if data.len() > MAX_MESSAGE_LEN {
+                        return Err(frame_too_large(data.len(), MAX_MESSAGE_LEN));
+                    }
+                    if fin {
+                        return message(first, data).map(Some);
+                    }
+                    self.partial = Some((first, data));
+                }
+                _ => return Err(invalid("frame WebSocket fora de ordem ou com opcode desconhecido")),
+            }
+        }
+    }
+}
+
+impl Encoder<Message> for WebSocketCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> io::Result<()> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OPCODE_TEXT, text.into_bytes()),
+            Message::Binary(data) => (OPCODE_BINARY, data),
+            Message::Ping(data) => (OPCODE_PING, data),
+            Message::Pong(data) => (OPCODE_PONG, data),
+            Message::Close => (OPCODE_CLOSE, Vec::new()),
+        };
+        dst.reserve(10 + payload.len());
+        dst.put_u8(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => dst.put_u8(len as u8),
+            len @ 126..=0xFFFF => {
+                dst.put_u8(126);
+                dst.put_u16(len as u16);
+            }
+            len => {
+                dst.put_u8(127);
+                dst.put_u64(len as u64);
+            }
+        }
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+/// Um frame completo do buffer: FIN, opcode e o conteúdo já desmascarado.
+fn decode_frame(src: &mut BytesMut) -> io::Result<Option<(bool, u8, Vec<u8>)>> {
+    if src.len() < 2 {
+        return Ok(None);
+    }
+    let fin = src[0] & 0x80 != 0;
+    let opcode = src[0] & 0x0F;
+    if src[1] & 0x80 == 0 {
+        return Err(invalid("frame do cliente sem máscara"));
+    }
+    let (len, header_len) = match src[1] & 0x7F {
+        126 if src.len() >= 4 => (u16::from_be_bytes([src[2], src[3]]) as usize, 4),
+        127 if src.len() >= 10 => (u64::from_be_bytes(src[2..10].try_into().unwrap()) as usize, 10),
+        126 | 127 => return Ok(None),
+        len => (len as usize, 2),
+    };
+    if len > MAX_MESSAGE_LEN {
+        return Err(frame_too_large(len, MAX_MESSAGE_LEN));
+    }
+    let total = header_len + 4 + len;
+    if src.len() < total {
+        src.reserve(total - src.len());
+        return Ok(None);
+    }
+    src.advance(header_len);
+    let mask: [u8; 4] = src[..4].try_into().unwrap();
+    src.advance(4);
+    let mut payload = src.split_to(len).to_vec();
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Some((fin, opcode, payload)))
+}
+
+fn message(opcode: u8, payload: Vec<u8>) -> io::Result<Message> {
+    match opcode {
+        OPCODE_TEXT => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| invalid("mensagem WebSocket de texto não é UTF-8")),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,252 @@
+﻿//! Protocolo de comunicação entre peers (BEP 3).
+
+use crate::codec::{Decoder, Encoder, LengthPrefixed};
+use crate::metainfo::InfoHash;
+use bytes::BytesMut;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+
+/// Tamanho do handshake: 1 + 19 + 8 + 20 + 20.
+pub const HANDSHAKE_LEN: usize = 68;
+
+/// Tamanho padrão de um bloco pedido com `request`.
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+/// Maior bloco que aceitamos servir num único `request`.
+pub const MAX_REQUEST_LEN: u32 = 128 * 1024;
+
+/// Limite para o tamanho de uma mensagem, evitando alocações absurdas.
+pub const MAX_MESSAGE_LEN: usize = 2 * 1024 * 1024;
+
+pub type PeerId = [u8; 20];
+
+/// Bit dos bytes reservados que indica suporte ao protocolo de extensões (BEP 10).
+const EXTENSION_BIT: (usize, u8) = (5, 0x10);
+
+/// Gera um peer id no estilo Azureus: `-GB0100-` seguido de 12 caracteres aleatórios.
+pub fn generate_peer_id() -> PeerId {
+    const CHARSET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut peer_id = [0u8; 20];
+    peer_id[..8].copy_from_slice(b"-GB0100-");
+    for byte in &mut peer_id[8..] {
+        *byte = CHARSET[rand::random::<usize>() % CHARSET.len()];
+    }
+    peer_id
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub reserved: [u8; 8],
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+}
+
+impl Handshake {
+    /// Handshake anunciando suporte ao protocolo de extensões.
+    pub fn new(info_hash: InfoHash, peer_id: PeerId) -> Self {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_BIT.0] |= EXTENSION_BIT.1;
+        Self { reserved, info_hash, peer_id }
+    }
+
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_BIT.0] & EXTENSION_BIT.1 != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut out = [0u8; HANDSHAKE_LEN];
+        out[0] = PROTOCOL.len() as u8;
+        out[1..20].copy_from_slice(PROTOCOL);
+        out[20..28].copy_from_slice(&self.reserved);
+        out[28..48].copy_from_slice(self.info_hash.as_bytes());
+        out[48..68].copy_from_slice(&self.peer_id);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; HANDSHAKE_LEN]) -> io::Result<Self> {
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            return Err(invalid("protocolo desconhecido no handshake"));
+        }
+        let mut reserved = [0u8; 8];
+        reserved.copy_from_slice(&bytes[20..28]);
+        let mut info_hash = [0u8; 20];
+        info_hash.copy_from_slice(&bytes[28..48]);
+        let mut peer_id = [0u8; 20];
+        peer_id.copy_from_slice(&bytes[48..68]);
+        Ok(Self { reserved, info_hash: InfoHash(info_hash), peer_id })
+    }
+}
+
+/// O handshake tem tamanho fixo e é lido com `read_exact` antes de a conexão
+/// passar a usar o [`MessageCodec`], sem ler nenhum byte a mais.
+pub async fn read_handshake<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Handshake> {
+    let mut buffer = [0u8; HANDSHAKE_LEN];
+    reader.read_exact(&mut buffer).await?;
+    Handshake::from_bytes(&buffer)
+}
+
+pub async fn write_handshake<W: AsyncWrite + Unpin>(writer: &mut W, handshake: &Handshake) -> io::Result<()> {
+    writer.write_all(&handshake.to_bytes()).await
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request { index: u32, begin: u32, length: u32 },
+    Piece { index: u32, begin: u32, block: Vec<u8> },
+    Cancel { index: u32, begin: u32, length: u32 },
+    /// Mensagem do protocolo de extensões (BEP 10); `id` 0 é o handshake estendido.
+    Extended { id: u8, payload: Vec<u8> },
+    /// Mensagem de extensão que não implementamos; é ignorada.
+    Unknown { id: u8, payload: Vec<u8> },
+}
+
+impl Message {
+    /// Codifica a mensagem com o prefixo de tamanho de 4 bytes.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(&[0; 4]);
+        match self {
+            Message::KeepAlive => {}
+            Message::Choke => out.push(0),
+            Message::Unchoke => out.push(1),
+            Message::Interested => out.push(2),
+            Message::NotInterested => out.push(3),
+            Message::Have(index) => {
+                out.push(4);
+                out.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::Bitfield(bits) => {
+                out.push(5);
+                out.extend_from_slice(bits);
+            }
+            Message::Request { index, begin, length } => {
+                out.push(6);
+                out.extend_from_slice(&index.to_be_bytes());
+                out.extend_from_slice(&begin.to_be_bytes());
+                out.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Piece { index, begin, block } => {
+                out.push(7);
+                out.extend_from_slice(&index.to_be_bytes());
+                out.extend_from_slice(&begin.to_be_bytes());
+                out.extend_from_slice(block);
+            }
+            Message::Cancel { index, begin, length } => {
+                out.push(8);
+                out.extend_from_slice(&index.to_be_bytes());
+                out.extend_from_slice(&begin.to_be_bytes());
+                out.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Extended { id, payload } => {
+                out.push(20);
+                out.push(*id);
+                out.extend_from_slice(payload);
+            }
+            Message::Unknown { id, payload } => {
+                out.push(*id);
+                out.extend_from_slice(payload);
+            }
+        }
+        let len = (out.len() - start - 4) as u32;
+        out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    /// Interpreta o corpo de uma mensagem (sem o prefixo de tamanho).
+    pub fn decode(payload: &[u8]) -> io::Result<Self> {
+        let Some((&id, body)) = payload.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+        let message = match id {
+            0..=3 if !body.is_empty() => return Err(invalid("mensagem sem corpo veio com dados")),
+            0 => Message::Choke,
+            1 => Message::Unchoke,
+            2 => Message::Interested,
+            3 => Message::NotInterested,
+            4 if body.len() != 4 => return Err(invalid("have com tamanho inválido")),
+            4 => Message::Have(be_u32(body, 0)),
+            5 => Message::Bitfield(body.to_vec()),
+            6 | 8 => {
+                if body.len() != 12 {
+                    return Err(invalid("request/cancel com tamanho inválido"));
+                }
+                let index = be_u32(body, 0);
+                let begin = be_u32(body, 4);
+                let length = be_u32(body, 8);
+                if id == 6 {
+                    Message::Request { index, begin, length }
+                } else {
+                    Message::Cancel { index, begin, length }
+                }
+            }
+            7 => {
+                if body.len() < 8 {
+                    return Err(invalid("piece com tamanho inválido"));
+                }
+                Message::Piece {
+                    index: be_u32(body, 0),
+                    begin: be_u32(body, 4),
+                    block: body[8..].to_vec(),
+                }
+            }
+            20 => {
+                let (&id, payload) = body.split_first().ok_or_else(|| invalid("mensagem estendida sem id"))?;
+                Message::Extended { id, payload: payload.to_vec() }
+            }
+            id => Message::Unknown { id, payload: body.to_vec() },
+        };
+        Ok(message)
+    }
+}
+
+/// Lê um inteiro big-endian; o chamador já validou o tamanho do corpo.
+fn be_u32(body: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(body[offset..offset + 4].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Codec das mensagens após o handshake: frames com prefixo de tamanho,
+/// limitados a [`MAX_MESSAGE_LEN`].
+#[derive(Debug, Clone, Copy)]
+pub struct MessageCodec {
+    frames: LengthPrefixed,
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self { frames: LengthPrefixed::new(MAX_MESSAGE_LEN) }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Message>> {
+        match self.frames.decode(src)? {
+            Some(payload) => Message::decode(&payload).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        message.encode(&mut buffer);
+        self.frames.encode(&buffer[4..], dst)
+    }
+}